@@ -17,15 +17,6 @@ else
   done
 fi
 
-echo "Applying migrations..."
-for f in /app/migrations/*_create_*.sql; do
-  # only apply up migrations (files with .down.sql are rollback files)
-  if [[ "$f" != *.down.sql ]]; then
-    echo "Applying $f"
-    # Use psql with connection string; forward stdout/stderr so failures are visible
-    psql "$DB_URL" -f "$f"
-  fi
-done
-
+# The app applies pending migrations itself on startup
 echo "Starting app..."
 exec /app/transaction-service
@@ -1,7 +1,7 @@
 -- migrate:up
 CREATE EXTENSION IF NOT EXISTS "pgcrypto";
 
-CREATE TABLE accounts (
+CREATE TABLE IF NOT EXISTS accounts (
     id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
     business_name TEXT NOT NULL,
     balance NUMERIC(12,2) NOT NULL DEFAULT 0.00,
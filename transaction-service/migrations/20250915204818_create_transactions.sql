@@ -1,5 +1,5 @@
 -- migrate:up
-CREATE TABLE transactions (
+CREATE TABLE IF NOT EXISTS transactions (
     id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
     from_account UUID REFERENCES accounts(id) ON DELETE CASCADE,
     to_account UUID REFERENCES accounts(id) ON DELETE CASCADE,
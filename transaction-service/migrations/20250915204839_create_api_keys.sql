@@ -1,5 +1,5 @@
 -- migrate:up
-CREATE TABLE api_keys (
+CREATE TABLE IF NOT EXISTS api_keys (
     id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
     account_id UUID REFERENCES accounts(id) ON DELETE CASCADE,
     -- A short fingerprint to quickly find candidate keys (e.g. first 8 hex chars of SHA256)
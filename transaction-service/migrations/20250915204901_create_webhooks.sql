@@ -1,5 +1,5 @@
 -- migrate:up
-CREATE TABLE webhooks (
+CREATE TABLE IF NOT EXISTS webhooks (
     id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
     account_id UUID REFERENCES accounts(id) ON DELETE CASCADE,
     url TEXT NOT NULL,
@@ -7,7 +7,7 @@ CREATE TABLE webhooks (
     created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
 );
 
-CREATE TABLE webhook_events (
+CREATE TABLE IF NOT EXISTS webhook_events (
     id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
     webhook_id UUID REFERENCES webhooks(id) ON DELETE CASCADE,
     txn_id UUID REFERENCES transactions(id) ON DELETE CASCADE,
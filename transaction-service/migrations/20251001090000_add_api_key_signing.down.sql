@@ -0,0 +1,3 @@
+-- migrate:down
+DROP TABLE IF EXISTS auth_nonces CASCADE;
+ALTER TABLE api_keys DROP COLUMN IF EXISTS signing_secret;
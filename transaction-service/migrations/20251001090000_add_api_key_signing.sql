@@ -0,0 +1,13 @@
+-- migrate:up
+-- Per-key secret used to verify `Authorization: TS-HMAC ...` request signatures
+ALTER TABLE api_keys ADD COLUMN IF NOT EXISTS signing_secret TEXT;
+
+-- Nonces already seen for signed requests; rows older than the allowed clock skew can be pruned
+CREATE TABLE IF NOT EXISTS auth_nonces (
+    api_key_id UUID NOT NULL REFERENCES api_keys(id) ON DELETE CASCADE,
+    nonce TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (api_key_id, nonce)
+);
+
+CREATE INDEX IF NOT EXISTS idx_auth_nonces_created_at ON auth_nonces(created_at);
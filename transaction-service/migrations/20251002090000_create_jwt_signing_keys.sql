@@ -0,0 +1,14 @@
+-- migrate:up
+-- Ed25519 keys used to sign OAuth2 access tokens. Public halves are published at /.well-known/jwks.json.
+CREATE TABLE IF NOT EXISTS jwt_signing_keys (
+    kid TEXT PRIMARY KEY,
+    -- PKCS#8 document holding the private key
+    private_key BYTEA NOT NULL,
+    -- Raw 32-byte Ed25519 public key
+    public_key BYTEA NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    -- New keys are published before they are used for signing so every instance can verify them
+    activated_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    -- Set when a newer key takes over; the key stays published until issued tokens expire
+    retired_at TIMESTAMPTZ
+);
@@ -0,0 +1,2 @@
+-- migrate:down
+ALTER TABLE api_keys DROP COLUMN IF EXISTS revoked_at;
@@ -0,0 +1,3 @@
+-- migrate:up
+-- Revoked keys are kept for auditing but no longer authenticate
+ALTER TABLE api_keys ADD COLUMN IF NOT EXISTS revoked_at TIMESTAMPTZ;
@@ -0,0 +1,3 @@
+-- migrate:down
+DROP TABLE IF EXISTS rate_limit_route_costs CASCADE;
+DROP TABLE IF EXISTS rate_limit_policies CASCADE;
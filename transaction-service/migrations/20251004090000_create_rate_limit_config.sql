@@ -0,0 +1,16 @@
+-- migrate:up
+-- Per-key token-bucket overrides; keys without a row use RATE_LIMIT_PER_MIN
+CREATE TABLE IF NOT EXISTS rate_limit_policies (
+    api_key_id UUID PRIMARY KEY REFERENCES api_keys(id) ON DELETE CASCADE,
+    capacity INT NOT NULL CHECK (capacity > 0),
+    refill_per_sec DOUBLE PRECISION NOT NULL CHECK (refill_per_sec >= 0),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+-- Tokens charged per request for a route pattern (e.g. 'POST', '/api/transactions'); unlisted routes cost 1
+CREATE TABLE IF NOT EXISTS rate_limit_route_costs (
+    method TEXT NOT NULL,
+    route TEXT NOT NULL,
+    cost INT NOT NULL CHECK (cost >= 0),
+    PRIMARY KEY (method, route)
+);
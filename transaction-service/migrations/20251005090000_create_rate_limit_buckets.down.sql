@@ -0,0 +1,2 @@
+-- migrate:down
+DROP TABLE IF EXISTS rate_limit_buckets;
@@ -0,0 +1,12 @@
+-- migrate:up
+-- Token buckets shared by all replicas when RATE_LIMIT_BACKEND=postgres.
+-- capacity/refill_per_sec are copied from the policy on each charge so idle rows can be evicted without it.
+CREATE TABLE IF NOT EXISTS rate_limit_buckets (
+    api_key_id UUID PRIMARY KEY,
+    tokens DOUBLE PRECISION NOT NULL,
+    capacity DOUBLE PRECISION NOT NULL,
+    refill_per_sec DOUBLE PRECISION NOT NULL,
+    -- Outcome of the last charge, returned by the upsert
+    allowed BOOLEAN NOT NULL,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
@@ -0,0 +1,12 @@
+-- migrate:up
+-- Audit trail of client addresses locked out for repeated failed authentication or public-route abuse
+CREATE TABLE IF NOT EXISTS auth_lockouts (
+    id BIGSERIAL PRIMARY KEY,
+    ip_address TEXT NOT NULL,
+    reason TEXT NOT NULL,
+    attempts INT NOT NULL,
+    locked_until TIMESTAMPTZ NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_auth_lockouts_ip_address ON auth_lockouts(ip_address, created_at DESC);
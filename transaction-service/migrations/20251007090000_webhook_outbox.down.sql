@@ -0,0 +1,5 @@
+-- migrate:down
+DROP INDEX IF EXISTS idx_webhook_events_due;
+ALTER TABLE webhook_events DROP COLUMN IF EXISTS next_attempt_at;
+ALTER TABLE webhook_events DROP COLUMN IF EXISTS payload;
+ALTER TABLE webhook_events DROP COLUMN IF EXISTS event_type;
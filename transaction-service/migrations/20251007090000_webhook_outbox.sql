@@ -0,0 +1,9 @@
+-- migrate:up
+-- Outbox columns: the payload is captured when the event is enqueued (inside the ledger transaction)
+-- and the dispatcher worker picks up rows whose next_attempt_at is due.
+ALTER TABLE webhook_events ADD COLUMN IF NOT EXISTS event_type TEXT NOT NULL DEFAULT 'transaction.created';
+ALTER TABLE webhook_events ADD COLUMN IF NOT EXISTS payload JSONB;
+-- NULL once the event is delivered or out of retries; rows from before the outbox stay NULL
+ALTER TABLE webhook_events ADD COLUMN IF NOT EXISTS next_attempt_at TIMESTAMPTZ;
+
+CREATE INDEX IF NOT EXISTS idx_webhook_events_due ON webhook_events(next_attempt_at) WHERE next_attempt_at IS NOT NULL;
@@ -0,0 +1,4 @@
+-- migrate:down
+DROP INDEX IF EXISTS idx_webhook_events_webhook_status;
+ALTER TABLE webhook_events DROP CONSTRAINT IF EXISTS webhook_events_status_check;
+ALTER TABLE webhook_events DROP COLUMN IF EXISTS status;
@@ -0,0 +1,16 @@
+-- migrate:up
+-- Delivery state: pending (queued or retrying), delivered, or failed (dead-lettered after the last retry)
+ALTER TABLE webhook_events ADD COLUMN IF NOT EXISTS status TEXT NOT NULL DEFAULT 'pending';
+
+UPDATE webhook_events SET status = 'delivered' WHERE delivered AND status = 'pending';
+UPDATE webhook_events SET status = 'failed' WHERE NOT delivered AND next_attempt_at IS NULL AND status = 'pending';
+
+DO $$
+BEGIN
+    IF NOT EXISTS (SELECT 1 FROM pg_constraint WHERE conname = 'webhook_events_status_check') THEN
+        ALTER TABLE webhook_events ADD CONSTRAINT webhook_events_status_check
+            CHECK (status IN ('pending', 'delivered', 'failed'));
+    END IF;
+END $$;
+
+CREATE INDEX IF NOT EXISTS idx_webhook_events_webhook_status ON webhook_events(webhook_id, status, created_at DESC);
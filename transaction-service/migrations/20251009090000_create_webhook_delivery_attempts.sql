@@ -0,0 +1,18 @@
+-- migrate:up
+-- One row per delivery attempt; webhook_events only keeps the latest state
+CREATE TABLE IF NOT EXISTS webhook_delivery_attempts (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    event_id UUID NOT NULL REFERENCES webhook_events(id) ON DELETE CASCADE,
+    attempt INT NOT NULL,
+    request_headers JSONB NOT NULL,
+    response_status INT,
+    -- First 4 KiB of the response body
+    response_body TEXT,
+    latency_ms INT NOT NULL,
+    -- NULL on success; otherwise dns, tls, timeout, connect, non_2xx or network
+    error_kind TEXT,
+    error_message TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_webhook_delivery_attempts_event ON webhook_delivery_attempts(event_id, created_at);
@@ -0,0 +1,2 @@
+-- migrate:down
+ALTER TABLE webhooks DROP COLUMN IF EXISTS event_types;
@@ -0,0 +1,3 @@
+-- migrate:up
+-- Event types a webhook is subscribed to; NULL means every event
+ALTER TABLE webhooks ADD COLUMN IF NOT EXISTS event_types TEXT[];
@@ -0,0 +1,4 @@
+-- migrate:up
+-- Secret replaced by the last rotation; still signed with until previous_secret_expires_at
+ALTER TABLE webhooks ADD COLUMN IF NOT EXISTS previous_secret TEXT;
+ALTER TABLE webhooks ADD COLUMN IF NOT EXISTS previous_secret_expires_at TIMESTAMPTZ;
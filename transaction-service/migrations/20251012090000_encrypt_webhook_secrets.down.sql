@@ -0,0 +1,4 @@
+-- migrate:down
+-- Sealed secrets cannot be decrypted in SQL; webhooks created after the up migration lose their secret
+ALTER TABLE webhooks DROP COLUMN IF EXISTS previous_secret_sealed;
+ALTER TABLE webhooks DROP COLUMN IF EXISTS secret_sealed;
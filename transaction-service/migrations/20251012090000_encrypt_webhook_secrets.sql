@@ -0,0 +1,6 @@
+-- migrate:up
+-- Webhook secrets sealed with envelope encryption (see src/secrets.rs). The plaintext columns are
+-- emptied by the service at startup, since only it holds the encryption keys.
+ALTER TABLE webhooks ADD COLUMN IF NOT EXISTS secret_sealed TEXT;
+ALTER TABLE webhooks ADD COLUMN IF NOT EXISTS previous_secret_sealed TEXT;
+ALTER TABLE webhooks ALTER COLUMN secret DROP NOT NULL;
@@ -0,0 +1,9 @@
+-- migrate:down
+DROP INDEX IF EXISTS idx_webhooks_account;
+ALTER TABLE webhooks DROP COLUMN IF EXISTS updated_at;
+ALTER TABLE webhooks DROP COLUMN IF EXISTS failing_since;
+ALTER TABLE webhooks DROP COLUMN IF EXISTS consecutive_failures;
+ALTER TABLE webhooks DROP COLUMN IF EXISTS disabled_at;
+ALTER TABLE webhooks DROP COLUMN IF EXISTS disabled_reason;
+ALTER TABLE webhooks DROP COLUMN IF EXISTS enabled;
+ALTER TABLE webhooks DROP COLUMN IF EXISTS description;
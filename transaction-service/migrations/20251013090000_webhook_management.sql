@@ -0,0 +1,13 @@
+-- migrate:up
+ALTER TABLE webhooks ADD COLUMN IF NOT EXISTS description TEXT;
+ALTER TABLE webhooks ADD COLUMN IF NOT EXISTS enabled BOOLEAN NOT NULL DEFAULT true;
+-- Why the webhook was disabled, e.g. 'manual' or '20 consecutive events failed'
+ALTER TABLE webhooks ADD COLUMN IF NOT EXISTS disabled_reason TEXT;
+ALTER TABLE webhooks ADD COLUMN IF NOT EXISTS disabled_at TIMESTAMPTZ;
+-- Events dead-lettered since the last successful delivery
+ALTER TABLE webhooks ADD COLUMN IF NOT EXISTS consecutive_failures INT NOT NULL DEFAULT 0;
+-- First failed attempt since the last successful delivery
+ALTER TABLE webhooks ADD COLUMN IF NOT EXISTS failing_since TIMESTAMPTZ;
+ALTER TABLE webhooks ADD COLUMN IF NOT EXISTS updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW();
+
+CREATE INDEX IF NOT EXISTS idx_webhooks_account ON webhooks(account_id);
@@ -0,0 +1,6 @@
+-- migrate:down
+ALTER TABLE webhook_events DROP COLUMN IF EXISTS first_attempt_at;
+ALTER TABLE webhooks DROP COLUMN IF EXISTS retry_deadline_secs;
+ALTER TABLE webhooks DROP COLUMN IF EXISTS retry_max_delay_secs;
+ALTER TABLE webhooks DROP COLUMN IF EXISTS retry_base_delay_secs;
+ALTER TABLE webhooks DROP COLUMN IF EXISTS retry_max_attempts;
@@ -0,0 +1,9 @@
+-- migrate:up
+-- Per-webhook retry policy overrides; NULL falls back to the WEBHOOK_RETRY_* defaults
+ALTER TABLE webhooks ADD COLUMN IF NOT EXISTS retry_max_attempts INT;
+ALTER TABLE webhooks ADD COLUMN IF NOT EXISTS retry_base_delay_secs INT;
+ALTER TABLE webhooks ADD COLUMN IF NOT EXISTS retry_max_delay_secs INT;
+ALTER TABLE webhooks ADD COLUMN IF NOT EXISTS retry_deadline_secs INT;
+
+-- Start of the current retry cycle (reset by replay); the retry deadline counts from here
+ALTER TABLE webhook_events ADD COLUMN IF NOT EXISTS first_attempt_at TIMESTAMPTZ;
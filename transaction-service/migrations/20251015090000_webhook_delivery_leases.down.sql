@@ -0,0 +1,3 @@
+-- migrate:down
+DROP INDEX IF EXISTS idx_webhook_events_leased;
+ALTER TABLE webhook_events DROP COLUMN IF EXISTS leased_until;
@@ -0,0 +1,4 @@
+-- migrate:up
+-- Set while a dispatcher holds the event; counts towards the endpoint's in-flight limit
+ALTER TABLE webhook_events ADD COLUMN IF NOT EXISTS leased_until TIMESTAMPTZ;
+CREATE INDEX IF NOT EXISTS idx_webhook_events_leased ON webhook_events(webhook_id) WHERE leased_until IS NOT NULL;
@@ -0,0 +1,6 @@
+-- migrate:down
+DROP INDEX IF EXISTS idx_webhook_events_sequence;
+ALTER TABLE webhook_events DROP COLUMN IF EXISTS sequence;
+ALTER TABLE webhooks DROP COLUMN IF EXISTS last_sequence;
+ALTER TABLE webhooks DROP COLUMN IF EXISTS batch_size;
+ALTER TABLE webhooks DROP COLUMN IF EXISTS ordered;
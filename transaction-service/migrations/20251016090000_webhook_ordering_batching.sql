@@ -0,0 +1,9 @@
+-- migrate:up
+-- Ordered endpoints get their events strictly by sequence; batched endpoints get up to batch_size events per request
+ALTER TABLE webhooks ADD COLUMN IF NOT EXISTS ordered BOOLEAN NOT NULL DEFAULT false;
+ALTER TABLE webhooks ADD COLUMN IF NOT EXISTS batch_size INT;
+-- Last sequence number handed out to this webhook's events
+ALTER TABLE webhooks ADD COLUMN IF NOT EXISTS last_sequence BIGINT NOT NULL DEFAULT 0;
+
+ALTER TABLE webhook_events ADD COLUMN IF NOT EXISTS sequence BIGINT;
+CREATE INDEX IF NOT EXISTS idx_webhook_events_sequence ON webhook_events(webhook_id, sequence) WHERE status = 'pending';
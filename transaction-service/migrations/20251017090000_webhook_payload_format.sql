@@ -0,0 +1,3 @@
+-- migrate:up
+-- Wire format of deliveries: legacy JSON or CloudEvents 1.0 (structured or binary mode)
+ALTER TABLE webhooks ADD COLUMN IF NOT EXISTS payload_format TEXT NOT NULL DEFAULT 'legacy';
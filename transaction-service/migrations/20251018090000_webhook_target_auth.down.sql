@@ -0,0 +1,5 @@
+-- migrate:down
+ALTER TABLE webhooks DROP COLUMN IF EXISTS basic_auth_username;
+ALTER TABLE webhooks DROP COLUMN IF EXISTS basic_auth_sealed;
+ALTER TABLE webhooks DROP COLUMN IF EXISTS custom_header_names;
+ALTER TABLE webhooks DROP COLUMN IF EXISTS custom_headers_sealed;
@@ -0,0 +1,7 @@
+-- migrate:up
+-- Extra headers and basic-auth credentials sent with every delivery, sealed like the signing secret.
+-- Header names and the username are kept in plaintext so responses can show them with masked values.
+ALTER TABLE webhooks ADD COLUMN IF NOT EXISTS custom_headers_sealed TEXT;
+ALTER TABLE webhooks ADD COLUMN IF NOT EXISTS custom_header_names TEXT[];
+ALTER TABLE webhooks ADD COLUMN IF NOT EXISTS basic_auth_sealed TEXT;
+ALTER TABLE webhooks ADD COLUMN IF NOT EXISTS basic_auth_username TEXT;
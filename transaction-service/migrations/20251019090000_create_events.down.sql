@@ -0,0 +1,3 @@
+-- migrate:down
+DROP TABLE IF EXISTS events;
+DROP TABLE IF EXISTS event_streams;
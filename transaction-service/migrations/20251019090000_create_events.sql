@@ -0,0 +1,18 @@
+-- migrate:up
+-- Per-account event counter; its row lock orders events by commit
+CREATE TABLE IF NOT EXISTS event_streams (
+    account_id UUID PRIMARY KEY REFERENCES accounts(id) ON DELETE CASCADE,
+    last_sequence BIGINT NOT NULL DEFAULT 0
+);
+
+-- Every domain event, kept for EVENT_RETENTION_DAYS and served by GET /api/events
+CREATE TABLE IF NOT EXISTS events (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    account_id UUID NOT NULL REFERENCES accounts(id) ON DELETE CASCADE,
+    sequence BIGINT NOT NULL,
+    event_type TEXT NOT NULL,
+    payload JSONB NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    UNIQUE (account_id, sequence)
+);
+CREATE INDEX IF NOT EXISTS idx_events_created_at ON events(created_at);
@@ -0,0 +1,6 @@
+-- migrate:down
+DROP INDEX IF EXISTS idx_transactions_to_account_created;
+DROP INDEX IF EXISTS idx_transactions_from_account_created;
+DROP INDEX IF EXISTS idx_webhook_events_replay_job;
+ALTER TABLE webhook_events DROP COLUMN IF EXISTS replay_job_id;
+DROP TABLE IF EXISTS webhook_replay_jobs;
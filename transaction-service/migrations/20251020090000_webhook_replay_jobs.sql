@@ -0,0 +1,30 @@
+-- migrate:up
+-- Backfills of historical transaction events to one webhook, worked off by webhooks::backfill
+CREATE TABLE IF NOT EXISTS webhook_replay_jobs (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    webhook_id UUID NOT NULL REFERENCES webhooks(id) ON DELETE CASCADE,
+    range_from TIMESTAMPTZ NOT NULL,
+    range_to TIMESTAMPTZ NOT NULL,
+    event_types TEXT[] NOT NULL,
+    -- running, completed or failed
+    status TEXT NOT NULL DEFAULT 'running',
+    total_events INT NOT NULL,
+    enqueued_events INT NOT NULL DEFAULT 0,
+    -- Last (transaction created_at, id, event type rank) enqueued
+    cursor_created_at TIMESTAMPTZ,
+    cursor_txn_id UUID,
+    cursor_rank INT,
+    error TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    completed_at TIMESTAMPTZ
+);
+-- One running backfill per webhook
+CREATE UNIQUE INDEX IF NOT EXISTS idx_webhook_replay_jobs_running ON webhook_replay_jobs(webhook_id) WHERE status = 'running';
+
+ALTER TABLE webhook_events ADD COLUMN IF NOT EXISTS replay_job_id UUID REFERENCES webhook_replay_jobs(id) ON DELETE SET NULL;
+CREATE INDEX IF NOT EXISTS idx_webhook_events_replay_job ON webhook_events(replay_job_id) WHERE replay_job_id IS NOT NULL;
+
+-- Backfills page through an account's transactions by time
+CREATE INDEX IF NOT EXISTS idx_transactions_from_account_created ON transactions(from_account, created_at);
+CREATE INDEX IF NOT EXISTS idx_transactions_to_account_created ON transactions(to_account, created_at);
@@ -0,0 +1,6 @@
+-- migrate:down
+-- Sealed keys cannot be decrypted in SQL; keys created after the up migration are dropped and
+-- recreated by the service on its next start
+DELETE FROM jwt_signing_keys WHERE private_key IS NULL;
+ALTER TABLE jwt_signing_keys DROP COLUMN IF EXISTS private_key_sealed;
+ALTER TABLE jwt_signing_keys ALTER COLUMN private_key SET NOT NULL;
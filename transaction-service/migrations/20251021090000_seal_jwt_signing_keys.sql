@@ -0,0 +1,5 @@
+-- migrate:up
+-- JWT private keys sealed with the same envelope encryption as webhook secrets (see src/secrets.rs).
+-- The plaintext column is emptied by the service at startup, since only it holds the encryption keys.
+ALTER TABLE jwt_signing_keys ADD COLUMN IF NOT EXISTS private_key_sealed TEXT;
+ALTER TABLE jwt_signing_keys ALTER COLUMN private_key DROP NOT NULL;
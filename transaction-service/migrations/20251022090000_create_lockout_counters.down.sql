@@ -0,0 +1,3 @@
+-- migrate:down
+DROP TABLE IF EXISTS active_lockouts;
+DROP TABLE IF EXISTS lockout_counters;
@@ -0,0 +1,15 @@
+-- migrate:up
+-- Attempt counters and current lockouts shared by all replicas when RATE_LIMIT_BACKEND=postgres
+CREATE TABLE IF NOT EXISTS lockout_counters (
+    ip_address TEXT NOT NULL,
+    -- failed_auth or public_route
+    reason TEXT NOT NULL,
+    attempts INT NOT NULL,
+    window_started TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (ip_address, reason)
+);
+
+CREATE TABLE IF NOT EXISTS active_lockouts (
+    ip_address TEXT PRIMARY KEY,
+    locked_until TIMESTAMPTZ NOT NULL
+);
@@ -0,0 +1,2 @@
+-- migrate:down
+ALTER TABLE webhooks DROP COLUMN IF EXISTS legacy_signature;
@@ -0,0 +1,5 @@
+-- migrate:up
+-- Webhooks created before the timestamped signature keep receiving the deprecated X-Signature header
+-- until their owners turn it off; new webhooks do not get it unless they ask for it.
+ALTER TABLE webhooks ADD COLUMN IF NOT EXISTS legacy_signature BOOLEAN NOT NULL DEFAULT TRUE;
+ALTER TABLE webhooks ALTER COLUMN legacy_signature SET DEFAULT FALSE;
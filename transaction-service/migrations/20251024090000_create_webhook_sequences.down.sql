@@ -0,0 +1,4 @@
+-- migrate:down
+ALTER TABLE webhooks ADD COLUMN IF NOT EXISTS last_sequence BIGINT NOT NULL DEFAULT 0;
+UPDATE webhooks w SET last_sequence = s.last_sequence FROM webhook_sequences s WHERE s.webhook_id = w.id;
+DROP TABLE IF EXISTS webhook_sequences;
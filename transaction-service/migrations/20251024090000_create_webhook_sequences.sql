@@ -0,0 +1,12 @@
+-- migrate:up
+-- Per-webhook event counter; its row lock orders a webhook's events by commit. Kept off the webhooks
+-- row so emitting never locks the row the dispatcher writes failure counters to.
+CREATE TABLE IF NOT EXISTS webhook_sequences (
+    webhook_id UUID PRIMARY KEY REFERENCES webhooks(id) ON DELETE CASCADE,
+    last_sequence BIGINT NOT NULL DEFAULT 0
+);
+
+INSERT INTO webhook_sequences (webhook_id, last_sequence)
+SELECT id, last_sequence FROM webhooks WHERE last_sequence > 0
+ON CONFLICT (webhook_id) DO NOTHING;
+ALTER TABLE webhooks DROP COLUMN IF EXISTS last_sequence;
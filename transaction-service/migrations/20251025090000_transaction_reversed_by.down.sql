@@ -0,0 +1,2 @@
+-- migrate:down
+ALTER TABLE transactions DROP COLUMN IF EXISTS reversed_by;
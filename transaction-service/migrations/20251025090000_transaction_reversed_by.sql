@@ -0,0 +1,4 @@
+-- migrate:up
+-- The compensating transaction that reversed this one; set by a reversal flow together with status 'reversed'.
+-- History replays date transaction.reversed by that row's created_at.
+ALTER TABLE transactions ADD COLUMN IF NOT EXISTS reversed_by UUID REFERENCES transactions(id);
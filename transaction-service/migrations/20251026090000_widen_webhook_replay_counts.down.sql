@@ -0,0 +1,2 @@
+-- migrate:down
+ALTER TABLE webhook_replay_jobs ALTER COLUMN total_events TYPE INT, ALTER COLUMN enqueued_events TYPE INT;
@@ -0,0 +1,3 @@
+-- migrate:up
+-- A long range of a busy account can exceed INT
+ALTER TABLE webhook_replay_jobs ALTER COLUMN total_events TYPE BIGINT, ALTER COLUMN enqueued_events TYPE BIGINT;
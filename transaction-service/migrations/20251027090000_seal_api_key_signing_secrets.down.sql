@@ -0,0 +1,4 @@
+-- migrate:down
+-- Sealed secrets cannot be decrypted in SQL; keys whose secret was sealed can no longer sign
+-- requests and still work as bearer keys
+ALTER TABLE api_keys DROP COLUMN IF EXISTS signing_secret_sealed;
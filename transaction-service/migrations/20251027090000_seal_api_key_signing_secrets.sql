@@ -0,0 +1,4 @@
+-- migrate:up
+-- TS-HMAC signing secrets sealed with the same envelope encryption as webhook secrets (see src/secrets.rs).
+-- The plaintext column is emptied by the service at startup, since only it holds the encryption keys.
+ALTER TABLE api_keys ADD COLUMN IF NOT EXISTS signing_secret_sealed TEXT;
@@ -1,34 +1,217 @@
 use sha2::{Digest, Sha256};
-use argon2::{Argon2, password_hash::{SaltString, PasswordHasher, PasswordHash, PasswordVerifier}};
+use argon2::{Algorithm, Argon2, Params, Version, password_hash::{SaltString, PasswordHasher, PasswordHash, PasswordVerifier}};
+use hmac::{Hmac, Mac};
+use once_cell::sync::Lazy;
 use rand::rngs::OsRng;
+use sqlx::PgPool;
+use std::env;
+use uuid::Uuid;
 
 pub const FINGERPRINT_LEN: usize = 16; // characters
 
+/// Authorization scheme name for signed requests: `Authorization: TS-HMAC keyId=..., ...`
+pub const HMAC_SCHEME: &str = "TS-HMAC";
+
+/// Identity of the caller, inserted into request extensions by `auth_middleware`.
+#[derive(Debug, Clone)]
+pub struct AuthContext {
+    pub api_key_id: Uuid,
+    pub account_id: Uuid,
+    /// Scopes granted to an OAuth2 access token; `None` for API key and TS-HMAC callers (full access).
+    pub scopes: Option<Vec<String>>,
+}
+
+impl AuthContext {
+    pub fn has_scope(&self, scope: &str) -> bool {
+        self.scopes.as_ref().is_none_or(|scopes| scopes.iter().any(|s| s == scope))
+    }
+}
+
 pub fn compute_fingerprint(key: &str) -> String {
+    compute_key_digest(key).chars().take(FINGERPRINT_LEN).collect()
+}
+
+/// Full hex SHA-256 of a raw key, used to index the verified-key cache.
+pub fn compute_key_digest(key: &str) -> String {
     let mut hasher = Sha256::new();
     hasher.update(key.as_bytes());
     let digest = hasher.finalize();
-    let hex = hex::encode(digest);
-    hex.chars().take(FINGERPRINT_LEN).collect()
+    hex::encode(digest)
+}
+
+fn env_cost(name: &str, default: u32) -> u32 {
+    env::var(name).ok().and_then(|v| v.parse().ok()).unwrap_or(default)
+}
+
+// Cost parameters for new hashes: ARGON2_MEMORY_KIB, ARGON2_ITERATIONS and ARGON2_PARALLELISM
+static PARAMS: Lazy<Params> = Lazy::new(|| {
+    let m_cost = env_cost("ARGON2_MEMORY_KIB", Params::DEFAULT_M_COST);
+    let t_cost = env_cost("ARGON2_ITERATIONS", Params::DEFAULT_T_COST);
+    let p_cost = env_cost("ARGON2_PARALLELISM", Params::DEFAULT_P_COST);
+    Params::new(m_cost, t_cost, p_cost, None).unwrap_or_else(|e| {
+        tracing::error!("Invalid Argon2 parameters (m={}, t={}, p={}): {}; using defaults", m_cost, t_cost, p_cost, e);
+        Params::default()
+    })
+});
+
+fn argon2() -> Argon2<'static> {
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, PARAMS.clone())
+}
+
+/// Label for the parameters new hashes are created with, e.g. `argon2id m=4096,t=3,p=1`.
+pub fn current_params_label() -> String {
+    format!("argon2id m={},t={},p={}", PARAMS.m_cost(), PARAMS.t_cost(), PARAMS.p_cost())
+}
+
+/// Label for the parameters a stored hash was created with, or `None` if it does not parse.
+pub fn hash_params_label(stored_hash: &str) -> Option<String> {
+    let parsed = PasswordHash::new(stored_hash).ok()?;
+    let params = Params::try_from(&parsed).ok()?;
+    Some(format!("{} m={},t={},p={}", parsed.algorithm, params.m_cost(), params.t_cost(), params.p_cost()))
+}
+
+/// Whether a stored hash was created with different algorithm or costs than the current ones.
+pub fn needs_rehash(stored_hash: &str) -> bool {
+    hash_params_label(stored_hash).is_none_or(|label| label != current_params_label())
 }
 
 pub fn hash_key(key: &str) -> Result<String, argon2::password_hash::Error> {
     let salt = SaltString::generate(&mut OsRng);
-    let argon2 = Argon2::default();
+    let argon2 = argon2();
     let password_hash = argon2.hash_password(key.as_bytes(), &salt)?;
     Ok(password_hash.to_string())
 }
 
 pub fn verify_key(key: &str, stored_hash: &str) -> Result<bool, argon2::password_hash::Error> {
+    // Verification uses the parameters recorded in the stored hash
     let parsed = PasswordHash::new(stored_hash)?;
     let verified = Argon2::default().verify_password(key.as_bytes(), &parsed).is_ok();
     Ok(verified)
 }
 
+/// After a successful verification, upgrade a hash created with outdated parameters.
+/// Runs in the background; the update is skipped if the stored hash changed meanwhile.
+pub fn spawn_rehash_if_outdated(pool: &PgPool, api_key_id: Uuid, key: &str, stored_hash: &str) {
+    if !needs_rehash(stored_hash) {
+        return;
+    }
+    let pool = pool.clone();
+    let key = key.to_string();
+    let stored_hash = stored_hash.to_string();
+    tokio::spawn(async move {
+        // Argon2 at the configured cost would stall an async worker thread
+        let new_hash = match tokio::task::spawn_blocking(move || hash_key(&key)).await {
+            Ok(Ok(hash)) => hash,
+            Ok(Err(e)) => {
+                tracing::error!("Failed to rehash API key {}: {}", api_key_id, e);
+                return;
+            }
+            Err(e) => {
+                tracing::error!("Failed to rehash API key {}: {}", api_key_id, e);
+                return;
+            }
+        };
+        let result = sqlx::query("UPDATE api_keys SET key_hash = $1 WHERE id = $2 AND key_hash = $3")
+            .bind(&new_hash)
+            .bind(api_key_id)
+            .bind(&stored_hash)
+            .execute(&pool)
+            .await;
+        match result {
+            Ok(done) if done.rows_affected() == 1 => {
+                tracing::info!("Upgraded Argon2 parameters for API key {}", api_key_id)
+            }
+            // Another request upgraded it first, or the key was rotated
+            Ok(_) => tracing::debug!("Skipped rehash of API key {}: stored hash changed", api_key_id),
+            Err(e) => tracing::error!("Failed to store rehashed API key {}: {}", api_key_id, e),
+        }
+    });
+}
+
+// Hash of a key nobody holds, verified when no stored key matches so that unknown keys
+// cost the same Argon2 work as wrong ones.
+static DUMMY_HASH: Lazy<String> = Lazy::new(|| {
+    let filler: String = (0..32).map(|_| char::from(b'a' + rand::random::<u8>() % 26)).collect();
+    hash_key(&filler).expect("hashing a random filler key should succeed")
+});
+
+/// Compute the dummy hash up front so the first unknown key is not slower than later ones.
+pub fn init_dummy_hash() {
+    Lazy::force(&DUMMY_HASH);
+}
+
+/// Run a full Argon2 verification that always fails.
+pub fn dummy_verify(key: &str) {
+    let _ = verify_key(key, &DUMMY_HASH);
+}
+
+/// Parsed parameters of a `TS-HMAC` authorization header.
+#[derive(Debug, PartialEq)]
+pub struct HmacAuthorization {
+    pub key_id: Uuid,
+    pub timestamp: i64,
+    pub nonce: String,
+    pub signature: String,
+}
+
+/// Parse the value of an `Authorization` header using the `TS-HMAC` scheme.
+/// Returns `None` if the scheme does not match or a parameter is missing/malformed.
+pub fn parse_hmac_authorization(value: &str) -> Option<HmacAuthorization> {
+    let params = value.strip_prefix(HMAC_SCHEME)?.strip_prefix(' ')?;
+
+    let mut key_id = None;
+    let mut timestamp = None;
+    let mut nonce = None;
+    let mut signature = None;
+    for part in params.split(',') {
+        let (name, val) = part.trim().split_once('=')?;
+        match name {
+            "keyId" => key_id = Uuid::parse_str(val).ok(),
+            "timestamp" => timestamp = val.parse().ok(),
+            "nonce" if !val.is_empty() && val.len() <= 128 => nonce = Some(val.to_string()),
+            "signature" => signature = Some(val.to_string()),
+            _ => {}
+        }
+    }
+
+    Some(HmacAuthorization {
+        key_id: key_id?,
+        timestamp: timestamp?,
+        nonce: nonce?,
+        signature: signature?,
+    })
+}
+
+/// Build the string that is signed by the client:
+/// `METHOD\nPATH_AND_QUERY\nTIMESTAMP\nNONCE\nhex(sha256(body))`
+pub fn hmac_string_to_sign(method: &str, path_and_query: &str, timestamp: i64, nonce: &str, body: &[u8]) -> String {
+    let body_hash = hex::encode(Sha256::digest(body));
+    format!("{}\n{}\n{}\n{}\n{}", method.to_uppercase(), path_and_query, timestamp, nonce, body_hash)
+}
+
+/// Verify a hex request signature in constant time.
+pub fn verify_request_signature(secret: &str, string_to_sign: &str, signature_hex: &str) -> bool {
+    let Ok(signature) = hex::decode(signature_hex) else {
+        return false;
+    };
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes())
+        .expect("HMAC can take key of any size");
+    mac.update(string_to_sign.as_bytes());
+    mac.verify_slice(&signature).is_ok()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    // Client-side counterpart of `verify_request_signature`
+    fn sign_request(secret: &str, string_to_sign: &str) -> String {
+        let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes())
+            .expect("HMAC can take key of any size");
+        mac.update(string_to_sign.as_bytes());
+        hex::encode(mac.finalize().into_bytes())
+    }
+
     #[test]
     fn fingerprint_length() {
         let key = "mysecretkey123";
@@ -45,4 +228,49 @@ mod tests {
         let bad = verify_key("wrong", &hashed).expect("verify should run");
         assert!(!bad);
     }
+
+    #[test]
+    fn rehash_detection() {
+        let current = hash_key("k").unwrap();
+        assert!(!needs_rehash(&current));
+        assert_eq!(hash_params_label(&current).unwrap(), current_params_label());
+
+        let salt = SaltString::generate(&mut OsRng);
+        let weak = Argon2::new(Algorithm::Argon2id, Version::V0x13, Params::new(1024, 1, 1, None).unwrap())
+            .hash_password(b"k", &salt)
+            .unwrap()
+            .to_string();
+        assert!(needs_rehash(&weak));
+        assert!(verify_key("k", &weak).unwrap());
+        assert!(needs_rehash("not a hash"));
+    }
+
+    #[test]
+    fn dummy_hash_never_matches() {
+        assert!(!verify_key("anything", &DUMMY_HASH).expect("dummy hash should parse"));
+    }
+
+    #[test]
+    fn parse_hmac_header() {
+        let id = Uuid::new_v4();
+        let header = format!("TS-HMAC keyId={}, timestamp=1700000000, nonce=abc123, signature=deadbeef", id);
+        let parsed = parse_hmac_authorization(&header).expect("header should parse");
+        assert_eq!(parsed.key_id, id);
+        assert_eq!(parsed.timestamp, 1_700_000_000);
+        assert_eq!(parsed.nonce, "abc123");
+        assert_eq!(parsed.signature, "deadbeef");
+
+        assert!(parse_hmac_authorization("Bearer abc").is_none());
+        assert!(parse_hmac_authorization("TS-HMAC keyId=not-a-uuid, timestamp=1, nonce=a, signature=b").is_none());
+    }
+
+    #[test]
+    fn sign_and_verify_request() {
+        let to_sign = hmac_string_to_sign("post", "/api/transactions", 1_700_000_000, "n1", b"{\"amount\":\"1.00\"}");
+        let sig = sign_request("secret", &to_sign);
+        assert!(verify_request_signature("secret", &to_sign, &sig));
+        assert!(!verify_request_signature("other", &to_sign, &sig));
+        let tampered = hmac_string_to_sign("POST", "/api/transactions", 1_700_000_000, "n1", b"{\"amount\":\"9.00\"}");
+        assert!(!verify_request_signature("secret", &tampered, &sig));
+    }
 }
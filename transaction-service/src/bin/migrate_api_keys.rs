@@ -1,25 +1,10 @@
 use sqlx::PgPool;
 use sqlx::Row;
 use std::env;
-use sha2::{Digest, Sha256};
-use argon2::{Argon2, password_hash::SaltString, PasswordHasher};
-use rand::rngs::OsRng;
 use anyhow::Context;
 
-fn compute_fingerprint(key: &str) -> String {
-    let mut hasher = Sha256::new();
-    hasher.update(key.as_bytes());
-    let digest = hasher.finalize();
-    let hex = hex::encode(digest);
-    hex.chars().take(16).collect()
-}
-
-fn hash_key(key: &str) -> Result<String, argon2::password_hash::Error> {
-    let salt = SaltString::generate(&mut OsRng);
-    let argon2 = Argon2::default();
-    let password_hash = argon2.hash_password(key.as_bytes(), &salt)?;
-    Ok(password_hash.to_string())
-}
+// The service's fingerprint and hashing code (including the ARGON2_* cost settings)
+use transaction_service::auth::{compute_fingerprint, hash_key};
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
@@ -0,0 +1,312 @@
+//! Pull-based event log: every domain event that can trigger a webhook is also written to `events`,
+//! whether or not a webhook is subscribed, and served by `GET /api/events`.
+//!
+//! Each account has its own stream numbered 1, 2, 3, ... by `event_streams.last_sequence`. The
+//! counter row stays locked until the writing transaction commits, so a client paging by sequence
+//! never sees a gap fill in later. Events are kept for `EVENT_RETENTION_DAYS` (default 30).
+//!
+//! Every committed event is announced on the `events` channel with the account id as payload.
+//! `listen` relays these to the instance's live streams (`stream`), so a stream on any instance
+//! hears about events written by any other.
+
+use axum::http::StatusCode;
+use once_cell::sync::Lazy;
+use sqlx::postgres::PgListener;
+use sqlx::types::Json;
+use sqlx::{PgConnection, PgPool};
+use std::env;
+use std::time::Duration;
+use tokio::sync::broadcast;
+use uuid::Uuid;
+
+use crate::models::{ErrorResponse, Event, EventPage, WebhookPayload};
+
+/// Postgres channel notified with the account id of every committed event.
+pub const CHANNEL: &str = "events";
+
+/// Account ids of committed events, relayed from `CHANNEL`. The nil id means notifications may
+/// have been missed and every stream should check for new events.
+static COMMITTED: Lazy<broadcast::Sender<Uuid>> = Lazy::new(|| broadcast::channel(1024).0);
+
+/// Why a page of events could not be served.
+#[derive(Debug)]
+pub enum ListError {
+    /// Events after the cursor have already been purged
+    Expired,
+    /// The cursor is past the newest event of the stream
+    Ahead,
+    Database(sqlx::Error),
+}
+
+impl From<sqlx::Error> for ListError {
+    fn from(e: sqlx::Error) -> Self {
+        ListError::Database(e)
+    }
+}
+
+impl ListError {
+    /// Status and body to answer with; database errors are logged here.
+    pub fn into_error(self) -> (StatusCode, ErrorResponse) {
+        match self {
+            ListError::Expired => (
+                StatusCode::GONE,
+                ErrorResponse::new(
+                    "cursor_expired",
+                    "Events after this cursor are past the retention window; restart without a cursor",
+                ),
+            ),
+            ListError::Ahead => (
+                StatusCode::BAD_REQUEST,
+                ErrorResponse::new("validation_error", "starting_after is past the newest event"),
+            ),
+            ListError::Database(e) => {
+                tracing::error!("Failed to fetch events: {}", e);
+                (StatusCode::INTERNAL_SERVER_ERROR, ErrorResponse::new("database_error", "Failed to fetch events"))
+            }
+        }
+    }
+}
+
+fn retention_days() -> i32 {
+    env::var("EVENT_RETENTION_DAYS").ok().and_then(|v| v.parse().ok()).unwrap_or(30)
+}
+
+/// Append an event to the stream of each of `account_ids`.
+///
+/// Called by `webhooks::emit` on the connection of the transaction that makes the change. Streams
+/// are locked in account id order, after the webhook rows, so concurrent writers cannot deadlock.
+pub async fn record(
+    conn: &mut PgConnection,
+    account_ids: &[Uuid],
+    event_type: &str,
+    payload: &WebhookPayload,
+) -> Result<(), sqlx::Error> {
+    let mut account_ids = account_ids.to_vec();
+    account_ids.sort();
+    account_ids.dedup();
+
+    // clock_timestamp() rather than NOW(): taken under the stream lock, so created_at follows
+    // sequence order and the retention sweep only ever removes a stream's oldest events
+    sqlx::query(
+        r#"
+        WITH streams AS (
+            INSERT INTO event_streams (account_id, last_sequence)
+            SELECT id, 1 FROM unnest($1::uuid[]) AS a(id) ORDER BY id
+            ON CONFLICT (account_id) DO UPDATE SET last_sequence = event_streams.last_sequence + 1
+            RETURNING account_id, last_sequence
+        )
+        INSERT INTO events (account_id, sequence, event_type, payload, created_at)
+        SELECT account_id, last_sequence, $2, $3, clock_timestamp() FROM streams
+        "#,
+    )
+    .bind(&account_ids)
+    .bind(event_type)
+    .bind(Json(payload))
+    .execute(&mut *conn)
+    .await?;
+
+    // Delivered to listeners only when the surrounding transaction commits
+    sqlx::query("SELECT pg_notify($1, id::text) FROM unnest($2::uuid[]) AS a(id)")
+        .bind(CHANNEL)
+        .bind(&account_ids)
+        .execute(&mut *conn)
+        .await?;
+    Ok(())
+}
+
+/// Sequence of the account's newest event, 0 if it has none yet.
+pub async fn head(pool: &PgPool, account_id: Uuid) -> Result<i64, sqlx::Error> {
+    let head: Option<i64> = sqlx::query_scalar("SELECT last_sequence FROM event_streams WHERE account_id = $1")
+        .bind(account_id)
+        .fetch_optional(pool)
+        .await?;
+    Ok(head.unwrap_or(0))
+}
+
+/// Up to `limit` events of the account after sequence `starting_after` (from the oldest retained
+/// event if `None`), oldest first, optionally only of `types`.
+pub async fn list(
+    pool: &PgPool,
+    account_id: Uuid,
+    starting_after: Option<i64>,
+    types: Option<&[String]>,
+    limit: i64,
+) -> Result<EventPage, ListError> {
+    // One snapshot for the stream head and the page, so a purge in between cannot hide a gap
+    let mut tx = pool.begin().await?;
+    sqlx::query("SET TRANSACTION ISOLATION LEVEL REPEATABLE READ READ ONLY").execute(&mut *tx).await?;
+
+    let (head, first_retained): (i64, Option<i64>) = sqlx::query_as(
+        r#"
+        SELECT COALESCE((SELECT last_sequence FROM event_streams WHERE account_id = $1), 0),
+               (SELECT MIN(sequence) FROM events WHERE account_id = $1)
+        "#,
+    )
+    .bind(account_id)
+    .fetch_one(&mut *tx)
+    .await?;
+
+    let after = starting_after.unwrap_or(0);
+    if after > head {
+        return Err(ListError::Ahead);
+    }
+    // Sequences have no gaps, so anything missing between the cursor and the oldest retained event was purged
+    if starting_after.is_some() && after < first_retained.unwrap_or(head + 1) - 1 {
+        return Err(ListError::Expired);
+    }
+
+    let mut events = sqlx::query_as::<_, Event>(
+        r#"
+        SELECT id, sequence, event_type, payload, created_at FROM events
+        WHERE account_id = $1 AND sequence > $2 AND ($3::text[] IS NULL OR event_type = ANY($3))
+        ORDER BY sequence
+        LIMIT $4
+        "#,
+    )
+    .bind(account_id)
+    .bind(after)
+    .bind(types)
+    .bind(limit + 1)
+    .fetch_all(&mut *tx)
+    .await?;
+    tx.commit().await?;
+
+    let has_more = events.len() as i64 > limit;
+    events.truncate(limit as usize);
+    // Without more matches the cursor can skip to the head, past events filtered out by `types`
+    let next_cursor = match events.last() {
+        Some(last) if has_more => last.sequence,
+        _ => head.max(after),
+    };
+    Ok(EventPage { events, has_more, next_cursor })
+}
+
+/// Hear about committed events; see `COMMITTED`.
+pub fn subscribe() -> broadcast::Receiver<Uuid> {
+    COMMITTED.subscribe()
+}
+
+/// Background task: relay `CHANNEL` notifications to this instance's subscribers.
+pub async fn listen(pool: PgPool) {
+    loop {
+        let mut listener = match PgListener::connect_with(&pool).await {
+            Ok(listener) => listener,
+            Err(e) => {
+                tracing::error!("Failed to connect event listener: {}", e);
+                tokio::time::sleep(Duration::from_secs(5)).await;
+                continue;
+            }
+        };
+        if let Err(e) = listener.listen(CHANNEL).await {
+            tracing::error!("Failed to LISTEN on {}: {}", CHANNEL, e);
+            tokio::time::sleep(Duration::from_secs(5)).await;
+            continue;
+        }
+
+        // Events committed while the listener was down were not announced
+        let _ = COMMITTED.send(Uuid::nil());
+
+        loop {
+            match listener.try_recv().await {
+                Ok(Some(notification)) => {
+                    if let Ok(account_id) = Uuid::parse_str(notification.payload()) {
+                        // Fails only if nobody is streaming on this instance
+                        let _ = COMMITTED.send(account_id);
+                    }
+                }
+                // Connection lost; the listener reconnects on the next call but notifications may have been missed
+                Ok(None) => {
+                    let _ = COMMITTED.send(Uuid::nil());
+                }
+                Err(e) => {
+                    tracing::warn!("Event listener disconnected: {}", e);
+                    break;
+                }
+            }
+        }
+    }
+}
+
+/// Background task: delete events older than the retention window, hourly.
+pub async fn purge_expired(pool: PgPool) {
+    let mut interval = tokio::time::interval(Duration::from_secs(3600));
+    loop {
+        interval.tick().await;
+        let purged = sqlx::query("DELETE FROM events WHERE created_at < NOW() - make_interval(days => $1)")
+            .bind(retention_days())
+            .execute(&pool)
+            .await;
+        match purged {
+            Ok(result) if result.rows_affected() > 0 => {
+                tracing::info!("Purged {} events past the retention window", result.rows_affected());
+            }
+            Ok(_) => {}
+            Err(e) => tracing::error!("Failed to purge expired events: {}", e),
+        }
+    }
+}
+
+/// Tests against Postgres; see `webhooks::tests` for how to run them.
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{Account, EventData};
+    use crate::webhooks::tests::account;
+
+    async fn record_for(pool: &PgPool, accounts: &[&Account], event_type: &str) {
+        let payload = WebhookPayload {
+            event_type: event_type.to_string(),
+            data: EventData::Account(accounts[0].clone()),
+            timestamp: chrono::Utc::now(),
+        };
+        let ids: Vec<Uuid> = accounts.iter().map(|account| account.id).collect();
+        let mut tx = pool.begin().await.unwrap();
+        record(&mut tx, &ids, event_type, &payload).await.unwrap();
+        tx.commit().await.unwrap();
+    }
+
+    fn sequences(page: &EventPage) -> Vec<i64> {
+        page.events.iter().map(|event| event.sequence).collect()
+    }
+
+    #[sqlx::test]
+    #[ignore]
+    async fn pages_through_an_accounts_stream(pool: PgPool) {
+        let owner = account(&pool).await;
+        let other = account(&pool).await;
+        for event_type in ["account.updated", "api_key.created", "account.updated", "account.updated", "api_key.created"] {
+            record_for(&pool, &[&owner], event_type).await;
+        }
+        record_for(&pool, &[&other, &owner], "transaction.created").await;
+
+        let page = list(&pool, owner.id, None, None, 4).await.unwrap();
+        assert_eq!((sequences(&page), page.has_more, page.next_cursor), (vec![1, 2, 3, 4], true, 4));
+        let page = list(&pool, owner.id, Some(4), None, 4).await.unwrap();
+        assert_eq!((sequences(&page), page.has_more, page.next_cursor), (vec![5, 6], false, 6));
+
+        // Filtered pages still move the cursor to the head once nothing else matches
+        let types = ["api_key.created".to_string()];
+        let page = list(&pool, owner.id, None, Some(&types), 10).await.unwrap();
+        assert_eq!((sequences(&page), page.has_more, page.next_cursor), (vec![2, 5], false, 6));
+
+        // Each account numbers its own stream
+        assert_eq!(sequences(&list(&pool, other.id, None, None, 10).await.unwrap()), [1]);
+        assert_eq!(head(&pool, other.id).await.unwrap(), 1);
+    }
+
+    #[sqlx::test]
+    #[ignore]
+    async fn rejects_cursors_ahead_of_the_stream_or_behind_retention(pool: PgPool) {
+        let owner = account(&pool).await;
+        for _ in 0..4 {
+            record_for(&pool, &[&owner], "account.updated").await;
+        }
+        assert!(matches!(list(&pool, owner.id, Some(5), None, 10).await, Err(ListError::Ahead)));
+
+        // The first two were purged: a cursor before them would silently skip events
+        sqlx::query("DELETE FROM events WHERE account_id = $1 AND sequence <= 2").bind(owner.id).execute(&pool).await.unwrap();
+        assert!(matches!(list(&pool, owner.id, Some(1), None, 10).await, Err(ListError::Expired)));
+        assert_eq!(sequences(&list(&pool, owner.id, Some(2), None, 10).await.unwrap()), [3, 4]);
+        assert_eq!(sequences(&list(&pool, owner.id, None, None, 10).await.unwrap()), [3, 4]);
+    }
+}
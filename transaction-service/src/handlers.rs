@@ -1,16 +1,30 @@
 use axum::{
-    extract::{Path, State},
-    http::StatusCode,
-    Json,
+    extract::{ws::WebSocketUpgrade, Path, Query, State},
+    Extension,
+    http::{header::{AUTHORIZATION, CACHE_CONTROL}, HeaderMap, HeaderName, HeaderValue, StatusCode},
+    response::{sse::{self, KeepAlive, Sse}, IntoResponse, Response},
+    Form, Json,
 };
-use sqlx::PgPool;
+use futures_util::Stream;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use sqlx::{PgConnection, PgPool};
+use std::collections::HashMap;
+use std::convert::Infallible;
 use uuid::Uuid;
-use sqlx::Row;
+use sqlx::{FromRow, Row};
 use rust_decimal::Decimal;
-use hmac::Hmac;
-use hmac::Mac;
-use sha2::Sha256;
-use crate::auth::{compute_fingerprint, hash_key};
+use crate::auth::{
+    compute_fingerprint, current_params_label, hash_key, hash_params_label, spawn_rehash_if_outdated, verify_key,
+    AuthContext,
+};
+use crate::events;
+use crate::jwt;
+use crate::key_cache;
+use crate::lockout;
+use crate::rate_limit;
+use crate::secrets;
+use crate::stream;
+use crate::webhooks;
 
 use crate::models::*;
 
@@ -24,6 +38,15 @@ pub async fn create_account(
 ) -> Result<Json<Account>, (StatusCode, Json<ErrorResponse>)> {
     let initial_balance = payload.initial_balance.unwrap_or_default();
 
+    let db_error = |e: sqlx::Error| {
+        tracing::error!("Failed to create account: {}", e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse::new("database_error", "Failed to create account")),
+        )
+    };
+    let mut tx = pool.begin().await.map_err(db_error)?;
+
     let account = sqlx::query_as::<_, Account>(
         r#"
         INSERT INTO accounts (business_name, balance)
@@ -33,15 +56,14 @@ pub async fn create_account(
     )
     .bind(payload.business_name)
     .bind(initial_balance)
-    .fetch_one(&pool)
+    .fetch_one(&mut *tx)
     .await
-    .map_err(|e| {
-        tracing::error!("Failed to create account: {}", e);
-        (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json(ErrorResponse::new("database_error", "Failed to create account")),
-        )
-    })?;
+    .map_err(db_error)?;
+
+    webhooks::emit(&mut tx, &[account.id], webhooks::ACCOUNT_CREATED, EventData::Account(account.clone()))
+        .await
+        .map_err(db_error)?;
+    tx.commit().await.map_err(db_error)?;
 
     Ok(Json(account))
 }
@@ -204,96 +226,33 @@ pub async fn create_transaction(
         )
     })?;
 
-    // Update balances based on transaction type
-    match payload.txn_type.as_str() {
-        "credit" => {
-            // Add to target account
-            let to_account_id = payload.to_account_id.unwrap();
-            sqlx::query(
-                "UPDATE accounts SET balance = balance + $1, updated_at = NOW() WHERE id = $2",
-            )
-            .bind(payload.amount)
-            .bind(to_account_id)
-            .execute(&mut *tx)
-            .await
-            .map_err(|e| {
-                tracing::error!("Failed to update account balance: {}", e);
-                (
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    Json(ErrorResponse::new("database_error", "Failed to update balance")),
-                )
-            })?;
-        }
-        "debit" => {
-            // Subtract from source account
-            let from_account_id = payload.from_account_id.unwrap();
-            let result = sqlx::query(
-                "UPDATE accounts SET balance = balance - $1, updated_at = NOW() WHERE id = $2 AND balance >= $1",
-            )
-            .bind(payload.amount)
-            .bind(from_account_id)
-            .execute(&mut *tx)
-            .await
-            .map_err(|e| {
-                tracing::error!("Failed to update account balance: {}", e);
-                (
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    Json(ErrorResponse::new("database_error", "Failed to update balance")),
-                )
-            })?;
-
-            if result.rows_affected() == 0 {
-                return Err((
-                    StatusCode::BAD_REQUEST,
-                    Json(ErrorResponse::new("insufficient_funds", "Insufficient funds")),
-                ));
-            }
+    // Move the funds: debit the source (if any), then credit the target (if any)
+    let updated_accounts = match apply_balance_changes(&mut tx, payload.from_account_id, payload.to_account_id, payload.amount).await {
+        Ok(accounts) => accounts,
+        Err(BalanceError::InsufficientFunds) => {
+            drop(tx);
+            let failed = FailedTransactionEventData {
+                from_account: payload.from_account_id,
+                to_account: payload.to_account_id,
+                amount: payload.amount,
+                txn_type: payload.txn_type,
+                status: "failed".to_string(),
+                reason: "insufficient_funds".to_string(),
+            };
+            emit_transaction_failed(&pool, failed).await;
+            return Err((
+                StatusCode::BAD_REQUEST,
+                Json(ErrorResponse::new("insufficient_funds", "Insufficient funds")),
+            ));
         }
-        "transfer" => {
-            // Subtract from source account
-            let from_account_id = payload.from_account_id.unwrap();
-            let to_account_id = payload.to_account_id.unwrap();
-
-            let result = sqlx::query(
-                "UPDATE accounts SET balance = balance - $1, updated_at = NOW() WHERE id = $2 AND balance >= $1",
-            )
-            .bind(payload.amount)
-            .bind(from_account_id)
-            .execute(&mut *tx)
-            .await
-            .map_err(|e| {
-                tracing::error!("Failed to update source account balance: {}", e);
-                (
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    Json(ErrorResponse::new("database_error", "Failed to update balance")),
-                )
-            })?;
-
-            if result.rows_affected() == 0 {
-                return Err((
-                    StatusCode::BAD_REQUEST,
-                    Json(ErrorResponse::new("insufficient_funds", "Insufficient funds")),
-                ));
-            }
-
-            // Add to target account
-            sqlx::query(
-                "UPDATE accounts SET balance = balance + $1, updated_at = NOW() WHERE id = $2",
-            )
-            .bind(payload.amount)
-            .bind(to_account_id)
-            .execute(&mut *tx)
-            .await
-            .map_err(|e| {
-                tracing::error!("Failed to update target account balance: {}", e);
-                (
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    Json(ErrorResponse::new("database_error", "Failed to update balance")),
-                )
-            })?;
+        Err(BalanceError::Database(e)) => {
+            tracing::error!("Failed to update account balance: {}", e);
+            return Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse::new("database_error", "Failed to update balance")),
+            ));
         }
-        _ => unreachable!(),
-    }
+    };
 
     // Create the transaction record
     let transaction = sqlx::query_as::<_, Transaction>(
@@ -317,6 +276,9 @@ pub async fn create_transaction(
         )
     })?;
 
+    // Queue webhook events in the same transaction so they are never lost or sent for a rolled-back change
+    emit_transaction_events(&mut tx, webhooks::TRANSACTION_CREATED, &transaction, updated_accounts).await?;
+
     // Commit the transaction
     tx.commit().await.map_err(|e| {
         tracing::error!("Failed to commit transaction: {}", e);
@@ -326,12 +288,219 @@ pub async fn create_transaction(
         )
     })?;
 
-    // TODO: Trigger webhook delivery
-    tokio::spawn(deliver_webhooks(pool.clone(), transaction.clone()));
-
     Ok(Json(transaction))
 }
 
+/// Reverse a transaction credited to the caller's account, returning the funds to the sender. Only
+/// the recipient can give money back this way; anything else needs an operator.
+pub async fn reverse_transaction(
+    State(pool): State<PgPool>,
+    Extension(auth): Extension<AuthContext>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<Transaction>, (StatusCode, Json<ErrorResponse>)> {
+    reverse(&pool, id, Some(auth.account_id)).await.map(Json)
+}
+
+/// Operator reversal of any completed transaction, including debits and payments the sender disputes.
+pub async fn admin_reverse_transaction(
+    State(pool): State<PgPool>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<Transaction>, (StatusCode, Json<ErrorResponse>)> {
+    reverse(&pool, id, None).await.map(Json)
+}
+
+/// Book the opposite movement of funds for a completed transaction, mark the original `reversed` with
+/// a link to the compensating transaction, and queue `transaction.created` for the reversal and
+/// `transaction.reversed` for the original. With `recipient`, the caller must be a party and the
+/// transaction must have credited that account.
+async fn reverse(pool: &PgPool, id: Uuid, recipient: Option<Uuid>) -> Result<Transaction, (StatusCode, Json<ErrorResponse>)> {
+    let db_error = |message: &'static str| {
+        move |e: sqlx::Error| {
+            tracing::error!("{}: {}", message, e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse::new("database_error", message)),
+            )
+        }
+    };
+    let mut tx = pool.begin().await.map_err(db_error("Failed to begin transaction"))?;
+
+    // Lock the original so it cannot be reversed twice concurrently
+    let original = sqlx::query_as::<_, Transaction>(
+        r#"
+        SELECT id, from_account, to_account, amount, txn_type, status, created_at FROM transactions
+        WHERE id = $1 AND ($2::uuid IS NULL OR from_account = $2 OR to_account = $2)
+        FOR UPDATE
+        "#,
+    )
+    .bind(id)
+    .bind(recipient)
+    .fetch_optional(&mut *tx)
+    .await
+    .map_err(db_error("Failed to fetch transaction"))?
+    .ok_or((
+        StatusCode::NOT_FOUND,
+        Json(ErrorResponse::new("not_found", "Transaction not found")),
+    ))?;
+    if recipient.is_some() && original.to_account != recipient {
+        return Err((
+            StatusCode::FORBIDDEN,
+            Json(ErrorResponse::new("not_recipient", "Only the account that received the funds can reverse a transaction")),
+        ));
+    }
+    if original.status != "completed" {
+        return Err((
+            StatusCode::CONFLICT,
+            Json(ErrorResponse::new("not_reversible", "Only completed transactions can be reversed")),
+        ));
+    }
+
+    // The reversal moves the same amount in the opposite direction
+    let reversal_type = match original.txn_type.as_str() {
+        "credit" => "debit",
+        "debit" => "credit",
+        _ => "transfer",
+    };
+    let updated_accounts = match apply_balance_changes(&mut tx, original.to_account, original.from_account, original.amount).await {
+        Ok(accounts) => accounts,
+        Err(BalanceError::InsufficientFunds) => {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                Json(ErrorResponse::new("insufficient_funds", "Insufficient funds to reverse the transaction")),
+            ));
+        }
+        Err(BalanceError::Database(e)) => return Err(db_error("Failed to update balance")(e)),
+    };
+
+    let reversal = sqlx::query_as::<_, Transaction>(
+        r#"
+        INSERT INTO transactions (from_account, to_account, amount, txn_type, status)
+        VALUES ($1, $2, $3, $4, 'completed')
+        RETURNING id, from_account, to_account, amount, txn_type, status, created_at
+        "#,
+    )
+    .bind(original.to_account)
+    .bind(original.from_account)
+    .bind(original.amount)
+    .bind(reversal_type)
+    .fetch_one(&mut *tx)
+    .await
+    .map_err(db_error("Failed to reverse transaction"))?;
+    let reversed = sqlx::query_as::<_, Transaction>(
+        r#"
+        UPDATE transactions SET status = 'reversed', reversed_by = $2 WHERE id = $1
+        RETURNING id, from_account, to_account, amount, txn_type, status, created_at
+        "#,
+    )
+    .bind(original.id)
+    .bind(reversal.id)
+    .fetch_one(&mut *tx)
+    .await
+    .map_err(db_error("Failed to reverse transaction"))?;
+
+    // Same order as a history replay regenerates them: the reversal's creation, then the reversal of the original
+    emit_transaction_events(&mut tx, webhooks::TRANSACTION_CREATED, &reversal, updated_accounts).await?;
+    emit_transaction_events(&mut tx, webhooks::TRANSACTION_REVERSED, &reversed, Vec::new()).await?;
+
+    tx.commit().await.map_err(db_error("Failed to commit transaction"))?;
+    Ok(reversal)
+}
+
+enum BalanceError {
+    InsufficientFunds,
+    Database(sqlx::Error),
+}
+
+impl From<sqlx::Error> for BalanceError {
+    fn from(e: sqlx::Error) -> Self {
+        BalanceError::Database(e)
+    }
+}
+
+/// Debit `from` (failing if its balance is too low) and credit `to`. Returns the updated accounts.
+async fn apply_balance_changes(
+    conn: &mut PgConnection,
+    from: Option<Uuid>,
+    to: Option<Uuid>,
+    amount: Decimal,
+) -> Result<Vec<Account>, BalanceError> {
+    let mut updated = Vec::new();
+    if let Some(from) = from {
+        let account = sqlx::query_as::<_, Account>(
+            r#"
+            UPDATE accounts SET balance = balance - $1, updated_at = NOW() WHERE id = $2 AND balance >= $1
+            RETURNING id, business_name, balance, created_at, updated_at
+            "#,
+        )
+        .bind(amount)
+        .bind(from)
+        .fetch_optional(&mut *conn)
+        .await?
+        .ok_or(BalanceError::InsufficientFunds)?;
+        updated.push(account);
+    }
+    if let Some(to) = to {
+        let account = sqlx::query_as::<_, Account>(
+            r#"
+            UPDATE accounts SET balance = balance + $1, updated_at = NOW() WHERE id = $2
+            RETURNING id, business_name, balance, created_at, updated_at
+            "#,
+        )
+        .bind(amount)
+        .bind(to)
+        .fetch_optional(&mut *conn)
+        .await?;
+        updated.extend(account);
+    }
+    Ok(updated)
+}
+
+/// Queue `event_type` for the accounts a transaction touches, plus `account.updated` for each
+/// account whose balance changed.
+async fn emit_transaction_events(
+    conn: &mut PgConnection,
+    event_type: &str,
+    transaction: &Transaction,
+    updated_accounts: Vec<Account>,
+) -> Result<(), (StatusCode, Json<ErrorResponse>)> {
+    let map_err = |e: sqlx::Error| {
+        tracing::error!("Failed to queue webhook events: {}", e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse::new("database_error", "Failed to queue webhook events")),
+        )
+    };
+    let account_ids: Vec<Uuid> = [transaction.from_account, transaction.to_account].into_iter().flatten().collect();
+    webhooks::emit(conn, &account_ids, event_type, EventData::Transaction(transaction.clone()))
+        .await
+        .map_err(map_err)?;
+    for account in updated_accounts {
+        webhooks::emit(conn, &[account.id], webhooks::ACCOUNT_UPDATED, EventData::Account(account))
+            .await
+            .map_err(map_err)?;
+    }
+    Ok(())
+}
+
+/// Queue `transaction.failed` for the accounts a rejected transaction names, like `transaction.created`.
+/// The ledger change was rolled back, so this runs in its own transaction; failures are only logged.
+async fn emit_transaction_failed(pool: &PgPool, failed: FailedTransactionEventData) {
+    let result = async {
+        let mut tx = pool.begin().await?;
+        // Nothing checked that the accounts exist, and events can only be recorded for ones that do
+        let account_ids: Vec<Uuid> = sqlx::query_scalar("SELECT id FROM accounts WHERE id = ANY($1) ORDER BY id")
+            .bind([failed.from_account, failed.to_account].into_iter().flatten().collect::<Vec<_>>())
+            .fetch_all(&mut *tx)
+            .await?;
+        webhooks::emit(&mut tx, &account_ids, webhooks::TRANSACTION_FAILED, EventData::FailedTransaction(failed)).await?;
+        tx.commit().await
+    }
+    .await;
+    if let Err(e) = result {
+        tracing::error!("Failed to queue transaction.failed event: {}", e);
+    }
+}
+
 pub async fn list_transactions(
     State(pool): State<PgPool>,
 ) -> Result<Json<Vec<Transaction>>, (StatusCode, Json<ErrorResponse>)> {
@@ -380,47 +549,89 @@ pub async fn get_transaction(
 // API Key Handlers
 // ============================
 
+/// Attempts at drawing a key whose fingerprint is not already taken.
+const MAX_KEY_ISSUE_ATTEMPTS: u32 = 5;
+
+fn is_unique_violation(e: &sqlx::Error) -> bool {
+    matches!(e, sqlx::Error::Database(db) if db.code().as_deref() == Some("23505"))
+}
+
 pub async fn create_api_key(
     State(pool): State<PgPool>,
     Json(payload): Json<CreateApiKeyRequest>,
 ) -> Result<Json<serde_json::Value>, (StatusCode, Json<ErrorResponse>)> {
-    // Generate a random API key
     use rand::Rng;
-    let api_key: String = rand::thread_rng()
+    // Separate secret for clients that sign requests (TS-HMAC) instead of sending the key
+    let signing_secret: String = rand::thread_rng()
         .sample_iter(&rand::distributions::Alphanumeric)
-        .take(32)
+        .take(48)
         .map(char::from)
         .collect();
-    // Compute fingerprint and Argon2 hash using helpers
-    let fingerprint = compute_fingerprint(&api_key);
-    let password_hash = hash_key(&api_key).map_err(|e| {
-        tracing::error!("Failed to hash API key: {}", e);
-        (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json(ErrorResponse::new("crypto_error", "Failed to hash API key")),
-        )
-    })?;
 
-    // Insert fingerprint + hash into DB
-    let row = sqlx::query(
-        r#"
-        INSERT INTO api_keys (account_id, key_fingerprint, key_hash)
-        VALUES ($1, $2, $3)
-        RETURNING id, account_id, key_fingerprint, key_hash, created_at, last_used
-        "#,
-    )
-    .bind(payload.account_id)
-    .bind(&fingerprint)
-    .bind(&password_hash)
-    .fetch_one(&pool)
-    .await
-    .map_err(|e| {
-        tracing::error!("Failed to insert API key: {}", e);
-        (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json(ErrorResponse::new("database_error", "Failed to create API key")),
-        )
-    })?;
+    // Fingerprints are truncated hashes, so a fresh key can collide with an existing one on the
+    // UNIQUE index; draw a new key when that happens.
+    let mut attempt = 0;
+    let (api_key, row) = loop {
+        attempt += 1;
+
+        // Generate a random API key
+        let api_key: String = rand::thread_rng()
+            .sample_iter(&rand::distributions::Alphanumeric)
+            .take(32)
+            .map(char::from)
+            .collect();
+        // Compute fingerprint and Argon2 hash using helpers
+        let fingerprint = compute_fingerprint(&api_key);
+        let password_hash = hash_key(&api_key).map_err(|e| {
+            tracing::error!("Failed to hash API key: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse::new("crypto_error", "Failed to hash API key")),
+            )
+        })?;
+
+        // Insert fingerprint + hash into DB, together with the api_key.created event
+        let result = async {
+            let mut tx = pool.begin().await?;
+            let row = sqlx::query(
+                r#"
+                INSERT INTO api_keys (account_id, key_fingerprint, key_hash, signing_secret_sealed)
+                VALUES ($1, $2, $3, $4)
+                RETURNING id, account_id, key_fingerprint, key_hash, created_at, last_used
+                "#,
+            )
+            .bind(payload.account_id)
+            .bind(&fingerprint)
+            .bind(&password_hash)
+            .bind(secrets::seal(&signing_secret))
+            .fetch_one(&mut *tx)
+            .await?;
+            let event = ApiKeyEventData {
+                id: row.get("id"),
+                account_id: row.get("account_id"),
+                created_at: row.get("created_at"),
+                revoked_at: None,
+            };
+            webhooks::emit(&mut tx, &[event.account_id], webhooks::API_KEY_CREATED, EventData::ApiKey(event)).await?;
+            tx.commit().await?;
+            Ok::<_, sqlx::Error>(row)
+        }
+        .await;
+
+        match result {
+            Ok(row) => break (api_key, row),
+            Err(e) if is_unique_violation(&e) && attempt < MAX_KEY_ISSUE_ATTEMPTS => {
+                tracing::warn!("API key fingerprint collision on attempt {}; retrying", attempt);
+            }
+            Err(e) => {
+                tracing::error!("Failed to insert API key: {}", e);
+                return Err((
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(ErrorResponse::new("database_error", "Failed to create API key")),
+                ));
+            }
+        }
+    };
 
     // Build response containing the raw key once
     #[derive(serde::Serialize)]
@@ -428,6 +639,7 @@ pub async fn create_api_key(
         id: uuid::Uuid,
         account_id: uuid::Uuid,
         key: String,
+        signing_secret: String,
         created_at: chrono::DateTime<chrono::Utc>,
         last_used: Option<chrono::DateTime<chrono::Utc>>,
     }
@@ -436,6 +648,7 @@ pub async fn create_api_key(
         id: row.get("id"),
         account_id: row.get("account_id"),
         key: api_key,
+        signing_secret,
         created_at: row.get("created_at"),
         last_used: row.get("last_used"),
     };
@@ -443,195 +656,1454 @@ pub async fn create_api_key(
     Ok(Json(serde_json::to_value(created).unwrap()))
 }
 
-// ============================
-// Webhook Handlers
-// ============================
-
-pub async fn create_webhook(
+/// Revoke one of the caller's API keys. Cached verifications are dropped on every instance;
+/// access tokens already issued for the key remain valid until they expire.
+pub async fn revoke_api_key(
     State(pool): State<PgPool>,
-    Json(payload): Json<CreateWebhookRequest>,
-) -> Result<Json<Webhook>, (StatusCode, Json<ErrorResponse>)> {
-    // Generate a random secret for HMAC signing
-    use rand::Rng;
-    let secret: String = rand::thread_rng()
-        .sample_iter(&rand::distributions::Alphanumeric)
-        .take(32)
-        .map(char::from)
-        .collect();
+    Extension(auth): Extension<AuthContext>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<serde_json::Value>, (StatusCode, Json<ErrorResponse>)> {
+    let mut tx = pool.begin().await.map_err(|e| {
+        tracing::error!("Failed to begin transaction: {}", e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse::new("database_error", "Failed to revoke API key")),
+        )
+    })?;
 
-    let webhook = sqlx::query_as::<_, Webhook>(
+    let (created_at, revoked_at): (chrono::DateTime<chrono::Utc>, chrono::DateTime<chrono::Utc>) = sqlx::query_as(
         r#"
-        INSERT INTO webhooks (account_id, url, secret)
-        VALUES ($1, $2, $3)
-        RETURNING id, account_id, url, secret, created_at
+        UPDATE api_keys SET revoked_at = NOW() WHERE id = $1 AND account_id = $2 AND revoked_at IS NULL
+        RETURNING created_at, revoked_at
         "#,
     )
-    .bind(payload.account_id)
-    .bind(payload.url)
-    .bind(secret)
-    .fetch_one(&pool)
+    .bind(id)
+    .bind(auth.account_id)
+    .fetch_optional(&mut *tx)
     .await
     .map_err(|e| {
-        tracing::error!("Failed to create webhook: {}", e);
+        tracing::error!("Failed to revoke API key: {}", e);
         (
             StatusCode::INTERNAL_SERVER_ERROR,
-            Json(ErrorResponse::new("database_error", "Failed to create webhook")),
+            Json(ErrorResponse::new("database_error", "Failed to revoke API key")),
         )
-    })?;
-    Ok(Json(webhook))
-}
+    })?
+    .ok_or((
+        StatusCode::NOT_FOUND,
+        Json(ErrorResponse::new("not_found", "API key not found")),
+    ))?;
 
-pub async fn list_webhooks(
-    State(pool): State<PgPool>,
-) -> Result<Json<Vec<Webhook>>, (StatusCode, Json<ErrorResponse>)> {
-    let webhooks = sqlx::query_as::<_, Webhook>(
-        "SELECT id, account_id, url, secret, created_at FROM webhooks ORDER BY created_at DESC",
-    )
-    .fetch_all(&pool)
-    .await
-    .map_err(|e| {
-        tracing::error!("Failed to fetch webhooks: {}", e);
+    // Delivered to every instance (including this one) when the transaction commits
+    sqlx::query("SELECT pg_notify($1, $2)")
+        .bind(key_cache::REVOCATION_CHANNEL)
+        .bind(id.to_string())
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to publish API key revocation: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse::new("database_error", "Failed to revoke API key")),
+            )
+        })?;
+
+    let event = ApiKeyEventData {
+        id,
+        account_id: auth.account_id,
+        created_at,
+        revoked_at: Some(revoked_at),
+    };
+    webhooks::emit(&mut tx, &[auth.account_id], webhooks::API_KEY_REVOKED, EventData::ApiKey(event))
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to queue webhook events: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse::new("database_error", "Failed to revoke API key")),
+            )
+        })?;
+
+    tx.commit().await.map_err(|e| {
+        tracing::error!("Failed to commit transaction: {}", e);
         (
             StatusCode::INTERNAL_SERVER_ERROR,
-            Json(ErrorResponse::new("database_error", "Failed to fetch webhooks")),
+            Json(ErrorResponse::new("database_error", "Failed to revoke API key")),
         )
     })?;
-    Ok(Json(webhooks))
+    key_cache::invalidate(id);
+
+    Ok(Json(serde_json::json!({ "id": id, "revoked_at": revoked_at })))
 }
 
 // ============================
-// Webhook Delivery
+// OAuth2 Handlers
 // ============================
 
-async fn deliver_webhooks(pool: PgPool, transaction: Transaction) {
-    tracing::info!("Starting webhook delivery for transaction {}", transaction.id);
-
-    // Find all webhooks for accounts involved in the transaction
-    let mut account_ids = Vec::new();
-    if let Some(from_account) = transaction.from_account {
-        account_ids.push(from_account);
-    }
-    if let Some(to_account) = transaction.to_account {
-        account_ids.push(to_account);
+/// `POST /oauth/token` — client_credentials grant. The client id is the API key id and the
+/// client secret is the raw API key.
+pub async fn issue_token(
+    State(pool): State<PgPool>,
+    headers: HeaderMap,
+    Form(payload): Form<TokenRequest>,
+) -> Result<impl IntoResponse, (StatusCode, Json<ErrorResponse>)> {
+    if payload.grant_type != "client_credentials" {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse::new("unsupported_grant_type", "Only the client_credentials grant is supported")),
+        ));
     }
 
-    for account_id in account_ids {
-        let rows = sqlx::query_as::<_, Webhook>(
-            "SELECT id, account_id, url, secret, created_at FROM webhooks WHERE account_id = $1",
+    let invalid_client = || {
+        (
+            StatusCode::UNAUTHORIZED,
+            Json(ErrorResponse::new("invalid_client", "Client authentication failed")),
         )
-        .bind(account_id)
-        .fetch_all(&pool)
-        .await;
-        if let Ok(rows) = rows {
-            let webhooks: Vec<Webhook> = rows;
-
-            for webhook in webhooks {
-                // Create webhook event record
-                let event = sqlx::query_as::<_, WebhookEvent>(
-                    r#"
-                    INSERT INTO webhook_events (webhook_id, txn_id, delivered, retry_count)
-                    VALUES ($1, $2, false, 0)
-                    RETURNING id, webhook_id, txn_id, delivered, retry_count, last_attempt, created_at
-                    "#,
-                )
-                .bind(webhook.id)
-                .bind(transaction.id)
-                .fetch_one(&pool)
-                .await;
-
-                if let Ok(event) = event {
-                    // Attempt delivery
-                    tokio::spawn(attempt_webhook_delivery(pool.clone(), webhook, transaction.clone(), event));
-                }
-            }
-        }
-    }
-}
-
-async fn attempt_webhook_delivery(
-    pool: PgPool,
-    webhook: Webhook,
-    transaction: Transaction,
-    mut event: WebhookEvent,
-) {
-    const MAX_RETRIES: i32 = 3;
-    
-    let payload = WebhookPayload {
-        event_type: "transaction.created".to_string(),
-        transaction: transaction.clone(),
-        timestamp: chrono::Utc::now(),
     };
 
-    for attempt in 0..=MAX_RETRIES {
-        tracing::info!("Attempting webhook delivery {} for event {}", attempt + 1, event.id);
+    // Client credentials come from HTTP Basic auth or the form body
+    let basic = headers
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Basic "))
+        .and_then(|encoded| STANDARD.decode(encoded.trim()).ok())
+        .and_then(|decoded| String::from_utf8(decoded).ok())
+        .and_then(|decoded| decoded.split_once(':').map(|(id, secret)| (id.to_string(), secret.to_string())));
+    let (client_id, client_secret) = match (basic, payload.client_id, payload.client_secret) {
+        (Some(credentials), _, _) => credentials,
+        (None, Some(id), Some(secret)) => (id, secret),
+        _ => return Err(invalid_client()),
+    };
+    let api_key_id = Uuid::parse_str(&client_id).map_err(|_| invalid_client())?;
 
-        let client = reqwest::Client::new();
-        // Compute HMAC-SHA256 signature over JSON body
-        let body = match serde_json::to_vec(&payload) {
-            Ok(b) => b,
-            Err(e) => {
-                tracing::error!("Failed to serialize webhook payload: {}", e);
-                // Mark event as failed and break
-                let _ = sqlx::query(
-                    "UPDATE webhook_events SET delivered = $1, retry_count = $2, last_attempt = NOW() WHERE id = $3",
-                )
-                .bind(false)
-                .bind(attempt)
-                .bind(event.id)
-                .execute(&pool)
-                .await;
-                break;
-            }
-        };
+    let scope = jwt::parse_scope(payload.scope.as_deref()).map_err(|message| {
+        (
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse::new("invalid_scope", &message)),
+        )
+    })?;
 
-        // NOTE: webhook.secret is stored as plain text; use it as HMAC key
-        let mut mac = Hmac::<Sha256>::new_from_slice(webhook.secret.as_bytes())
-            .expect("HMAC can take key of any size");
-        mac.update(&body);
-        let signature = mac.finalize().into_bytes();
-        let sig_hex = hex::encode(signature);
-        let sig_header = format!("sha256={}", sig_hex);
-
-        let result = client
-            .post(&webhook.url)
-            .header("Content-Type", "application/json")
-            .header("X-Signature", sig_header)
-            .body(body.clone())
-            .send()
-            .await;
-
-        let success = match result {
-            Ok(response) => response.status().is_success(),
-            Err(e) => {
-                tracing::error!("Webhook delivery failed: {}", e);
-                false
-            }
-        };
+    let row = sqlx::query("SELECT account_id, key_hash FROM api_keys WHERE id = $1 AND account_id IS NOT NULL AND revoked_at IS NULL")
+        .bind(api_key_id)
+        .fetch_optional(&pool)
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to look up OAuth client: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse::new("database_error", "Failed to authenticate client")),
+            )
+        })?
+        .ok_or_else(invalid_client)?;
 
-        // Update webhook event
-        event.retry_count = attempt;
-        event.delivered = success;
+    let key_hash: String = row.get("key_hash");
+    if !verify_key(&client_secret, &key_hash).unwrap_or(false) {
+        return Err(invalid_client());
+    }
+    spawn_rehash_if_outdated(&pool, api_key_id, &client_secret, &key_hash);
 
-        let _ = sqlx::query(
-            "UPDATE webhook_events SET delivered = $1, retry_count = $2, last_attempt = NOW() WHERE id = $3",
+    let (access_token, expires_in) = jwt::issue_token(api_key_id, row.get("account_id"), &scope).map_err(|e| {
+        tracing::error!("Failed to issue access token: {}", e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse::new("crypto_error", "Failed to issue access token")),
         )
-        .bind(event.delivered)
-        .bind(event.retry_count)
-        .bind(event.id)
-        .execute(&pool)
-        .await;
+    })?;
 
-        if success {
-            tracing::info!("Webhook delivered successfully for event {}", event.id);
-            break;
-        }
+    key_cache::touch(api_key_id);
+
+    Ok((
+        [(CACHE_CONTROL, "no-store")],
+        Json(TokenResponse {
+            access_token,
+            token_type: "Bearer".to_string(),
+            expires_in,
+            scope,
+        }),
+    ))
+}
+
+/// `GET /.well-known/jwks.json` — public keys for verifying access tokens.
+pub async fn jwks() -> Json<serde_json::Value> {
+    Json(jwt::jwks())
+}
+
+// ============================
+// Admin Handlers
+// ============================
+
+/// Publish a new JWT signing key; the previous key stays published until its tokens expire.
+pub async fn rotate_jwt_keys(
+    State(pool): State<PgPool>,
+) -> Result<Json<serde_json::Value>, (StatusCode, Json<ErrorResponse>)> {
+    let (kid, activates_at) = jwt::rotate(&pool).await.map_err(|e| {
+        tracing::error!("Failed to rotate JWT signing key: {}", e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse::new("database_error", "Failed to rotate signing key")),
+        )
+    })?;
+    Ok(Json(serde_json::json!({ "kid": kid, "activates_at": activates_at })))
+}
+
+/// Report how many active API keys are hashed with each set of Argon2 parameters, so operators
+/// can track the upgrade to the current costs (hashes are upgraded on each key's next use).
+pub async fn api_key_hash_report(
+    State(pool): State<PgPool>,
+) -> Result<Json<HashReport>, (StatusCode, Json<ErrorResponse>)> {
+    let hashes: Vec<String> = sqlx::query_scalar("SELECT key_hash FROM api_keys WHERE revoked_at IS NULL")
+        .fetch_all(&pool)
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to fetch API key hashes: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse::new("database_error", "Failed to build hash report")),
+            )
+        })?;
+
+    let current = current_params_label();
+    let mut counts: std::collections::BTreeMap<String, i64> = std::collections::BTreeMap::new();
+    for hash in &hashes {
+        let label = hash_params_label(hash).unwrap_or_else(|| "unparseable".to_string());
+        *counts.entry(label).or_default() += 1;
+    }
+
+    let outdated = counts.iter().filter(|(label, _)| **label != current).map(|(_, n)| n).sum();
+    Ok(Json(HashReport {
+        current_params: current.clone(),
+        total: hashes.len() as i64,
+        outdated,
+        by_params: counts
+            .into_iter()
+            .map(|(params, count)| HashParamsCount { current: params == current, params, count })
+            .collect(),
+    }))
+}
+
+/// Inspect a key's rate-limit policy and current bucket.
+pub async fn get_rate_limit(
+    Path(api_key_id): Path<Uuid>,
+) -> Result<Json<RateLimitStatus>, (StatusCode, Json<ErrorResponse>)> {
+    let (policy, policy_override) = rate_limit::policy_for(api_key_id);
+    let tokens = rate_limit::inspect(api_key_id).await.map_err(|e| {
+        tracing::error!("Failed to inspect rate limit bucket: {}", e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse::new("database_error", "Failed to inspect rate limit bucket")),
+        )
+    })?;
+    Ok(Json(RateLimitStatus {
+        api_key_id,
+        capacity: policy.capacity,
+        refill_per_sec: policy.refill_per_sec,
+        policy_override,
+        tokens: tokens.unwrap_or(policy.capacity),
+    }))
+}
+
+/// Refill a key's bucket.
+pub async fn reset_rate_limit(Path(api_key_id): Path<Uuid>) -> Result<StatusCode, (StatusCode, Json<ErrorResponse>)> {
+    rate_limit::reset(api_key_id).await.map_err(|e| {
+        tracing::error!("Failed to reset rate limit bucket: {}", e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse::new("database_error", "Failed to reset rate limit bucket")),
+        )
+    })?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Set a per-key policy override.
+pub async fn set_rate_limit_policy(
+    State(pool): State<PgPool>,
+    Path(api_key_id): Path<Uuid>,
+    Json(payload): Json<SetRateLimitPolicyRequest>,
+) -> Result<Json<RateLimitStatus>, (StatusCode, Json<ErrorResponse>)> {
+    if payload.capacity <= 0 || !payload.refill_per_sec.is_finite() || payload.refill_per_sec < 0.0 {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse::new("validation_error", "capacity must be positive and refill_per_sec non-negative")),
+        ));
+    }
+
+    let result = sqlx::query(
+        r#"
+        INSERT INTO rate_limit_policies (api_key_id, capacity, refill_per_sec)
+        VALUES ($1, $2, $3)
+        ON CONFLICT (api_key_id) DO UPDATE
+        SET capacity = EXCLUDED.capacity, refill_per_sec = EXCLUDED.refill_per_sec, updated_at = NOW()
+        "#,
+    )
+    .bind(api_key_id)
+    .bind(payload.capacity)
+    .bind(payload.refill_per_sec)
+    .execute(&pool)
+    .await;
+    if let Err(e) = result {
+        if let sqlx::Error::Database(db_err) = &e {
+            if db_err.code().as_deref() == Some("23503") {
+                return Err((
+                    StatusCode::NOT_FOUND,
+                    Json(ErrorResponse::new("not_found", "API key not found")),
+                ));
+            }
+        }
+        tracing::error!("Failed to set rate limit policy: {}", e);
+        return Err((
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse::new("database_error", "Failed to set rate limit policy")),
+        ));
+    }
+
+    reload_rate_limits(&pool).await?;
+    get_rate_limit(Path(api_key_id)).await
+}
+
+/// Set the number of tokens a route costs.
+pub async fn set_route_cost(
+    State(pool): State<PgPool>,
+    Json(payload): Json<RouteCost>,
+) -> Result<Json<RouteCost>, (StatusCode, Json<ErrorResponse>)> {
+    if payload.cost < 0 {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse::new("validation_error", "cost must be non-negative")),
+        ));
+    }
+    let method = payload.method.to_uppercase();
+
+    sqlx::query(
+        r#"
+        INSERT INTO rate_limit_route_costs (method, route, cost) VALUES ($1, $2, $3)
+        ON CONFLICT (method, route) DO UPDATE SET cost = EXCLUDED.cost
+        "#,
+    )
+    .bind(&method)
+    .bind(&payload.route)
+    .bind(payload.cost)
+    .execute(&pool)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to set route cost: {}", e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse::new("database_error", "Failed to set route cost")),
+        )
+    })?;
+
+    reload_rate_limits(&pool).await?;
+    Ok(Json(RouteCost { method, ..payload }))
+}
+
+async fn reload_rate_limits(pool: &PgPool) -> Result<(), (StatusCode, Json<ErrorResponse>)> {
+    rate_limit::reload_config(pool).await.map_err(|e| {
+        tracing::error!("Failed to reload rate limit configuration: {}", e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse::new("database_error", "Failed to reload rate limit configuration")),
+        )
+    })
+}
+
+/// Recent lockouts from the audit trail, newest first.
+pub async fn list_lockouts(
+    State(pool): State<PgPool>,
+    Query(query): Query<LockoutQuery>,
+) -> Result<Json<Vec<AuthLockout>>, (StatusCode, Json<ErrorResponse>)> {
+    let lockouts = sqlx::query_as::<_, AuthLockout>(
+        r#"
+        SELECT id, ip_address, reason, attempts, locked_until, created_at FROM auth_lockouts
+        WHERE ($1::text IS NULL OR ip_address = $1) AND (NOT $2 OR locked_until > NOW())
+        ORDER BY created_at DESC
+        LIMIT 100
+        "#,
+    )
+    .bind(query.ip)
+    .bind(query.active)
+    .fetch_all(&pool)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to list lockouts: {}", e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse::new("database_error", "Failed to list lockouts")),
+        )
+    })?;
+    Ok(Json(lockouts))
+}
+
+/// Lift a lockout before it expires.
+pub async fn clear_lockout(Path(ip): Path<String>) -> Result<StatusCode, (StatusCode, Json<ErrorResponse>)> {
+    let ip: std::net::IpAddr = ip.parse().map_err(|_| {
+        (
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse::new("validation_error", "Invalid IP address")),
+        )
+    })?;
+    let cleared = lockout::clear(ip).await.map_err(|e| {
+        tracing::error!("Failed to clear lockout of {}: {}", ip, e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse::new("database_error", "Failed to clear lockout")),
+        )
+    })?;
+    if cleared {
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Err((
+            StatusCode::NOT_FOUND,
+            Json(ErrorResponse::new("not_found", "Address is not locked out")),
+        ))
+    }
+}
+
+// ============================
+// Event Handlers
+// ============================
+
+/// Page through the caller's event log, oldest first, for clients that poll instead of (or as well
+/// as) receiving webhooks.
+pub async fn list_events(
+    State(pool): State<PgPool>,
+    Extension(auth): Extension<AuthContext>,
+    Query(query): Query<EventQuery>,
+) -> Result<Json<EventPage>, (StatusCode, Json<ErrorResponse>)> {
+    let types = parse_types_filter(query.types.as_deref())?;
+    validate_cursor(query.starting_after)?;
+
+    let page = events::list(
+        &pool,
+        auth.account_id,
+        query.starting_after,
+        types.as_deref(),
+        query.limit.unwrap_or(50).clamp(1, 100),
+    )
+    .await
+    .map_err(events_error)?;
+    Ok(Json(page))
+}
+
+/// Push the caller's events as Server-Sent Events as they are committed. Resumes after
+/// `Last-Event-ID` (sent by `EventSource` on reconnect) or `starting_after`; otherwise only new
+/// events are sent.
+pub async fn stream_events(
+    State(pool): State<PgPool>,
+    Extension(auth): Extension<AuthContext>,
+    headers: HeaderMap,
+    Query(query): Query<StreamQuery>,
+) -> Result<Sse<impl Stream<Item = Result<sse::Event, Infallible>>>, (StatusCode, Json<ErrorResponse>)> {
+    let last_event_id = match headers.get("last-event-id") {
+        Some(value) => Some(value.to_str().ok().and_then(|v| v.trim().parse().ok()).ok_or_else(|| {
+            (
+                StatusCode::BAD_REQUEST,
+                Json(ErrorResponse::new("validation_error", "Last-Event-ID must be an event sequence")),
+            )
+        })?),
+        None => None,
+    };
+    let feed = open_feed(pool, auth.account_id, last_event_id.or(query.starting_after), query.types.as_deref()).await?;
+    Ok(Sse::new(stream::into_sse(feed)).keep_alive(KeepAlive::default()))
+}
+
+/// WebSocket equivalent of `stream_events`: one JSON text message per event.
+pub async fn stream_events_ws(
+    State(pool): State<PgPool>,
+    Extension(auth): Extension<AuthContext>,
+    Query(query): Query<StreamQuery>,
+    ws: WebSocketUpgrade,
+) -> Result<Response, (StatusCode, Json<ErrorResponse>)> {
+    let feed = open_feed(pool, auth.account_id, query.starting_after, query.types.as_deref()).await?;
+    Ok(ws.on_upgrade(move |socket| stream::serve_socket(socket, feed)))
+}
+
+async fn open_feed(
+    pool: PgPool,
+    account_id: Uuid,
+    starting_after: Option<i64>,
+    types: Option<&str>,
+) -> Result<stream::Feed, (StatusCode, Json<ErrorResponse>)> {
+    let types = parse_types_filter(types)?;
+    validate_cursor(starting_after)?;
+    stream::Feed::open(pool, account_id, starting_after, types).await.map_err(events_error)
+}
+
+/// Comma-separated event types; empty means no filter.
+fn parse_types_filter(types: Option<&str>) -> Result<Option<Vec<String>>, (StatusCode, Json<ErrorResponse>)> {
+    let types: Vec<String> =
+        types.unwrap_or_default().split(',').map(str::trim).filter(|t| !t.is_empty()).map(str::to_string).collect();
+    if types.is_empty() {
+        return Ok(None);
+    }
+    validate_event_types(&types)?;
+    Ok(Some(types))
+}
+
+fn validate_cursor(starting_after: Option<i64>) -> Result<(), (StatusCode, Json<ErrorResponse>)> {
+    if starting_after.is_some_and(|cursor| cursor < 0) {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse::new("validation_error", "starting_after must not be negative")),
+        ));
+    }
+    Ok(())
+}
+
+fn events_error(e: events::ListError) -> (StatusCode, Json<ErrorResponse>) {
+    let (status, error) = e.into_error();
+    (status, Json(error))
+}
+
+// ============================
+// Webhook Handlers
+// ============================
+
+/// Columns selected into `Webhook`. The secret is only readable by the dispatcher, so responses carry
+/// a mask; create and rotate replace it with the new plaintext secret.
+const WEBHOOK_COLUMNS: &str = "id, account_id, url, '********' AS secret, description, event_types, enabled, \
+    disabled_reason, disabled_at, consecutive_failures, failing_since, retry_max_attempts, retry_base_delay_secs, \
+    retry_max_delay_secs, retry_deadline_secs, ordered, batch_size, payload_format, legacy_signature, \
+    (SELECT jsonb_object_agg(name, '********') FROM unnest(custom_header_names) AS name) AS headers, \
+    CASE WHEN basic_auth_sealed IS NOT NULL \
+        THEN jsonb_build_object('username', basic_auth_username, 'password', '********') END AS basic_auth, \
+    previous_secret_expires_at, created_at, updated_at";
+
+/// Overlap allowed when rotating a webhook secret.
+const MAX_SECRET_OVERLAP_SECS: i64 = 7 * 24 * 60 * 60;
+
+/// Custom headers per webhook.
+const MAX_CUSTOM_HEADERS: usize = 20;
+
+/// Headers the service sets itself, which custom headers may not replace.
+const RESERVED_HEADERS: &[&str] = &["host", "content-type", "content-length", "transfer-encoding", "connection", "user-agent"];
+
+/// Custom headers sealed as one JSON object, and their names for display.
+fn seal_custom_headers(headers: &HashMap<String, String>) -> (String, Vec<String>) {
+    let headers: HashMap<String, &str> =
+        headers.iter().map(|(name, value)| (name.to_ascii_lowercase(), value.as_str())).collect();
+    let mut names: Vec<String> = headers.keys().cloned().collect();
+    names.sort();
+    let json = serde_json::to_string(&headers).expect("string map serializes");
+    (secrets::seal(&json), names)
+}
+
+fn seal_basic_auth(auth: &BasicAuth) -> String {
+    secrets::seal(&serde_json::to_string(auth).expect("basic auth serializes"))
+}
+
+/// Random secret for HMAC signing of webhook deliveries.
+fn generate_webhook_secret() -> String {
+    use rand::Rng;
+    rand::thread_rng()
+        .sample_iter(&rand::distributions::Alphanumeric)
+        .take(32)
+        .map(char::from)
+        .collect()
+}
+
+pub async fn create_webhook(
+    State(pool): State<PgPool>,
+    Extension(auth): Extension<AuthContext>,
+    Json(payload): Json<CreateWebhookRequest>,
+) -> Result<Json<Webhook>, (StatusCode, Json<ErrorResponse>)> {
+    // A webhook receives its account's events and secret, so only the account itself may register one
+    if payload.account_id != auth.account_id {
+        return Err((
+            StatusCode::FORBIDDEN,
+            Json(ErrorResponse::new("forbidden", "Webhooks can only be registered for the caller's account")),
+        ));
+    }
+    validate_webhook_url(&payload.url)?;
+    if let Some(event_types) = &payload.event_types {
+        validate_event_types(event_types)?;
+    }
+    let retry_policy = payload.retry_policy.unwrap_or_default();
+    validate_retry_policy(&retry_policy)?;
+    let payload_format = payload.payload_format.as_deref().unwrap_or(webhooks::format::LEGACY);
+    validate_delivery_format(payload_format, payload.batch_size)?;
+    validate_target_headers(payload.headers.as_ref(), payload.basic_auth.as_ref())?;
+    let custom_headers = payload.headers.as_ref().map(seal_custom_headers);
+
+    let secret = generate_webhook_secret();
+    let mut webhook = sqlx::query_as::<_, Webhook>(&format!(
+        r#"
+        INSERT INTO webhooks (account_id, url, secret_sealed, event_types, description,
+                              retry_max_attempts, retry_base_delay_secs, retry_max_delay_secs, retry_deadline_secs,
+                              ordered, batch_size, payload_format, custom_headers_sealed, custom_header_names,
+                              basic_auth_sealed, basic_auth_username, legacy_signature)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17)
+        RETURNING {WEBHOOK_COLUMNS}
+        "#
+    ))
+    .bind(payload.account_id)
+    .bind(payload.url)
+    .bind(secrets::seal(&secret))
+    .bind(payload.event_types)
+    .bind(payload.description)
+    .bind(retry_policy.max_attempts)
+    .bind(retry_policy.base_delay_secs)
+    .bind(retry_policy.max_delay_secs)
+    .bind(retry_policy.deadline_secs)
+    .bind(payload.ordered)
+    .bind(payload.batch_size)
+    .bind(payload_format)
+    .bind(custom_headers.as_ref().map(|(sealed, _)| sealed))
+    .bind(custom_headers.as_ref().map(|(_, names)| names))
+    .bind(payload.basic_auth.as_ref().map(seal_basic_auth))
+    .bind(payload.basic_auth.as_ref().map(|auth| &auth.username))
+    .bind(payload.legacy_signature)
+    .fetch_one(&pool)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to create webhook: {}", e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse::new("database_error", "Failed to create webhook")),
+        )
+    })?;
+    // The only time the secret is shown, apart from rotation
+    webhook.secret = secret;
+    Ok(Json(webhook))
+}
+
+pub async fn list_webhooks(
+    State(pool): State<PgPool>,
+    Extension(auth): Extension<AuthContext>,
+) -> Result<Json<Vec<Webhook>>, (StatusCode, Json<ErrorResponse>)> {
+    let webhooks = sqlx::query_as::<_, Webhook>(&format!(
+        "SELECT {WEBHOOK_COLUMNS} FROM webhooks WHERE account_id = $1 ORDER BY created_at DESC"
+    ))
+    .bind(auth.account_id)
+    .fetch_all(&pool)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to fetch webhooks: {}", e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse::new("database_error", "Failed to fetch webhooks")),
+        )
+    })?;
+    Ok(Json(webhooks))
+}
+
+pub async fn get_webhook(
+    State(pool): State<PgPool>,
+    Extension(auth): Extension<AuthContext>,
+    Path(webhook_id): Path<Uuid>,
+) -> Result<Json<Webhook>, (StatusCode, Json<ErrorResponse>)> {
+    find_owned_webhook(&pool, webhook_id, auth.account_id).await.map(Json)
+}
+
+/// Change a webhook's URL, description or subscription, or disable / re-enable it.
+pub async fn update_webhook(
+    State(pool): State<PgPool>,
+    Extension(auth): Extension<AuthContext>,
+    Path(webhook_id): Path<Uuid>,
+    Json(payload): Json<UpdateWebhookRequest>,
+) -> Result<Json<Webhook>, (StatusCode, Json<ErrorResponse>)> {
+    if let Some(url) = &payload.url {
+        validate_webhook_url(url)?;
+    }
+    if let Some(Some(event_types)) = &payload.event_types {
+        validate_event_types(event_types)?;
+    }
+    if let Some(Some(retry_policy)) = &payload.retry_policy {
+        validate_retry_policy(retry_policy)?;
+    }
+    if let Some(format) = &payload.payload_format {
+        validate_delivery_format(format, None)?;
+    }
+    if let Some(batch_size) = payload.batch_size {
+        validate_delivery_format(webhooks::format::LEGACY, batch_size)?;
+    }
+    validate_target_headers(payload.headers.as_ref().and_then(Option::as_ref), payload.basic_auth.as_ref().and_then(Option::as_ref))?;
+    let custom_headers = payload.headers.as_ref().and_then(Option::as_ref).map(seal_custom_headers);
+    let basic_auth = payload.basic_auth.as_ref().and_then(Option::as_ref);
+
+    let db_error = |e: sqlx::Error| {
+        tracing::error!("Failed to update webhook: {}", e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse::new("database_error", "Failed to update webhook")),
+        )
+    };
+    let mut tx = pool.begin().await.map_err(db_error)?;
+
+    let retry_policy = payload.retry_policy.clone().flatten().unwrap_or_default();
+    // Re-enabling starts the failure tracking from scratch
+    let reenable = payload.enabled == Some(true);
+    let updated = sqlx::query(
+        r#"
+        UPDATE webhooks
+        SET url = COALESCE($3, url),
+            description = CASE WHEN $4 THEN $5 ELSE description END,
+            event_types = CASE WHEN $6 THEN $7 ELSE event_types END,
+            enabled = enabled OR $8,
+            disabled_reason = CASE WHEN $8 THEN NULL ELSE disabled_reason END,
+            disabled_at = CASE WHEN $8 THEN NULL ELSE disabled_at END,
+            consecutive_failures = CASE WHEN $8 THEN 0 ELSE consecutive_failures END,
+            failing_since = CASE WHEN $8 THEN NULL ELSE failing_since END,
+            retry_max_attempts = CASE WHEN $9 THEN $10 ELSE retry_max_attempts END,
+            retry_base_delay_secs = CASE WHEN $9 THEN $11 ELSE retry_base_delay_secs END,
+            retry_max_delay_secs = CASE WHEN $9 THEN $12 ELSE retry_max_delay_secs END,
+            retry_deadline_secs = CASE WHEN $9 THEN $13 ELSE retry_deadline_secs END,
+            ordered = COALESCE($14, ordered),
+            batch_size = CASE WHEN $15 THEN $16 ELSE batch_size END,
+            payload_format = COALESCE($17, payload_format),
+            custom_headers_sealed = CASE WHEN $18 THEN $19 ELSE custom_headers_sealed END,
+            custom_header_names = CASE WHEN $18 THEN $20 ELSE custom_header_names END,
+            basic_auth_sealed = CASE WHEN $21 THEN $22 ELSE basic_auth_sealed END,
+            basic_auth_username = CASE WHEN $21 THEN $23 ELSE basic_auth_username END,
+            legacy_signature = COALESCE($24, legacy_signature),
+            updated_at = NOW()
+        WHERE id = $1 AND account_id = $2
+        "#,
+    )
+    .bind(webhook_id)
+    .bind(auth.account_id)
+    .bind(payload.url)
+    .bind(payload.description.is_some())
+    .bind(payload.description.flatten())
+    .bind(payload.event_types.is_some())
+    .bind(payload.event_types.flatten())
+    .bind(reenable)
+    .bind(payload.retry_policy.is_some())
+    .bind(retry_policy.max_attempts)
+    .bind(retry_policy.base_delay_secs)
+    .bind(retry_policy.max_delay_secs)
+    .bind(retry_policy.deadline_secs)
+    .bind(payload.ordered)
+    .bind(payload.batch_size.is_some())
+    .bind(payload.batch_size.flatten())
+    .bind(payload.payload_format)
+    .bind(payload.headers.is_some())
+    .bind(custom_headers.as_ref().map(|(sealed, _)| sealed))
+    .bind(custom_headers.as_ref().map(|(_, names)| names))
+    .bind(payload.basic_auth.is_some())
+    .bind(basic_auth.map(seal_basic_auth))
+    .bind(basic_auth.map(|auth| &auth.username))
+    .bind(payload.legacy_signature)
+    .execute(&mut *tx)
+    .await
+    .map_err(db_error)?;
+    if updated.rows_affected() == 0 {
+        return Err((
+            StatusCode::NOT_FOUND,
+            Json(ErrorResponse::new("not_found", "Webhook not found")),
+        ));
+    }
+
+    if payload.enabled == Some(false) {
+        webhooks::disable(&mut tx, webhook_id, "manual").await.map_err(db_error)?;
+    }
+
+    let webhook = sqlx::query_as::<_, Webhook>(&format!("SELECT {WEBHOOK_COLUMNS} FROM webhooks WHERE id = $1"))
+        .bind(webhook_id)
+        .fetch_one(&mut *tx)
+        .await
+        .map_err(db_error)?;
+    // Settings that conflict may come from different requests; dropping `tx` rolls back
+    validate_delivery_format(&webhook.payload_format, webhook.batch_size)?;
+    let sets_authorization = webhook.headers.as_ref().is_some_and(|headers| headers.get("authorization").is_some());
+    if sets_authorization && webhook.basic_auth.is_some() {
+        return Err(authorization_conflict());
+    }
+    tx.commit().await.map_err(db_error)?;
+    Ok(Json(webhook))
+}
+
+/// Delete a webhook together with its events and delivery log.
+pub async fn delete_webhook(
+    State(pool): State<PgPool>,
+    Extension(auth): Extension<AuthContext>,
+    Path(webhook_id): Path<Uuid>,
+) -> Result<StatusCode, (StatusCode, Json<ErrorResponse>)> {
+    let deleted = sqlx::query("DELETE FROM webhooks WHERE id = $1 AND account_id = $2")
+        .bind(webhook_id)
+        .bind(auth.account_id)
+        .execute(&pool)
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to delete webhook: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse::new("database_error", "Failed to delete webhook")),
+            )
+        })?;
+    if deleted.rows_affected() == 0 {
+        return Err((
+            StatusCode::NOT_FOUND,
+            Json(ErrorResponse::new("not_found", "Webhook not found")),
+        ));
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Send a signed `webhook.ping` to the endpoint right away and report how it responded.
+/// Nothing is queued or recorded, and the failure counters are left alone.
+pub async fn test_webhook(
+    State(pool): State<PgPool>,
+    Extension(auth): Extension<AuthContext>,
+    Path(webhook_id): Path<Uuid>,
+) -> Result<Json<WebhookTestResult>, (StatusCode, Json<ErrorResponse>)> {
+    let row = sqlx::query(
+        r#"
+        SELECT id, account_id, url, description, event_types, enabled, disabled_reason, disabled_at,
+               consecutive_failures, failing_since, secret_sealed, batch_size, payload_format, legacy_signature,
+               custom_headers_sealed, basic_auth_sealed,
+               CASE WHEN previous_secret_expires_at > NOW() THEN previous_secret_sealed END AS previous_secret_sealed
+        FROM webhooks WHERE id = $1 AND account_id = $2
+        "#,
+    )
+    .bind(webhook_id)
+    .bind(auth.account_id)
+    .fetch_optional(&pool)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to fetch webhook: {}", e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse::new("database_error", "Failed to fetch webhook")),
+        )
+    })?
+    .ok_or((
+        StatusCode::NOT_FOUND,
+        Json(ErrorResponse::new("not_found", "Webhook not found")),
+    ))?;
+
+    let internal = |message: &str| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse::new("internal_error", message)),
+        )
+    };
+    let webhook = WebhookEventData::from_row(&row).map_err(|e| {
+        tracing::error!("Failed to read webhook: {}", e);
+        internal("Failed to fetch webhook")
+    })?;
+    let secrets = webhooks::delivery::unseal_secrets(row.get("secret_sealed"), row.get("previous_secret_sealed"))
+        .and_then(|secrets| {
+            let headers =
+                webhooks::delivery::unseal_target_headers(row.get("custom_headers_sealed"), row.get("basic_auth_sealed"))?;
+            Ok((secrets, headers))
+        });
+    let (secrets, target_headers) = secrets.map_err(|e| {
+        tracing::error!("Failed to decrypt secrets of webhook {}: {:#}", webhook_id, e);
+        internal("Failed to read webhook secret")
+    })?;
+
+    let url = webhook.url.clone();
+    let payload = WebhookPayload {
+        event_type: webhooks::WEBHOOK_PING.to_string(),
+        data: EventData::Webhook(webhook),
+        timestamp: chrono::Utc::now(),
+    };
+    // Encoded like a real delivery, including as a batch of one in batch mode
+    let event_id = Uuid::new_v4();
+    let format: String = row.get("payload_format");
+    let batched = row.get::<Option<i32>, _>("batch_size").is_some() && format != webhooks::format::CLOUDEVENTS_BINARY;
+    let request = serde_json::to_value(&payload)
+        .and_then(|payload| webhooks::format::encode(&format, &[webhooks::format::Event { id: event_id, payload: &payload }], batched))
+        .map_err(|e| {
+            tracing::error!("Failed to serialize ping: {}", e);
+            internal("Failed to build ping")
+        })?;
+
+    let result = webhooks::delivery::send(&url, event_id, request, &target_headers, &secrets, row.get("legacy_signature")).await;
+    Ok(Json(WebhookTestResult {
+        event_id,
+        success: result.outcome.succeeded(),
+        response_status: result.outcome.response_status,
+        response_body: result.outcome.response_body,
+        latency_ms: result.latency.as_millis(),
+        error_kind: result.outcome.error_kind,
+        error_message: result.outcome.error_message,
+    }))
+}
+
+/// Replace a webhook's signing secret. The old secret keeps signing alongside the new one for the
+/// overlap window, so receivers can switch over without rejecting deliveries.
+pub async fn rotate_webhook_secret(
+    State(pool): State<PgPool>,
+    Extension(auth): Extension<AuthContext>,
+    Path(webhook_id): Path<Uuid>,
+    payload: Option<Json<RotateWebhookSecretRequest>>,
+) -> Result<Json<Webhook>, (StatusCode, Json<ErrorResponse>)> {
+    let overlap_secs = match payload.and_then(|Json(p)| p.overlap_secs) {
+        Some(secs) => secs,
+        None => std::env::var("WEBHOOK_SECRET_OVERLAP_SECS").ok().and_then(|v| v.parse().ok()).unwrap_or(86400),
+    };
+    if !(0..=MAX_SECRET_OVERLAP_SECS).contains(&overlap_secs) {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse::new(
+                "validation_error",
+                &format!("overlap_secs must be between 0 and {}", MAX_SECRET_OVERLAP_SECS),
+            )),
+        ));
+    }
+
+    // Only the secret being replaced is kept; one from an earlier rotation stops signing here
+    let secret = generate_webhook_secret();
+    let mut webhook = sqlx::query_as::<_, Webhook>(&format!(
+        r#"
+        UPDATE webhooks
+        SET previous_secret_sealed = secret_sealed, secret_sealed = $3,
+            previous_secret_expires_at = NOW() + make_interval(secs => $4)
+        WHERE id = $1 AND account_id = $2
+        RETURNING {WEBHOOK_COLUMNS}
+        "#
+    ))
+    .bind(webhook_id)
+    .bind(auth.account_id)
+    .bind(secrets::seal(&secret))
+    .bind(overlap_secs as f64)
+    .fetch_optional(&pool)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to rotate webhook secret: {}", e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse::new("database_error", "Failed to rotate webhook secret")),
+        )
+    })?
+    .ok_or((
+        StatusCode::NOT_FOUND,
+        Json(ErrorResponse::new("not_found", "Webhook not found")),
+    ))?;
+    webhook.secret = secret;
+    Ok(Json(webhook))
+}
+
+/// Catalog of event types webhooks can subscribe to.
+pub async fn list_event_types() -> Json<&'static [EventTypeInfo]> {
+    Json(webhooks::EVENT_CATALOG)
+}
+
+/// Events queued for one of the caller's webhooks, newest first, optionally filtered by status.
+pub async fn list_webhook_events(
+    State(pool): State<PgPool>,
+    Extension(auth): Extension<AuthContext>,
+    Path(webhook_id): Path<Uuid>,
+    Query(query): Query<WebhookEventQuery>,
+) -> Result<Json<Vec<WebhookEvent>>, (StatusCode, Json<ErrorResponse>)> {
+    validate_event_status(query.status.as_deref())?;
+    find_owned_webhook(&pool, webhook_id, auth.account_id).await?;
+
+    let events = sqlx::query_as::<_, WebhookEvent>(
+        r#"
+        SELECT id, webhook_id, txn_id, event_type, sequence, payload, status, delivered, retry_count, last_attempt,
+               next_attempt_at, created_at
+        FROM webhook_events
+        WHERE webhook_id = $1 AND ($2::text IS NULL OR status = $2)
+        ORDER BY created_at DESC
+        LIMIT $3
+        "#,
+    )
+    .bind(webhook_id)
+    .bind(query.status)
+    .bind(query.limit.unwrap_or(50).clamp(1, 100))
+    .fetch_all(&pool)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to fetch webhook events: {}", e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse::new("database_error", "Failed to fetch webhook events")),
+        )
+    })?;
+    Ok(Json(events))
+}
+
+/// Requeue a single delivered or dead-lettered event.
+pub async fn replay_webhook_event(
+    State(pool): State<PgPool>,
+    Extension(auth): Extension<AuthContext>,
+    Path(event_id): Path<Uuid>,
+) -> Result<Json<ReplayWebhookEventsResponse>, (StatusCode, Json<ErrorResponse>)> {
+    let event_ids = webhooks::replay(&pool, auth.account_id, Some(event_id), &ReplayWebhookEventsRequest::default())
+        .await
+        .map_err(replay_error)?;
+    if event_ids.is_empty() {
+        return Err((
+            StatusCode::NOT_FOUND,
+            Json(ErrorResponse::new("not_found", "Webhook event not found or already pending")),
+        ));
+    }
+    Ok(Json(ReplayWebhookEventsResponse { replayed: event_ids.len(), event_ids }))
+}
+
+/// Requeue every event matching a filter (dead-lettered events by default).
+pub async fn replay_webhook_events(
+    State(pool): State<PgPool>,
+    Extension(auth): Extension<AuthContext>,
+    Json(mut filter): Json<ReplayWebhookEventsRequest>,
+) -> Result<Json<ReplayWebhookEventsResponse>, (StatusCode, Json<ErrorResponse>)> {
+    let status = filter.status.get_or_insert_with(|| "failed".to_string());
+    validate_event_status(Some(status))?;
+
+    let event_ids = webhooks::replay(&pool, auth.account_id, None, &filter).await.map_err(replay_error)?;
+    Ok(Json(ReplayWebhookEventsResponse { replayed: event_ids.len(), event_ids }))
+}
+
+/// Regenerate transaction events from a past time range for one webhook, e.g. to give a new
+/// webhook the history from before it was registered. The backfill runs in the background;
+/// the response is the job to poll for progress.
+pub async fn replay_webhook_history(
+    State(pool): State<PgPool>,
+    Extension(auth): Extension<AuthContext>,
+    Path(webhook_id): Path<Uuid>,
+    Json(request): Json<WebhookReplayRequest>,
+) -> Result<(StatusCode, Json<WebhookReplayJob>), (StatusCode, Json<ErrorResponse>)> {
+    let webhook = find_owned_webhook(&pool, webhook_id, auth.account_id).await?;
+    if !webhook.enabled {
+        return Err((
+            StatusCode::CONFLICT,
+            Json(ErrorResponse::new("webhook_disabled", "Enable the webhook before replaying history")),
+        ));
+    }
+
+    // Anything later is delivered live; replaying it too would send it twice
+    let now = chrono::Utc::now();
+    let to = request.to.map_or(now, |to| to.min(now));
+    if request.from >= to {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse::new("validation_error", "from must be before to and in the past")),
+        ));
+    }
+
+    let requested = match request.event_types {
+        Some(event_types) => {
+            if let Some(other) = event_types.iter().find(|t| !webhooks::backfill::REPLAYABLE_EVENT_TYPES.contains(&t.as_str())) {
+                return Err((
+                    StatusCode::BAD_REQUEST,
+                    Json(ErrorResponse::new(
+                        "validation_error",
+                        &format!("'{}' cannot be replayed; use transaction.created or transaction.reversed", other),
+                    )),
+                ));
+            }
+            event_types
+        }
+        None => webhooks::backfill::REPLAYABLE_EVENT_TYPES.iter().map(|t| t.to_string()).collect(),
+    };
+    let event_types: Vec<String> = requested
+        .into_iter()
+        .filter(|t| webhook.event_types.as_ref().is_none_or(|subscribed| subscribed.contains(t)))
+        .collect();
+    if event_types.is_empty() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse::new("validation_error", "The webhook is not subscribed to any of these event types")),
+        ));
+    }
+
+    let job = webhooks::backfill::start(&pool, webhook_id, auth.account_id, request.from, to, &event_types)
+        .await
+        .map_err(|e| {
+            if is_unique_violation(&e) {
+                return (
+                    StatusCode::CONFLICT,
+                    Json(ErrorResponse::new("replay_in_progress", "A replay is already running for this webhook")),
+                );
+            }
+            tracing::error!("Failed to start webhook replay: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse::new("database_error", "Failed to start webhook replay")),
+            )
+        })?;
+    Ok((StatusCode::ACCEPTED, Json(job)))
+}
+
+/// Progress of a history replay.
+pub async fn get_webhook_replay(
+    State(pool): State<PgPool>,
+    Extension(auth): Extension<AuthContext>,
+    Path((webhook_id, job_id)): Path<(Uuid, Uuid)>,
+) -> Result<Json<WebhookReplayJob>, (StatusCode, Json<ErrorResponse>)> {
+    find_owned_webhook(&pool, webhook_id, auth.account_id).await?;
+    let progress = async { webhooks::backfill::progress(&mut *pool.acquire().await?, webhook_id, job_id).await };
+    progress
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to fetch webhook replay: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse::new("database_error", "Failed to fetch webhook replay")),
+            )
+        })?
+        .map(Json)
+        .ok_or((
+            StatusCode::NOT_FOUND,
+            Json(ErrorResponse::new("not_found", "Replay not found")),
+        ))
+}
+
+/// Delivery attempts of one of the caller's webhook events, oldest first.
+pub async fn list_webhook_event_attempts(
+    State(pool): State<PgPool>,
+    Extension(auth): Extension<AuthContext>,
+    Path(event_id): Path<Uuid>,
+) -> Result<Json<Vec<WebhookDeliveryAttempt>>, (StatusCode, Json<ErrorResponse>)> {
+    let fetch_error = |e: sqlx::Error| {
+        tracing::error!("Failed to fetch webhook delivery attempts: {}", e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse::new("database_error", "Failed to fetch delivery attempts")),
+        )
+    };
+
+    let owned: Option<Uuid> = sqlx::query_scalar(
+        "SELECT e.id FROM webhook_events e JOIN webhooks w ON w.id = e.webhook_id WHERE e.id = $1 AND w.account_id = $2",
+    )
+    .bind(event_id)
+    .bind(auth.account_id)
+    .fetch_optional(&pool)
+    .await
+    .map_err(fetch_error)?;
+    if owned.is_none() {
+        return Err((
+            StatusCode::NOT_FOUND,
+            Json(ErrorResponse::new("not_found", "Webhook event not found")),
+        ));
+    }
+
+    let attempts = sqlx::query_as::<_, WebhookDeliveryAttempt>(
+        r#"
+        SELECT id, event_id, attempt, request_headers, response_status, response_body, latency_ms, error_kind,
+               error_message, created_at
+        FROM webhook_delivery_attempts
+        WHERE event_id = $1
+        ORDER BY created_at
+        "#,
+    )
+    .bind(event_id)
+    .fetch_all(&pool)
+    .await
+    .map_err(fetch_error)?;
+    Ok(Json(attempts))
+}
+
+fn replay_error(e: sqlx::Error) -> (StatusCode, Json<ErrorResponse>) {
+    tracing::error!("Failed to replay webhook events: {}", e);
+    (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        Json(ErrorResponse::new("database_error", "Failed to replay webhook events")),
+    )
+}
+
+fn validate_event_status(status: Option<&str>) -> Result<(), (StatusCode, Json<ErrorResponse>)> {
+    match status {
+        Some(status) if !webhooks::EVENT_STATUSES.contains(&status) => Err((
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse::new("validation_error", "status must be one of pending, delivered, failed")),
+        )),
+        _ => Ok(()),
+    }
+}
+
+fn validate_webhook_url(url: &str) -> Result<(), (StatusCode, Json<ErrorResponse>)> {
+    webhooks::ssrf::validate(url)
+        .map(|_| ())
+        .map_err(|blocked| (StatusCode::BAD_REQUEST, Json(ErrorResponse::new("invalid_url", &blocked.0))))
+}
+
+fn validate_event_types(event_types: &[String]) -> Result<(), (StatusCode, Json<ErrorResponse>)> {
+    if event_types.is_empty() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse::new("validation_error", "event_types must not be empty; omit it to receive every event")),
+        ));
+    }
+    if let Some(unknown) = event_types.iter().find(|t| !webhooks::is_known_event_type(t)) {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse::new("validation_error", &format!("Unknown event type '{}'", unknown))),
+        ));
+    }
+    Ok(())
+}
+
+/// Longest retry deadline a webhook may ask for.
+const MAX_RETRY_DEADLINE_SECS: i32 = 30 * 24 * 60 * 60;
+
+fn validate_retry_policy(policy: &RetryPolicyOverride) -> Result<(), (StatusCode, Json<ErrorResponse>)> {
+    let invalid = |message: &str| {
+        Err((StatusCode::BAD_REQUEST, Json(ErrorResponse::new("validation_error", message))))
+    };
+    if policy.max_attempts.is_some_and(|n| !(1..=100).contains(&n)) {
+        return invalid("retry_policy.max_attempts must be between 1 and 100");
+    }
+    if policy.base_delay_secs.is_some_and(|secs| secs < 1) || policy.max_delay_secs.is_some_and(|secs| secs < 1) {
+        return invalid("retry_policy delays must be at least 1 second");
+    }
+    if let (Some(base), Some(max)) = (policy.base_delay_secs, policy.max_delay_secs) {
+        if base > max {
+            return invalid("retry_policy.base_delay_secs must not exceed max_delay_secs");
+        }
+    }
+    if policy.deadline_secs.is_some_and(|secs| !(1..=MAX_RETRY_DEADLINE_SECS).contains(&secs)) {
+        return invalid(&format!("retry_policy.deadline_secs must be between 1 and {}", MAX_RETRY_DEADLINE_SECS));
+    }
+    Ok(())
+}
+
+fn authorization_conflict() -> (StatusCode, Json<ErrorResponse>) {
+    (
+        StatusCode::BAD_REQUEST,
+        Json(ErrorResponse::new(
+            "validation_error",
+            "basic_auth and a custom Authorization header cannot be used together",
+        )),
+    )
+}
+
+fn validate_target_headers(
+    headers: Option<&HashMap<String, String>>,
+    basic_auth: Option<&BasicAuth>,
+) -> Result<(), (StatusCode, Json<ErrorResponse>)> {
+    let invalid = |message: String| Err((StatusCode::BAD_REQUEST, Json(ErrorResponse::new("validation_error", &message))));
+    if let Some(auth) = basic_auth {
+        if auth.username.is_empty() || auth.username.contains(':') {
+            return invalid("basic_auth.username must be non-empty and must not contain ':'".to_string());
+        }
+    }
+    let Some(headers) = headers else {
+        return Ok(());
+    };
+    if headers.len() > MAX_CUSTOM_HEADERS {
+        return invalid(format!("at most {} custom headers are allowed", MAX_CUSTOM_HEADERS));
+    }
+    for (name, value) in headers {
+        let Ok(parsed) = HeaderName::from_bytes(name.as_bytes()) else {
+            return invalid(format!("'{}' is not a valid header name", name));
+        };
+        let lower = parsed.as_str();
+        if RESERVED_HEADERS.contains(&lower) || lower.starts_with("x-webhook-") || lower.starts_with("ce-") {
+            return invalid(format!("header '{}' is set by the service and cannot be overridden", name));
+        }
+        if lower == "authorization" && basic_auth.is_some() {
+            return Err(authorization_conflict());
+        }
+        if value.len() > 2048 || HeaderValue::from_str(value).is_err() {
+            return invalid(format!("value of header '{}' is invalid or longer than 2048 bytes", name));
+        }
+    }
+    Ok(())
+}
+
+fn validate_delivery_format(format: &str, batch_size: Option<i32>) -> Result<(), (StatusCode, Json<ErrorResponse>)> {
+    let invalid = |message: String| Err((StatusCode::BAD_REQUEST, Json(ErrorResponse::new("validation_error", &message))));
+    if !webhooks::format::PAYLOAD_FORMATS.contains(&format) {
+        return invalid(format!(
+            "payload_format must be one of: {}",
+            webhooks::format::PAYLOAD_FORMATS.join(", ")
+        ));
+    }
+    match batch_size {
+        Some(n) if !(1..=webhooks::MAX_BATCH_SIZE).contains(&n) => {
+            invalid(format!("batch_size must be between 1 and {}", webhooks::MAX_BATCH_SIZE))
+        }
+        Some(_) if format == webhooks::format::CLOUDEVENTS_BINARY => {
+            invalid("cloudevents_binary sends one event per request and cannot be combined with batch_size".to_string())
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Look up a webhook belonging to the caller's account; 404 otherwise.
+async fn find_owned_webhook(
+    pool: &PgPool,
+    webhook_id: Uuid,
+    account_id: Uuid,
+) -> Result<Webhook, (StatusCode, Json<ErrorResponse>)> {
+    sqlx::query_as::<_, Webhook>(&format!(
+        "SELECT {WEBHOOK_COLUMNS} FROM webhooks WHERE id = $1 AND account_id = $2"
+    ))
+    .bind(webhook_id)
+    .bind(account_id)
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to fetch webhook: {}", e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse::new("database_error", "Failed to fetch webhook")),
+        )
+    })?
+    .ok_or((
+        StatusCode::NOT_FOUND,
+        Json(ErrorResponse::new("not_found", "Webhook not found")),
+    ))
+}
+
+/// Handler tests against Postgres; see `webhooks::tests` for how to run them.
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::webhooks::tests::{account, queued, webhook};
+
+    fn caller(account_id: Uuid) -> Extension<AuthContext> {
+        Extension(AuthContext { api_key_id: Uuid::new_v4(), account_id, scopes: None })
+    }
+
+    fn transfer(from: Uuid, to: Uuid, amount: i64) -> Json<CreateTransactionRequest> {
+        Json(CreateTransactionRequest {
+            from_account_id: Some(from),
+            to_account_id: Some(to),
+            amount: Decimal::from(amount),
+            txn_type: "transfer".to_string(),
+        })
+    }
+
+    fn status<T>(result: Result<T, (StatusCode, Json<ErrorResponse>)>) -> StatusCode {
+        result.err().map_or(StatusCode::OK, |(status, _)| status)
+    }
+
+    #[sqlx::test]
+    #[ignore]
+    async fn the_recipient_reverses_a_transfer_once(pool: PgPool) {
+        let sender = account(&pool).await;
+        let recipient = account(&pool).await;
+        let sender_hook = webhook(&pool, sender.id, Some(&[webhooks::TRANSACTION_CREATED, webhooks::TRANSACTION_REVERSED])).await;
+        let recipient_hook = webhook(&pool, recipient.id, None).await;
+
+        let Json(original) = create_transaction(State(pool.clone()), transfer(sender.id, recipient.id, 30)).await.unwrap();
+        let reverse = |by: Uuid| reverse_transaction(State(pool.clone()), caller(by), Path(original.id));
+        assert_eq!(status(reverse(sender.id).await), StatusCode::FORBIDDEN);
+        let Json(reversal) = reverse(recipient.id).await.unwrap();
+        assert_eq!(status(reverse(recipient.id).await), StatusCode::CONFLICT);
+
+        assert_eq!((reversal.from_account, reversal.to_account), (Some(recipient.id), Some(sender.id)));
+        let (status, reversed_by): (String, Option<Uuid>) =
+            sqlx::query_as("SELECT status, reversed_by FROM transactions WHERE id = $1")
+                .bind(original.id)
+                .fetch_one(&pool)
+                .await
+                .unwrap();
+        assert_eq!((status.as_str(), reversed_by), ("reversed", Some(reversal.id)));
+        let balance: Decimal = sqlx::query_scalar("SELECT balance FROM accounts WHERE id = $1")
+            .bind(sender.id)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(balance, sender.balance);
+
+        use webhooks::{ACCOUNT_UPDATED as UPDATED, TRANSACTION_CREATED as CREATED, TRANSACTION_REVERSED as REVERSED};
+        assert_eq!(queued(&pool, sender_hook).await, [CREATED, CREATED, REVERSED]);
+        assert_eq!(queued(&pool, recipient_hook).await, [CREATED, UPDATED, CREATED, UPDATED, REVERSED]);
+    }
+
+    #[sqlx::test]
+    #[ignore]
+    async fn only_operators_reverse_debits(pool: PgPool) {
+        let owner = account(&pool).await;
+        let request = Json(CreateTransactionRequest {
+            from_account_id: Some(owner.id),
+            to_account_id: None,
+            amount: Decimal::from(10),
+            txn_type: "debit".to_string(),
+        });
+        let Json(debit) = create_transaction(State(pool.clone()), request).await.unwrap();
+
+        assert_eq!(
+            status(reverse_transaction(State(pool.clone()), caller(owner.id), Path(debit.id)).await),
+            StatusCode::FORBIDDEN
+        );
+        let Json(reversal) = admin_reverse_transaction(State(pool.clone()), Path(debit.id)).await.unwrap();
+        assert_eq!((reversal.txn_type.as_str(), reversal.to_account), ("credit", Some(owner.id)));
+    }
+
+    #[sqlx::test]
+    #[ignore]
+    async fn a_rejected_transfer_is_reported_to_both_parties(pool: PgPool) {
+        let sender = account(&pool).await;
+        let recipient = account(&pool).await;
+        let sender_hook = webhook(&pool, sender.id, None).await;
+        let recipient_hook = webhook(&pool, recipient.id, None).await;
+
+        let result = create_transaction(State(pool.clone()), transfer(sender.id, recipient.id, 1000)).await;
+        assert_eq!(status(result), StatusCode::BAD_REQUEST);
+        assert_eq!(queued(&pool, sender_hook).await, [webhooks::TRANSACTION_FAILED]);
+        assert_eq!(queued(&pool, recipient_hook).await, [webhooks::TRANSACTION_FAILED]);
+
+        // An unknown recipient is skipped rather than failing the event for the sender
+        let result = create_transaction(State(pool.clone()), transfer(sender.id, Uuid::new_v4(), 1000)).await;
+        assert_eq!(status(result), StatusCode::BAD_REQUEST);
+        assert_eq!(queued(&pool, sender_hook).await, [webhooks::TRANSACTION_FAILED, webhooks::TRANSACTION_FAILED]);
+    }
+
+    fn new_webhook(account_id: Uuid) -> Json<CreateWebhookRequest> {
+        Json(serde_json::from_value(serde_json::json!({ "account_id": account_id, "url": "https://example.com/hook" })).unwrap())
+    }
+
+    #[sqlx::test]
+    #[ignore]
+    async fn webhooks_are_registered_only_for_the_callers_account(pool: PgPool) {
+        secrets::init_for_tests();
+        let owner = account(&pool).await;
+        let other = account(&pool).await;
+
+        let result = create_webhook(State(pool.clone()), caller(owner.id), new_webhook(other.id)).await;
+        assert_eq!(status(result), StatusCode::FORBIDDEN);
+        let Json(created) = create_webhook(State(pool.clone()), caller(owner.id), new_webhook(owner.id)).await.unwrap();
+        assert_eq!(created.account_id, owner.id);
+
+        let webhooks: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM webhooks WHERE account_id = $1")
+            .bind(other.id)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(webhooks, 0);
+    }
+
+    #[sqlx::test]
+    #[ignore]
+    async fn a_ping_is_sent_to_disabled_webhooks_and_leaves_no_trace(pool: PgPool) {
+        secrets::init_for_tests();
+        let _ = webhooks::delivery::init();
+        let owner = account(&pool).await;
+        let other = account(&pool).await;
+        let webhook_id = webhook(&pool, owner.id, Some(&[webhooks::TRANSACTION_CREATED])).await;
+        sqlx::query("UPDATE webhooks SET url = 'https://127.0.0.1:9/hook', secret_sealed = $2, enabled = false WHERE id = $1")
+            .bind(webhook_id)
+            .bind(secrets::seal("whsec_test"))
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        let result = test_webhook(State(pool.clone()), caller(other.id), Path(webhook_id)).await;
+        assert_eq!(status(result), StatusCode::NOT_FOUND);
+
+        // Delivery rules still apply, so the loopback target is refused
+        let Json(result) = test_webhook(State(pool.clone()), caller(owner.id), Path(webhook_id)).await.unwrap();
+        assert!(!result.success);
+        if !webhooks::ssrf::dev_mode() {
+            assert_eq!(result.error_kind, Some("blocked"));
+        }
+
+        let stored: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM webhook_events").fetch_one(&pool).await.unwrap();
+        let attempts: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM webhook_delivery_attempts").fetch_one(&pool).await.unwrap();
+        let failures: i32 = sqlx::query_scalar("SELECT consecutive_failures FROM webhooks WHERE id = $1")
+            .bind(webhook_id)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!((stored, attempts, failures), (0, 0, 0));
+    }
+
+    #[test]
+    fn target_headers_cannot_override_the_services_own() {
+        let headers = |name: &str| HashMap::from([(name.to_string(), "value".to_string())]);
+        let basic_auth = |username: &str| BasicAuth { username: username.to_string(), password: "secret".to_string() };
 
-        if attempt < MAX_RETRIES {
-            // Exponential backoff: 1s, 2s, 4s
-            let delay = std::time::Duration::from_secs(2_u64.pow(attempt as u32));
-            tokio::time::sleep(delay).await;
-        } else {
-            tracing::error!("Failed to deliver webhook after {} attempts for event {}", MAX_RETRIES + 1, event.id);
+        assert!(validate_target_headers(Some(&headers("X-Api-Token")), Some(&basic_auth("hooks"))).is_ok());
+        for reserved in ["Content-Type", "Host", "X-Webhook-Signature", "ce-id"] {
+            assert_eq!(status(validate_target_headers(Some(&headers(reserved)), None)), StatusCode::BAD_REQUEST, "{}", reserved);
         }
+        assert!(validate_target_headers(Some(&headers("Authorization")), None).is_ok());
+        assert_eq!(status(validate_target_headers(Some(&headers("Authorization")), Some(&basic_auth("hooks")))), StatusCode::BAD_REQUEST);
+        assert_eq!(status(validate_target_headers(None, Some(&basic_auth("ho:oks")))), StatusCode::BAD_REQUEST);
     }
 }
@@ -0,0 +1,331 @@
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::{DateTime, Utc};
+use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use once_cell::sync::Lazy;
+use ring::rand::SystemRandom;
+use ring::signature::{Ed25519KeyPair, KeyPair};
+use serde::{Deserialize, Serialize};
+use sqlx::{PgPool, Row};
+use std::env;
+use std::sync::RwLock;
+use std::time::Duration;
+use uuid::Uuid;
+
+use crate::secrets;
+
+/// Scopes that can be granted to an access token. Requesting no scope grants all of them.
+pub const SCOPES: &[&str] = &[
+    "accounts:read",
+    "accounts:write",
+    "events:read",
+    "transactions:read",
+    "transactions:write",
+    "webhooks:read",
+    "webhooks:write",
+];
+
+/// Claims carried by access tokens issued from `POST /oauth/token`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Claims {
+    pub iss: String,
+    /// API key (OAuth client) id
+    pub sub: String,
+    pub account_id: Uuid,
+    /// Space-separated list of granted scopes
+    pub scope: String,
+    pub iat: i64,
+    pub exp: i64,
+    pub jti: String,
+}
+
+struct SigningKey {
+    kid: String,
+    encoding: EncodingKey,
+    decoding: DecodingKey,
+    public_key: Vec<u8>,
+    activated_at: DateTime<Utc>,
+    retired_at: Option<DateTime<Utc>>,
+}
+
+impl SigningKey {
+    fn from_pkcs8(
+        kid: String,
+        pkcs8: &[u8],
+        activated_at: DateTime<Utc>,
+        retired_at: Option<DateTime<Utc>>,
+    ) -> anyhow::Result<Self> {
+        let pair = Ed25519KeyPair::from_pkcs8(pkcs8).map_err(|e| anyhow::anyhow!("invalid Ed25519 key {}: {}", kid, e))?;
+        let public_key = pair.public_key().as_ref().to_vec();
+        Ok(Self {
+            kid,
+            encoding: EncodingKey::from_ed_der(pkcs8),
+            decoding: DecodingKey::from_ed_der(&public_key),
+            public_key,
+            activated_at,
+            retired_at,
+        })
+    }
+
+    fn can_sign(&self, now: DateTime<Utc>) -> bool {
+        self.activated_at <= now && self.retired_at.is_none_or(|retired| retired > now)
+    }
+}
+
+// Published keys, refreshed from `jwt_signing_keys` in the background so verification never hits the DB
+static KEYS: Lazy<RwLock<Vec<SigningKey>>> = Lazy::new(|| RwLock::new(Vec::new()));
+
+pub fn token_ttl_secs() -> i64 {
+    env::var("JWT_TTL_SECS").ok().and_then(|v| v.parse().ok()).unwrap_or(300)
+}
+
+fn issuer() -> String {
+    env::var("JWT_ISSUER").unwrap_or_else(|_| "transaction-service".to_string())
+}
+
+fn refresh_interval_secs() -> u64 {
+    env::var("JWT_KEY_REFRESH_SECS").ok().and_then(|v| v.parse().ok()).unwrap_or(60)
+}
+
+/// Seal private keys, load the published keys, and create the first signing key if none exists yet.
+pub async fn init(pool: &PgPool) -> anyhow::Result<()> {
+    seal_stored_keys(pool).await?;
+    let (active,): (i64,) = sqlx::query_as("SELECT COUNT(*) FROM jwt_signing_keys WHERE retired_at IS NULL")
+        .fetch_one(pool)
+        .await?;
+    if active == 0 {
+        let kid = insert_key(pool, Utc::now()).await?;
+        tracing::info!("Created initial JWT signing key {}", kid);
+    }
+    reload(pool).await
+}
+
+/// Periodically reload keys so rotations made by other instances are picked up.
+pub async fn refresh_keys(pool: PgPool) {
+    let interval = Duration::from_secs(refresh_interval_secs());
+    loop {
+        tokio::time::sleep(interval).await;
+        if let Err(e) = reload(&pool).await {
+            tracing::error!("Failed to reload JWT signing keys: {}", e);
+        }
+    }
+}
+
+/// Publish a new signing key and retire the current one once the new key activates.
+///
+/// The new key only starts signing after one refresh interval, so every instance has
+/// loaded it before tokens signed with it are presented. Returns the new key id and activation time.
+pub async fn rotate(pool: &PgPool) -> anyhow::Result<(String, DateTime<Utc>)> {
+    let activates_at = Utc::now() + chrono::Duration::seconds(refresh_interval_secs() as i64);
+    sqlx::query("UPDATE jwt_signing_keys SET retired_at = $1 WHERE retired_at IS NULL")
+        .bind(activates_at)
+        .execute(pool)
+        .await?;
+    let kid = insert_key(pool, activates_at).await?;
+    reload(pool).await?;
+    Ok((kid, activates_at))
+}
+
+async fn insert_key(pool: &PgPool, activated_at: DateTime<Utc>) -> anyhow::Result<String> {
+    let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new())
+        .map_err(|_| anyhow::anyhow!("failed to generate Ed25519 key"))?;
+    let pair = Ed25519KeyPair::from_pkcs8(pkcs8.as_ref())
+        .map_err(|_| anyhow::anyhow!("failed to parse generated Ed25519 key"))?;
+    let kid = Uuid::new_v4().simple().to_string();
+
+    sqlx::query(
+        "INSERT INTO jwt_signing_keys (kid, private_key_sealed, public_key, activated_at) VALUES ($1, $2, $3, $4)",
+    )
+    .bind(&kid)
+    .bind(secrets::seal(&URL_SAFE_NO_PAD.encode(pkcs8.as_ref())))
+    .bind(pair.public_key().as_ref())
+    .bind(activated_at)
+    .execute(pool)
+    .await?;
+    Ok(kid)
+}
+
+async fn reload(pool: &PgPool) -> anyhow::Result<()> {
+    // Retired keys stay published until every token they signed has expired
+    let rows = sqlx::query(
+        r#"
+        SELECT kid, private_key_sealed, activated_at, retired_at FROM jwt_signing_keys
+        WHERE retired_at IS NULL OR retired_at > NOW() - make_interval(secs => $1)
+        ORDER BY activated_at
+        "#,
+    )
+    .bind(token_ttl_secs() as f64)
+    .fetch_all(pool)
+    .await?;
+
+    let mut keys = Vec::with_capacity(rows.len());
+    for row in rows {
+        let kid: String = row.get("kid");
+        let sealed: Option<String> = row.get("private_key_sealed");
+        let private_key = sealed
+            .ok_or_else(|| anyhow::anyhow!("JWT signing key {} is not sealed", kid))
+            .and_then(|sealed| secrets::open(&sealed))
+            .and_then(|encoded| URL_SAFE_NO_PAD.decode(encoded).map_err(Into::into))?;
+        keys.push(SigningKey::from_pkcs8(
+            kid,
+            &private_key,
+            row.get("activated_at"),
+            row.get("retired_at"),
+        )?);
+    }
+    *KEYS.write().expect("JWT key lock poisoned") = keys;
+    Ok(())
+}
+
+/// Seal private keys still stored in plaintext and re-wrap sealed ones under the active key.
+///
+/// Every instance runs this at startup, so each update only applies if the row still holds the value
+/// that was read.
+async fn seal_stored_keys(pool: &PgPool) -> anyhow::Result<()> {
+    let rows: Vec<(String, Option<Vec<u8>>, Option<String>)> = sqlx::query_as(
+        r#"
+        SELECT kid, private_key, private_key_sealed FROM jwt_signing_keys
+        WHERE private_key IS NOT NULL OR split_part(private_key_sealed, '.', 1) <> $1
+        "#,
+    )
+    .bind(secrets::active_key_id())
+    .fetch_all(pool)
+    .await?;
+
+    for (kid, plaintext, sealed) in rows {
+        let resealed = match (plaintext, &sealed) {
+            (Some(plaintext), _) => secrets::seal(&URL_SAFE_NO_PAD.encode(plaintext)),
+            (None, Some(sealed)) => match secrets::rewrap(sealed)? {
+                Some(rewrapped) => rewrapped,
+                None => continue,
+            },
+            (None, None) => continue,
+        };
+        sqlx::query(
+            r#"
+            UPDATE jwt_signing_keys SET private_key = NULL, private_key_sealed = $2
+            WHERE kid = $1 AND private_key_sealed IS NOT DISTINCT FROM $3
+            "#,
+        )
+        .bind(&kid)
+        .bind(resealed)
+        .bind(sealed)
+        .execute(pool)
+        .await?;
+    }
+    Ok(())
+}
+
+/// Validate a requested `scope` parameter. Returns the granted scope string.
+pub fn parse_scope(requested: Option<&str>) -> Result<String, String> {
+    match requested.map(str::trim).filter(|s| !s.is_empty()) {
+        None => Ok(SCOPES.join(" ")),
+        Some(requested) => {
+            for scope in requested.split_whitespace() {
+                if !SCOPES.contains(&scope) {
+                    return Err(format!("Unknown scope '{}'", scope));
+                }
+            }
+            Ok(requested.split_whitespace().collect::<Vec<_>>().join(" "))
+        }
+    }
+}
+
+/// Issue a signed access token for an API key. Returns the token and its lifetime in seconds.
+pub fn issue_token(api_key_id: Uuid, account_id: Uuid, scope: &str) -> anyhow::Result<(String, i64)> {
+    let now = Utc::now();
+    let keys = KEYS.read().expect("JWT key lock poisoned");
+    let key = keys
+        .iter()
+        .filter(|k| k.can_sign(now))
+        .max_by_key(|k| k.activated_at)
+        .ok_or_else(|| anyhow::anyhow!("no active JWT signing key"))?;
+
+    let ttl = token_ttl_secs();
+    let claims = Claims {
+        iss: issuer(),
+        sub: api_key_id.to_string(),
+        account_id,
+        scope: scope.to_string(),
+        iat: now.timestamp(),
+        exp: now.timestamp() + ttl,
+        jti: Uuid::new_v4().to_string(),
+    };
+    let mut header = Header::new(Algorithm::EdDSA);
+    header.kid = Some(key.kid.clone());
+    let token = jsonwebtoken::encode(&header, &claims, &key.encoding)?;
+    Ok((token, ttl))
+}
+
+/// Verify an access token against the published keys. No database access.
+pub fn verify_token(token: &str) -> Option<Claims> {
+    let header = jsonwebtoken::decode_header(token).ok()?;
+    let kid = header.kid?;
+
+    let keys = KEYS.read().expect("JWT key lock poisoned");
+    let key = keys.iter().find(|k| k.kid == kid)?;
+
+    let mut validation = Validation::new(Algorithm::EdDSA);
+    validation.set_issuer(&[issuer()]);
+    match jsonwebtoken::decode::<Claims>(token, &key.decoding, &validation) {
+        Ok(data) => Some(data.claims),
+        Err(e) => {
+            tracing::debug!("Rejected access token: {}", e);
+            None
+        }
+    }
+}
+
+/// JSON Web Key Set with the public half of every published key.
+pub fn jwks() -> serde_json::Value {
+    let keys = KEYS.read().expect("JWT key lock poisoned");
+    let keys: Vec<serde_json::Value> = keys
+        .iter()
+        .map(|k| {
+            serde_json::json!({
+                "kty": "OKP",
+                "crv": "Ed25519",
+                "alg": "EdDSA",
+                "use": "sig",
+                "kid": k.kid,
+                "x": URL_SAFE_NO_PAD.encode(&k.public_key),
+            })
+        })
+        .collect();
+    serde_json::json!({ "keys": keys })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_key(kid: &str) -> SigningKey {
+        let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new()).unwrap();
+        SigningKey::from_pkcs8(kid.to_string(), pkcs8.as_ref(), Utc::now() - chrono::Duration::seconds(1), None).unwrap()
+    }
+
+    #[test]
+    fn scope_parsing() {
+        assert_eq!(parse_scope(None).unwrap(), SCOPES.join(" "));
+        assert_eq!(parse_scope(Some("  accounts:read   webhooks:write ")).unwrap(), "accounts:read webhooks:write");
+        assert!(parse_scope(Some("accounts:read admin")).is_err());
+    }
+
+    #[test]
+    fn issue_and_verify_roundtrip() {
+        KEYS.write().unwrap().push(test_key("test-roundtrip"));
+        let api_key_id = Uuid::new_v4();
+        let account_id = Uuid::new_v4();
+        let (token, ttl) = issue_token(api_key_id, account_id, "accounts:read").expect("token should be issued");
+        assert_eq!(ttl, token_ttl_secs());
+
+        let claims = verify_token(&token).expect("token should verify");
+        assert_eq!(claims.sub, api_key_id.to_string());
+        assert_eq!(claims.account_id, account_id);
+        assert_eq!(claims.scope, "accounts:read");
+
+        let mut tampered = token.clone();
+        tampered.pop();
+        assert!(verify_token(&tampered).is_none());
+        assert!(jwks()["keys"].as_array().unwrap().iter().any(|k| k["kid"] == "test-roundtrip"));
+    }
+}
@@ -0,0 +1,249 @@
+use chrono::{DateTime, Utc};
+use dashmap::DashMap;
+use once_cell::sync::Lazy;
+use sqlx::postgres::PgListener;
+use sqlx::PgPool;
+use std::env;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+/// Postgres channel used to tell every instance that a key was revoked (payload: key id).
+pub const REVOCATION_CHANNEL: &str = "api_key_revoked";
+
+#[derive(Debug, Clone)]
+pub struct CachedKey {
+    pub api_key_id: Uuid,
+    pub account_id: Uuid,
+    expires_at: Instant,
+}
+
+/// Verified keys, indexed by the full SHA-256 of the presented key.
+///
+/// Every invalidation bumps `generation`. A key read from the database before an invalidation
+/// may already be revoked, so it is only cached if the generation is still the one seen before
+/// the read.
+struct Cache {
+    entries: DashMap<String, CachedKey>,
+    generation: AtomicU64,
+}
+
+impl Cache {
+    fn new() -> Self {
+        Self {
+            entries: DashMap::new(),
+            generation: AtomicU64::new(0),
+        }
+    }
+
+    fn get(&self, key_digest: &str) -> Option<CachedKey> {
+        let entry = self.entries.get(key_digest)?;
+        if entry.expires_at > Instant::now() {
+            return Some(entry.clone());
+        }
+        drop(entry);
+        self.entries.remove(key_digest);
+        None
+    }
+
+    fn insert(&self, key_digest: String, cached: CachedKey, generation: u64) {
+        self.entries.insert(key_digest.clone(), cached);
+        // Checked after inserting: an invalidation that bumps the generation later also sweeps this entry
+        if self.generation.load(Ordering::SeqCst) != generation {
+            self.entries.remove(&key_digest);
+        }
+    }
+
+    fn invalidate(&self, api_key_id: Uuid) {
+        self.generation.fetch_add(1, Ordering::SeqCst);
+        self.entries.retain(|_, cached| cached.api_key_id != api_key_id);
+    }
+
+    fn clear(&self) {
+        self.generation.fetch_add(1, Ordering::SeqCst);
+        self.entries.clear();
+    }
+
+    fn evict_expired(&self) {
+        let now = Instant::now();
+        self.entries.retain(|_, cached| cached.expires_at > now);
+    }
+}
+
+static VERIFIED: Lazy<Cache> = Lazy::new(Cache::new);
+
+// last_used timestamps waiting to be flushed to the database
+static PENDING_LAST_USED: Lazy<DashMap<Uuid, DateTime<Utc>>> = Lazy::new(DashMap::new);
+
+fn ttl() -> Duration {
+    let secs = env::var("API_KEY_CACHE_TTL_SECS").ok().and_then(|v| v.parse().ok()).unwrap_or(60);
+    Duration::from_secs(secs)
+}
+
+/// Look up a previously verified key by its digest.
+pub fn get(key_digest: &str) -> Option<CachedKey> {
+    VERIFIED.get(key_digest)
+}
+
+/// Current invalidation generation; take it before reading a key from the database and pass it to `insert`.
+pub fn generation() -> u64 {
+    VERIFIED.generation.load(Ordering::SeqCst)
+}
+
+/// Cache a verified key, unless a key was invalidated since `generation` was taken.
+pub fn insert(key_digest: String, api_key_id: Uuid, account_id: Uuid, generation: u64) {
+    let ttl = ttl();
+    if ttl.is_zero() {
+        return;
+    }
+    let cached = CachedKey {
+        api_key_id,
+        account_id,
+        expires_at: Instant::now() + ttl,
+    };
+    VERIFIED.insert(key_digest, cached, generation);
+}
+
+/// Drop every cached entry for a key (called on revocation).
+pub fn invalidate(api_key_id: Uuid) {
+    VERIFIED.invalidate(api_key_id);
+}
+
+/// Record that a key was used; the timestamp is written by `flush_last_used`.
+pub fn touch(api_key_id: Uuid) {
+    PENDING_LAST_USED.insert(api_key_id, Utc::now());
+}
+
+async fn flush_pending(pool: &PgPool) -> Result<(), sqlx::Error> {
+    let ids: Vec<Uuid> = PENDING_LAST_USED.iter().map(|e| *e.key()).collect();
+    let mut flushed_ids = Vec::with_capacity(ids.len());
+    let mut timestamps = Vec::with_capacity(ids.len());
+    for id in ids {
+        if let Some((id, ts)) = PENDING_LAST_USED.remove(&id) {
+            flushed_ids.push(id);
+            timestamps.push(ts);
+        }
+    }
+    if flushed_ids.is_empty() {
+        return Ok(());
+    }
+
+    sqlx::query(
+        r#"
+        UPDATE api_keys AS k SET last_used = GREATEST(k.last_used, v.last_used)
+        FROM UNNEST($1::uuid[], $2::timestamptz[]) AS v(id, last_used)
+        WHERE k.id = v.id
+        "#,
+    )
+    .bind(&flushed_ids)
+    .bind(&timestamps)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Background task: batch `last_used` writes and evict expired cache entries.
+pub async fn flush_last_used(pool: PgPool) {
+    let secs = env::var("LAST_USED_FLUSH_SECS").ok().and_then(|v| v.parse().ok()).unwrap_or(30);
+    let mut interval = tokio::time::interval(Duration::from_secs(secs));
+    loop {
+        interval.tick().await;
+        if let Err(e) = flush_pending(&pool).await {
+            tracing::error!("Failed to flush api_keys.last_used: {}", e);
+        }
+        VERIFIED.evict_expired();
+    }
+}
+
+/// Background task: drop cached keys revoked on any instance.
+pub async fn listen_for_revocations(pool: PgPool) {
+    loop {
+        let mut listener = match PgListener::connect_with(&pool).await {
+            Ok(listener) => listener,
+            Err(e) => {
+                tracing::error!("Failed to connect revocation listener: {}", e);
+                tokio::time::sleep(Duration::from_secs(5)).await;
+                continue;
+            }
+        };
+        if let Err(e) = listener.listen(REVOCATION_CHANNEL).await {
+            tracing::error!("Failed to LISTEN on {}: {}", REVOCATION_CHANNEL, e);
+            tokio::time::sleep(Duration::from_secs(5)).await;
+            continue;
+        }
+
+        // Anything revoked while the listener was down may still be cached
+        VERIFIED.clear();
+
+        loop {
+            match listener.try_recv().await {
+                Ok(Some(notification)) => {
+                    if let Ok(id) = Uuid::parse_str(notification.payload()) {
+                        invalidate(id);
+                    }
+                }
+                // Connection lost; the listener reconnects on the next call but notifications may have been missed
+                Ok(None) => VERIFIED.clear(),
+                Err(e) => {
+                    tracing::warn!("Revocation listener disconnected: {}", e);
+                    break;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(api_key_id: Uuid, ttl: Duration) -> CachedKey {
+        CachedKey {
+            api_key_id,
+            account_id: Uuid::new_v4(),
+            expires_at: Instant::now() + ttl,
+        }
+    }
+
+    #[test]
+    fn entries_expire_after_ttl() {
+        let cache = Cache::new();
+        let id = Uuid::new_v4();
+        cache.insert("digest".to_string(), entry(id, Duration::from_millis(50)), 0);
+        assert_eq!(cache.get("digest").map(|c| c.api_key_id), Some(id));
+
+        std::thread::sleep(Duration::from_millis(60));
+        assert!(cache.get("digest").is_none());
+        assert!(cache.entries.is_empty());
+    }
+
+    #[test]
+    fn invalidate_drops_only_that_key() {
+        let cache = Cache::new();
+        let (revoked, kept) = (Uuid::new_v4(), Uuid::new_v4());
+        cache.insert("a".to_string(), entry(revoked, Duration::from_secs(60)), 0);
+        cache.insert("b".to_string(), entry(revoked, Duration::from_secs(60)), 0);
+        cache.insert("c".to_string(), entry(kept, Duration::from_secs(60)), 0);
+
+        cache.invalidate(revoked);
+        assert!(cache.get("a").is_none());
+        assert!(cache.get("b").is_none());
+        assert!(cache.get("c").is_some());
+    }
+
+    #[test]
+    fn key_read_before_an_invalidation_is_not_cached() {
+        let cache = Cache::new();
+        let id = Uuid::new_v4();
+        let generation = cache.generation.load(Ordering::SeqCst);
+        // Revoked while the key was being verified
+        cache.invalidate(id);
+        cache.insert("digest".to_string(), entry(id, Duration::from_secs(60)), generation);
+        assert!(cache.get("digest").is_none());
+
+        cache.clear();
+        let generation = cache.generation.load(Ordering::SeqCst);
+        cache.insert("digest".to_string(), entry(id, Duration::from_secs(60)), generation);
+        assert!(cache.get("digest").is_some());
+    }
+}
@@ -0,0 +1,3 @@
+//! Code shared by the service and the `migrate_api_keys` tool, so both hash keys the same way.
+
+pub mod auth;
@@ -0,0 +1,87 @@
+use dashmap::DashMap;
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+use super::{BoxFuture, LockoutBackend, Reason};
+
+#[derive(Debug)]
+struct Counter {
+    attempts: u32,
+    window_started: Instant,
+}
+
+#[derive(Debug, Default)]
+struct IpState {
+    failed_auth: Option<Counter>,
+    public_route: Option<Counter>,
+    locked_until: Option<Instant>,
+}
+
+impl IpState {
+    fn counter(&mut self, reason: Reason) -> &mut Option<Counter> {
+        match reason {
+            Reason::FailedAuth => &mut self.failed_auth,
+            Reason::PublicRoute => &mut self.public_route,
+        }
+    }
+}
+
+/// Counters and lockouts held in this process only; each replica grants its own allowance.
+#[derive(Default)]
+pub struct MemoryBackend {
+    state: DashMap<IpAddr, IpState>,
+}
+
+impl LockoutBackend for MemoryBackend {
+    fn locked_for(&self, ip: IpAddr) -> BoxFuture<'_, anyhow::Result<Option<Duration>>> {
+        let remaining = self.state.get(&ip).and_then(|state| {
+            let remaining = state.locked_until?.saturating_duration_since(Instant::now());
+            (!remaining.is_zero()).then_some(remaining)
+        });
+        Box::pin(async move { Ok(remaining) })
+    }
+
+    fn record(
+        &self,
+        ip: IpAddr,
+        reason: Reason,
+        window: Duration,
+        duration: Duration,
+    ) -> BoxFuture<'_, anyhow::Result<Option<u32>>> {
+        let now = Instant::now();
+        let limit = reason.limit();
+
+        let mut state = self.state.entry(ip).or_default();
+        let counter = state.counter(reason);
+        let counter = match counter {
+            Some(c) if now.saturating_duration_since(c.window_started) < window => c,
+            _ => counter.insert(Counter { attempts: 0, window_started: now }),
+        };
+        counter.attempts += 1;
+        let attempts = counter.attempts;
+        let locked = attempts > limit;
+        if locked {
+            // Start a lockout and begin counting afresh once it ends
+            state.locked_until = Some(now + duration);
+            *state.counter(reason) = None;
+        }
+        Box::pin(async move { Ok(locked.then_some(attempts)) })
+    }
+
+    fn clear(&self, ip: IpAddr) -> BoxFuture<'_, anyhow::Result<bool>> {
+        let was_locked = self
+            .state
+            .remove(&ip)
+            .is_some_and(|(_, state)| state.locked_until.is_some_and(|until| until > Instant::now()));
+        Box::pin(async move { Ok(was_locked) })
+    }
+
+    fn sweep(&self, window: Duration) -> BoxFuture<'_, anyhow::Result<()>> {
+        let now = Instant::now();
+        self.state.retain(|_, state| {
+            let live = |c: &Option<Counter>| c.as_ref().is_some_and(|c| now.saturating_duration_since(c.window_started) < window);
+            state.locked_until.is_some_and(|until| until > now) || live(&state.failed_auth) || live(&state.public_route)
+        });
+        Box::pin(async { Ok(()) })
+    }
+}
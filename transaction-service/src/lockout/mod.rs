@@ -0,0 +1,192 @@
+use axum::extract::ConnectInfo;
+use axum::http::HeaderMap;
+use chrono::Utc;
+use once_cell::sync::OnceCell;
+use sqlx::PgPool;
+use std::env;
+use std::future::Future;
+use std::net::{IpAddr, SocketAddr};
+use std::pin::Pin;
+use std::time::Duration;
+
+mod memory;
+mod postgres;
+
+pub use memory::MemoryBackend;
+pub use postgres::PostgresBackend;
+
+/// Why an address was locked out.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Reason {
+    /// Too many requests rejected with 401
+    FailedAuth,
+    /// Too many calls to the unauthenticated account/key creation routes
+    PublicRoute,
+}
+
+impl Reason {
+    fn as_str(self) -> &'static str {
+        match self {
+            Reason::FailedAuth => "failed_auth",
+            Reason::PublicRoute => "public_route",
+        }
+    }
+
+    /// Attempts allowed per window before a lockout.
+    fn limit(self) -> u32 {
+        let (var, default) = match self {
+            Reason::FailedAuth => ("AUTH_FAILURE_LIMIT", 10),
+            Reason::PublicRoute => ("PUBLIC_ROUTE_LIMIT", 20),
+        };
+        env::var(var).ok().and_then(|v| v.parse().ok()).unwrap_or(default)
+    }
+}
+
+type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// Storage for attempt counters and lockouts.
+pub trait LockoutBackend: Send + Sync {
+    /// Remaining lockout time for an address, if it is locked out.
+    fn locked_for(&self, ip: IpAddr) -> BoxFuture<'_, anyhow::Result<Option<Duration>>>;
+    /// Count an attempt within `window` and lock the address out for `duration` once it exceeds the
+    /// reason's limit. Returns the attempt count if this attempt started a lockout.
+    fn record(&self, ip: IpAddr, reason: Reason, window: Duration, duration: Duration)
+        -> BoxFuture<'_, anyhow::Result<Option<u32>>>;
+    /// Drop an address's counters and lockout. Returns whether it was locked out.
+    fn clear(&self, ip: IpAddr) -> BoxFuture<'_, anyhow::Result<bool>>;
+    /// Drop counters whose window has passed and lockouts that have ended.
+    fn sweep(&self, window: Duration) -> BoxFuture<'_, anyhow::Result<()>>;
+}
+
+static BACKEND: OnceCell<Box<dyn LockoutBackend>> = OnceCell::new();
+
+fn backend() -> &'static dyn LockoutBackend {
+    BACKEND.get_or_init(|| Box::new(MemoryBackend::default())).as_ref()
+}
+
+/// Keep counters where the rate limiter keeps its buckets (`RATE_LIMIT_BACKEND`), so replicas that
+/// share buckets also share lockouts.
+pub fn init(pool: &PgPool) -> anyhow::Result<()> {
+    let backend: Box<dyn LockoutBackend> = match env::var("RATE_LIMIT_BACKEND").as_deref() {
+        Ok("postgres") => Box::new(PostgresBackend::new(pool.clone())),
+        Ok("memory") | Err(_) => Box::new(MemoryBackend::default()),
+        Ok(other) => anyhow::bail!("unknown RATE_LIMIT_BACKEND '{}'", other),
+    };
+    if BACKEND.set(backend).is_err() {
+        anyhow::bail!("lockout backend already initialised");
+    }
+    Ok(())
+}
+
+fn window() -> Duration {
+    Duration::from_secs(env::var("LOCKOUT_WINDOW_SECS").ok().and_then(|v| v.parse().ok()).unwrap_or(60))
+}
+
+fn lockout_duration() -> Duration {
+    Duration::from_secs(env::var("LOCKOUT_SECS").ok().and_then(|v| v.parse().ok()).unwrap_or(900))
+}
+
+/// Address the request came from. `X-Forwarded-For` is only trusted when `TRUST_X_FORWARDED_FOR=true`,
+/// in which case the last entry (the one added by our own proxy) is used.
+pub fn client_ip(headers: &HeaderMap, connect_info: Option<&ConnectInfo<SocketAddr>>) -> Option<IpAddr> {
+    if env::var("TRUST_X_FORWARDED_FOR").is_ok_and(|v| v == "true") {
+        let forwarded = headers
+            .get("x-forwarded-for")
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.rsplit(',').next())
+            .and_then(|v| v.trim().parse().ok());
+        if forwarded.is_some() {
+            return forwarded;
+        }
+    }
+    connect_info.map(|ConnectInfo(addr)| addr.ip())
+}
+
+/// Remaining lockout time for an address, if it is locked out. Lets the request through if the
+/// backend is unavailable.
+pub async fn locked_for(ip: IpAddr) -> Option<Duration> {
+    backend().locked_for(ip).await.unwrap_or_else(|e| {
+        tracing::error!("Lockout backend unavailable, allowing request from {}: {}", ip, e);
+        None
+    })
+}
+
+/// Count an attempt against an address. Returns the lockout duration if this attempt triggered one.
+pub async fn record(pool: &PgPool, ip: IpAddr, reason: Reason) -> Option<Duration> {
+    let duration = lockout_duration();
+    let attempts = match backend().record(ip, reason, window(), duration).await {
+        Ok(attempts) => attempts?,
+        Err(e) => {
+            tracing::error!("Failed to count {} attempt from {}: {}", reason.as_str(), ip, e);
+            return None;
+        }
+    };
+    tracing::warn!("Locking out {} for {:?} after {} {} attempts", ip, duration, attempts, reason.as_str());
+    spawn_audit(pool.clone(), ip, reason, attempts, duration);
+    Some(duration)
+}
+
+/// Lift a lockout early. Returns whether the address was locked out.
+pub async fn clear(ip: IpAddr) -> anyhow::Result<bool> {
+    backend().clear(ip).await
+}
+
+fn spawn_audit(pool: PgPool, ip: IpAddr, reason: Reason, attempts: u32, duration: Duration) {
+    tokio::spawn(async move {
+        let locked_until = Utc::now() + chrono::Duration::from_std(duration).unwrap_or_else(|_| chrono::Duration::zero());
+        let result = sqlx::query(
+            "INSERT INTO auth_lockouts (ip_address, reason, attempts, locked_until) VALUES ($1, $2, $3, $4)",
+        )
+        .bind(ip.to_string())
+        .bind(reason.as_str())
+        .bind(attempts as i32)
+        .bind(locked_until)
+        .execute(&pool)
+        .await;
+        if let Err(e) = result {
+            tracing::error!("Failed to record lockout of {}: {}", ip, e);
+        }
+    });
+}
+
+/// Background task: drop counters and lockouts that have expired.
+pub async fn sweep() {
+    let mut interval = tokio::time::interval(Duration::from_secs(60));
+    loop {
+        interval.tick().await;
+        if let Err(e) = backend().sweep(window()).await {
+            tracing::error!("Failed to sweep expired lockouts: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn locks_out_after_limit() {
+        // Lazy pool: nothing connects unless the audit insert runs, and a failed insert is only logged
+        let pool = PgPool::connect_lazy("postgres://localhost/unused").unwrap();
+        let ip: IpAddr = "192.0.2.10".parse().unwrap();
+        let limit = Reason::FailedAuth.limit();
+
+        for _ in 0..limit {
+            assert!(record(&pool, ip, Reason::FailedAuth).await.is_none());
+        }
+        assert!(locked_for(ip).await.is_none());
+        assert!(record(&pool, ip, Reason::FailedAuth).await.is_some());
+        assert!(locked_for(ip).await.is_some());
+
+        assert!(clear(ip).await.unwrap());
+        assert!(locked_for(ip).await.is_none());
+    }
+
+    #[test]
+    fn forwarded_for_is_ignored_by_default() {
+        let mut headers = HeaderMap::new();
+        headers.insert("x-forwarded-for", "198.51.100.1".parse().unwrap());
+        let peer = ConnectInfo(SocketAddr::from(([203, 0, 113, 5], 4000)));
+        assert_eq!(client_ip(&headers, Some(&peer)), Some("203.0.113.5".parse().unwrap()));
+    }
+}
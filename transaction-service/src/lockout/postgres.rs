@@ -0,0 +1,121 @@
+use sqlx::PgPool;
+use std::net::IpAddr;
+use std::time::Duration;
+
+use super::{BoxFuture, LockoutBackend, Reason};
+
+/// Counters in `lockout_counters` and lockouts in `active_lockouts`, shared by every replica.
+///
+/// Each attempt is a single upsert, so the row lock serialises concurrent attempts from one address.
+pub struct PostgresBackend {
+    pool: PgPool,
+}
+
+impl PostgresBackend {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+impl LockoutBackend for PostgresBackend {
+    fn locked_for(&self, ip: IpAddr) -> BoxFuture<'_, anyhow::Result<Option<Duration>>> {
+        Box::pin(async move {
+            let remaining: Option<f64> = sqlx::query_scalar(
+                r#"
+                SELECT EXTRACT(EPOCH FROM locked_until - NOW())::float8 FROM active_lockouts
+                WHERE ip_address = $1 AND locked_until > NOW()
+                "#,
+            )
+            .bind(ip.to_string())
+            .fetch_optional(&self.pool)
+            .await?;
+            Ok(remaining.map(Duration::from_secs_f64))
+        })
+    }
+
+    fn record(
+        &self,
+        ip: IpAddr,
+        reason: Reason,
+        window: Duration,
+        duration: Duration,
+    ) -> BoxFuture<'_, anyhow::Result<Option<u32>>> {
+        Box::pin(async move {
+            let attempts: i32 = sqlx::query_scalar(
+                r#"
+                INSERT INTO lockout_counters AS c (ip_address, reason, attempts, window_started)
+                VALUES ($1, $2, 1, NOW())
+                ON CONFLICT (ip_address, reason) DO UPDATE SET
+                    attempts = CASE WHEN c.window_started > NOW() - make_interval(secs => $3) THEN c.attempts + 1 ELSE 1 END,
+                    window_started = CASE WHEN c.window_started > NOW() - make_interval(secs => $3) THEN c.window_started ELSE NOW() END
+                RETURNING attempts
+                "#,
+            )
+            .bind(ip.to_string())
+            .bind(reason.as_str())
+            .bind(window.as_secs_f64())
+            .fetch_one(&self.pool)
+            .await?;
+            if attempts as u32 <= reason.limit() {
+                return Ok(None);
+            }
+
+            // Start a lockout and begin counting afresh once it ends. Only the request that removes
+            // the counter starts it, so concurrent attempts over the limit lock out (and audit) once
+            let mut tx = self.pool.begin().await?;
+            let removed: Option<i32> = sqlx::query_scalar(
+                "DELETE FROM lockout_counters WHERE ip_address = $1 AND reason = $2 AND attempts > $3 RETURNING attempts",
+            )
+            .bind(ip.to_string())
+            .bind(reason.as_str())
+            .bind(reason.limit() as i32)
+            .fetch_optional(&mut *tx)
+            .await?;
+            let Some(attempts) = removed else {
+                return Ok(None);
+            };
+            sqlx::query(
+                r#"
+                INSERT INTO active_lockouts (ip_address, locked_until) VALUES ($1, NOW() + make_interval(secs => $2))
+                ON CONFLICT (ip_address) DO UPDATE SET locked_until = GREATEST(active_lockouts.locked_until, EXCLUDED.locked_until)
+                "#,
+            )
+            .bind(ip.to_string())
+            .bind(duration.as_secs_f64())
+            .execute(&mut *tx)
+            .await?;
+            tx.commit().await?;
+            Ok(Some(attempts as u32))
+        })
+    }
+
+    fn clear(&self, ip: IpAddr) -> BoxFuture<'_, anyhow::Result<bool>> {
+        Box::pin(async move {
+            let mut tx = self.pool.begin().await?;
+            sqlx::query("DELETE FROM lockout_counters WHERE ip_address = $1")
+                .bind(ip.to_string())
+                .execute(&mut *tx)
+                .await?;
+            let was_locked: Option<bool> =
+                sqlx::query_scalar("DELETE FROM active_lockouts WHERE ip_address = $1 RETURNING locked_until > NOW()")
+                    .bind(ip.to_string())
+                    .fetch_optional(&mut *tx)
+                    .await?;
+            tx.commit().await?;
+            Ok(was_locked.unwrap_or(false))
+        })
+    }
+
+    fn sweep(&self, window: Duration) -> BoxFuture<'_, anyhow::Result<()>> {
+        Box::pin(async move {
+            sqlx::query("DELETE FROM lockout_counters WHERE window_started <= NOW() - make_interval(secs => $1)")
+                .bind(window.as_secs_f64())
+                .execute(&self.pool)
+                .await?;
+            sqlx::query("DELETE FROM active_lockouts WHERE locked_until <= NOW()")
+                .execute(&self.pool)
+                .await?;
+            Ok(())
+        })
+    }
+}
@@ -4,18 +4,24 @@ use axum::http::header::CONTENT_TYPE;
 use axum::http::StatusCode;
 use tokio::fs;
 use std::net::SocketAddr;
-use tracing_subscriber;
 use dotenvy::dotenv;
 use std::env;
+use transaction_service::auth;
 
 mod routes;
 mod db;
 mod middleware;
 mod models;
 mod handlers;
-mod auth;
 mod embedded_assets;
 mod rate_limit;
+mod jwt;
+mod key_cache;
+mod lockout;
+mod secrets;
+mod webhooks;
+mod events;
+mod stream;
 
 #[tokio::main]
 async fn main() {
@@ -26,6 +32,26 @@ async fn main() {
         .init();
 
     let pool = db::init_pool().await.expect("DB connection failed");
+    // Applied migrations are recorded in _sqlx_migrations, so each runs once per database
+    sqlx::migrate!().run(&pool).await.expect("Failed to apply migrations");
+
+    auth::init_dummy_hash();
+    secrets::init().expect("Failed to load secret encryption keys");
+    jwt::init(&pool).await.expect("Failed to load JWT signing keys");
+    tokio::spawn(jwt::refresh_keys(pool.clone()));
+    tokio::spawn(key_cache::flush_last_used(pool.clone()));
+    tokio::spawn(key_cache::listen_for_revocations(pool.clone()));
+    rate_limit::init(&pool).await.expect("Failed to initialise rate limiting");
+    tokio::spawn(rate_limit::maintain(pool.clone()));
+    lockout::init(&pool).expect("Failed to initialise lockouts");
+    tokio::spawn(lockout::sweep());
+    webhooks::seal_stored_secrets(&pool).await.expect("Failed to seal stored webhook secrets");
+    middleware::seal_stored_signing_secrets(&pool).await.expect("Failed to seal stored API key signing secrets");
+    webhooks::delivery::init().expect("Failed to set up webhook delivery");
+    tokio::spawn(webhooks::worker::run(pool.clone()));
+    tokio::spawn(webhooks::backfill::run(pool.clone()));
+    tokio::spawn(events::listen(pool.clone()));
+    tokio::spawn(events::purge_expired(pool.clone()));
 
     let app = Router::new()
         .route("/health", get(|| async { "OK" }))
@@ -33,6 +59,7 @@ async fn main() {
         .route("/docs", get(serve_swagger))
         .route("/static/swagger-ui.css", get(serve_swagger_css))
         .route("/static/swagger-ui-bundle.js", get(serve_swagger_bundle_js))
+        .merge(routes::oauth_routes(pool.clone()))
         .nest("/api", routes::routes(pool.clone()));
 
     let port: u16 = env::var("PORT").ok().and_then(|v| v.parse().ok()).unwrap_or(3000);
@@ -40,7 +67,10 @@ async fn main() {
     tracing::info!("Starting server at {}", addr);
 
     let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
-    axum::serve(listener, app).await.unwrap();
+    // Peer addresses are needed for per-IP lockouts
+    axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>())
+        .await
+        .unwrap();
 }
 
 async fn serve_openapi() -> impl IntoResponse {
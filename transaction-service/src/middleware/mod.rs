@@ -1,48 +1,344 @@
 use axum::{
-    extract::{Request, State},
-    http::{HeaderMap, StatusCode},
+    body::Body,
+    extract::{ConnectInfo, MatchedPath, OriginalUri, Request, State},
+    http::{header::{AUTHORIZATION, RETRY_AFTER}, HeaderMap, HeaderValue, Method, StatusCode},
     middleware::Next,
-    response::Response,
+    response::{IntoResponse, Response},
     Json,
 };
 use sqlx::PgPool;
 use sqlx::Row;
-use crate::auth::{compute_fingerprint, verify_key};
+use crate::auth::{
+    compute_fingerprint, compute_key_digest, dummy_verify, hmac_string_to_sign, parse_hmac_authorization, spawn_rehash_if_outdated, verify_key,
+    verify_request_signature, AuthContext, HMAC_SCHEME,
+};
+use crate::jwt;
+use crate::key_cache;
+use crate::lockout::{self, Reason};
 use crate::rate_limit;
+use crate::secrets;
+use sha2::{Digest, Sha256};
 use std::env;
-// argon2 imports not needed here (verification uses helper)
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
 
 use crate::models::ErrorResponse;
 
+type AuthError = (StatusCode, Json<ErrorResponse>);
+
+/// Largest request body accepted for signed requests (the body is buffered to verify its hash).
+const MAX_SIGNED_BODY_BYTES: usize = 1024 * 1024;
+
+fn unauthorized(message: &str) -> AuthError {
+    (
+        StatusCode::UNAUTHORIZED,
+        Json(ErrorResponse::new("unauthorized", message)),
+    )
+}
+
+/// Why a request was turned away by `auth_middleware`.
+enum Rejection {
+    Auth(AuthError),
+    RateLimited(rate_limit::Decision),
+}
+
+impl From<AuthError> for Rejection {
+    fn from(err: AuthError) -> Self {
+        Rejection::Auth(err)
+    }
+}
+
+impl IntoResponse for Rejection {
+    fn into_response(self) -> Response {
+        match self {
+            Rejection::Auth(err) => err.into_response(),
+            Rejection::RateLimited(decision) => {
+                let mut response = (
+                    StatusCode::TOO_MANY_REQUESTS,
+                    Json(ErrorResponse::new("rate_limited", "Too many requests")),
+                )
+                    .into_response();
+                set_rate_limit_headers(response.headers_mut(), &decision);
+                response
+            }
+        }
+    }
+}
+
+fn set_rate_limit_headers(headers: &mut HeaderMap, decision: &rate_limit::Decision) {
+    headers.insert("x-ratelimit-limit", HeaderValue::from(decision.limit));
+    headers.insert("x-ratelimit-remaining", HeaderValue::from(decision.remaining));
+    if !decision.allowed {
+        headers.insert(RETRY_AFTER, HeaderValue::from(decision.retry_after_secs.max(1)));
+    }
+}
+
+/// Charge the key's rate-limit bucket, rejecting the request if it is empty.
+async fn charge(api_key_id: uuid::Uuid, cost: u32) -> Result<rate_limit::Decision, Rejection> {
+    let decision = rate_limit::take(api_key_id, cost).await;
+    if decision.allowed {
+        Ok(decision)
+    } else {
+        Err(Rejection::RateLimited(decision))
+    }
+}
+
 pub async fn auth_middleware(
     State(pool): State<PgPool>,
+    request: Request,
+    next: Next,
+) -> Result<Response, impl IntoResponse> {
+    // Dispatch on the authentication scheme: signed requests and OAuth2 access tokens use the
+    // Authorization header, everything else falls back to the bearer x-api-key header.
+    let authorization = request
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string);
+
+    // Tokens charged for this route (configured in rate_limit_route_costs)
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|matched| matched.as_str().to_string())
+        .unwrap_or_default();
+    let cost = rate_limit::route_cost(request.method().as_str(), &route);
+
+    let started = Instant::now();
+    let authenticated = if authorization.as_deref().is_some_and(|v| v.starts_with(HMAC_SCHEME)) {
+        match authenticate_signed(&pool, request).await {
+            Ok((ctx, request)) => charge(ctx.api_key_id, cost).await.map(|decision| (ctx, request, decision)),
+            Err(err) => Err(err.into()),
+        }
+    } else if let Some(token) = authorization.as_deref().and_then(|v| v.strip_prefix("Bearer ")) {
+        match authenticate_access_token(token.trim()) {
+            Ok(ctx) => charge(ctx.api_key_id, cost).await.map(|decision| (ctx, request, decision)),
+            Err(err) => Err(err.into()),
+        }
+    } else {
+        // The bucket is charged inside, before the expensive Argon2 verification
+        authenticate_api_key(&pool, request.headers(), cost)
+            .await
+            .map(|(ctx, decision)| (ctx, request, decision))
+    };
+
+    let (ctx, mut request, decision) = match authenticated {
+        Ok(authenticated) => authenticated,
+        Err(rejection) => {
+            // Pad rejections to a fixed minimum duration so response timing does not reveal
+            // which check failed
+            if matches!(&rejection, Rejection::Auth((StatusCode::UNAUTHORIZED, _))) {
+                let floor = Duration::from_millis(
+                    env::var("AUTH_FAILURE_MIN_MS").ok().and_then(|v| v.parse().ok()).unwrap_or(200),
+                );
+                tokio::time::sleep(floor.saturating_sub(started.elapsed())).await;
+            }
+            return Err(rejection);
+        }
+    };
+
+    // Access tokens are limited to their granted scopes
+    let path = request
+        .extensions()
+        .get::<OriginalUri>()
+        .map(|original| original.0.path().to_string())
+        .unwrap_or_else(|| request.uri().path().to_string());
+    let allowed = match required_scope(request.method(), &path) {
+        Some(scope) => ctx.has_scope(&scope),
+        None => ctx.scopes.is_none(),
+    };
+    if !allowed {
+        return Err(Rejection::Auth((
+            StatusCode::FORBIDDEN,
+            Json(ErrorResponse::new("insufficient_scope", "Access token does not grant access to this resource")),
+        )));
+    }
+
+    tracing::debug!("Authenticated API key {} for account {}", ctx.api_key_id, ctx.account_id);
+    request.extensions_mut().insert(ctx);
+
+    // Continue to the next handler
+    let mut response = next.run(request).await;
+    set_rate_limit_headers(response.headers_mut(), &decision);
+    Ok(response)
+}
+
+/// Scope an access token needs for `method` on `path` (e.g. `GET /api/accounts` -> `accounts:read`).
+/// Returns `None` for paths outside the scoped resources, which access tokens cannot reach.
+fn required_scope(method: &Method, path: &str) -> Option<String> {
+    let segment = path.trim_start_matches("/api/").split('/').next()?;
+    let resource = match segment {
+        "accounts" => "accounts",
+        "transactions" => "transactions",
+        "webhooks" | "webhook-events" => "webhooks",
+        "events" | "stream" => "events",
+        _ => return None,
+    };
+    let action = if method == Method::GET || method == Method::HEAD { "read" } else { "write" };
+    Some(format!("{}:{}", resource, action))
+}
+
+/// Authenticate an OAuth2 access token issued by `POST /oauth/token`.
+/// Tokens are verified locally against the published signing keys, without a database round trip.
+fn authenticate_access_token(token: &str) -> Result<AuthContext, AuthError> {
+    let claims = jwt::verify_token(token).ok_or_else(|| unauthorized("Invalid or expired access token"))?;
+    let api_key_id = uuid::Uuid::parse_str(&claims.sub).map_err(|_| unauthorized("Invalid or expired access token"))?;
+    Ok(AuthContext {
+        api_key_id,
+        account_id: claims.account_id,
+        scopes: Some(claims.scope.split_whitespace().map(str::to_string).collect()),
+    })
+}
+
+/// Guard for `/api/admin/*`: requires `x-admin-token` to match the `ADMIN_TOKEN` env var.
+/// The admin API is disabled when `ADMIN_TOKEN` is unset.
+pub async fn admin_middleware(
     headers: HeaderMap,
     request: Request,
     next: Next,
 ) -> Result<Response, (StatusCode, Json<ErrorResponse>)> {
+    let expected = env::var("ADMIN_TOKEN").ok().filter(|t| !t.is_empty()).ok_or_else(|| {
+        (
+            StatusCode::FORBIDDEN,
+            Json(ErrorResponse::new("forbidden", "Admin API is disabled")),
+        )
+    })?;
+    let provided = headers
+        .get("x-admin-token")
+        .and_then(|value| value.to_str().ok())
+        .ok_or_else(|| unauthorized("Missing x-admin-token header"))?;
+
+    // Compare digests so the comparison time does not depend on the token contents
+    if Sha256::digest(provided.as_bytes()) != Sha256::digest(expected.as_bytes()) {
+        return Err(unauthorized("Invalid admin token"));
+    }
+
+    Ok(next.run(request).await)
+}
+
+/// Authenticate a request carrying the raw key in `x-api-key`.
+/// Returns the caller identity and the rate-limit decision for the request.
+async fn authenticate_api_key(
+    pool: &PgPool,
+    headers: &HeaderMap,
+    cost: u32,
+) -> Result<(AuthContext, rate_limit::Decision), Rejection> {
     // Extract API key from x-api-key header
     let api_key = headers
         .get("x-api-key")
         .and_then(|value| value.to_str().ok())
-        .ok_or_else(|| {
+        .ok_or_else(|| unauthorized("Missing x-api-key header"))?;
+
+    let fingerprint = compute_fingerprint(api_key);
+
+    // Fast path: this exact key was verified recently
+    let digest = compute_key_digest(api_key);
+    if let Some(cached) = key_cache::get(&digest) {
+        let decision = charge(cached.api_key_id, cost).await?;
+        key_cache::touch(cached.api_key_id);
+        let ctx = AuthContext {
+            api_key_id: cached.api_key_id,
+            account_id: cached.account_id,
+            scopes: None,
+        };
+        return Ok((ctx, decision));
+    }
+
+    // Validate API key against database
+    // Use the fingerprint to find the candidate. Taken before the read, so a revocation that
+    // lands while the key is being verified keeps it out of the cache
+    let generation = key_cache::generation();
+    let candidates = sqlx::query(
+        "SELECT id, account_id, key_hash FROM api_keys WHERE key_fingerprint = $1 AND account_id IS NOT NULL AND revoked_at IS NULL",
+    )
+    .bind(&fingerprint)
+    .fetch_all(pool)
+    .await
+    .map_err(|_| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse::new(
+                "database_error",
+                "Failed to validate API key",
+            )),
+        )
+    })?;
+
+    if candidates.is_empty() {
+        // Spend the same Argon2 work as a wrong key so the two cases look alike
+        dummy_verify(api_key);
+        return Err(unauthorized("Invalid API key").into());
+    }
+
+    // Charge the candidate's bucket before paying for Argon2; guessing a key whose fingerprint
+    // matches someone else's is infeasible, so this cannot be used to drain another key's bucket
+    let candidate_id: uuid::Uuid = candidates[0].get("id");
+    let decision = charge(candidate_id, cost).await?;
+
+    // Verify Argon2 hash with helper; a fingerprint may match more than one stored key
+    let mut matched = None;
+    for candidate in candidates {
+        let key_hash: String = candidate.get("key_hash");
+        let verified = verify_key(api_key, &key_hash).map_err(|_| {
             (
-                StatusCode::UNAUTHORIZED,
-                Json(ErrorResponse::new(
-                    "unauthorized",
-                    "Missing x-api-key header",
-                )),
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse::new("crypto_error", "Invalid key hash format")),
             )
         })?;
+        if verified {
+            matched = Some(candidate);
+            break;
+        }
+    }
+    let row = matched.ok_or_else(|| unauthorized("Invalid API key"))?;
 
-    // Validate API key against database
-    // Compute fingerprint to find candidate
-    let fingerprint = compute_fingerprint(api_key);
+    // last_used is written in batches by key_cache::flush_last_used
+    let key_id: uuid::Uuid = row.get("id");
+    let account_id: uuid::Uuid = row.get("account_id");
+    let key_hash: String = row.get("key_hash");
+    spawn_rehash_if_outdated(pool, key_id, api_key, &key_hash);
+    key_cache::touch(key_id);
+    key_cache::insert(digest, key_id, account_id, generation);
+
+    let ctx = AuthContext {
+        api_key_id: key_id,
+        account_id,
+        scopes: None,
+    };
+    Ok((ctx, decision))
+}
+
+/// Authenticate a request signed with `Authorization: TS-HMAC keyId=..., timestamp=..., nonce=..., signature=...`.
+///
+/// The body is buffered to verify its hash, so the rebuilt request is returned to the caller.
+async fn authenticate_signed(pool: &PgPool, request: Request) -> Result<(AuthContext, Request), AuthError> {
+    let auth = request
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(parse_hmac_authorization)
+        .ok_or_else(|| unauthorized("Malformed TS-HMAC authorization header"))?;
+
+    // Reject stale or future-dated requests before touching the database
+    let max_skew: i64 = env::var("HMAC_MAX_SKEW_SECS").ok().and_then(|v| v.parse().ok()).unwrap_or(300);
+    let now = chrono::Utc::now().timestamp();
+    if (now - auth.timestamp).abs() > max_skew {
+        return Err(unauthorized("Request timestamp outside allowed window"));
+    }
+
+    let (parts, body) = request.into_parts();
+    let body = axum::body::to_bytes(body, MAX_SIGNED_BODY_BYTES).await.map_err(|_| {
+        (
+            StatusCode::PAYLOAD_TOO_LARGE,
+            Json(ErrorResponse::new("payload_too_large", "Request body too large")),
+        )
+    })?;
 
     let key_row = sqlx::query(
-        "SELECT id, account_id, key_hash FROM api_keys WHERE key_fingerprint = $1",
+        "SELECT id, account_id, signing_secret, signing_secret_sealed FROM api_keys WHERE id = $1 AND account_id IS NOT NULL AND revoked_at IS NULL",
     )
-    .bind(&fingerprint)
-    .fetch_optional(&pool)
+    .bind(auth.key_id)
+    .fetch_optional(pool)
     .await
     .map_err(|_| {
         (
@@ -53,51 +349,149 @@ pub async fn auth_middleware(
             )),
         )
     })?;
-    let row = match key_row {
-        Some(r) => r,
-        None => {
-            return Err((
-                StatusCode::UNAUTHORIZED,
-                Json(ErrorResponse::new("unauthorized", "Invalid API key")),
-            ));
-        }
+    let row = key_row.ok_or_else(|| unauthorized("Invalid API key"))?;
+    // A plaintext secret is only left until the startup reseal has run
+    let secret = match (row.get::<Option<String>, _>("signing_secret_sealed"), row.get::<Option<String>, _>("signing_secret")) {
+        (Some(sealed), _) => secrets::open(&sealed).map_err(|e| {
+            tracing::error!("Failed to unseal signing secret of API key {}: {}", auth.key_id, e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse::new("crypto_error", "Failed to validate API key")),
+            )
+        })?,
+        (None, Some(plaintext)) => plaintext,
+        (None, None) => return Err(unauthorized("API key has no signing secret")),
     };
 
-    let key_hash: String = row.get("key_hash");
+    // Routes are nested under /api, so sign over the original (unstripped) URI
+    let uri = parts
+        .extensions
+        .get::<OriginalUri>()
+        .map(|original| &original.0)
+        .unwrap_or(&parts.uri);
+    let path_and_query = uri
+        .path_and_query()
+        .map(|pq| pq.as_str())
+        .unwrap_or_else(|| uri.path());
+    let to_sign = hmac_string_to_sign(parts.method.as_str(), path_and_query, auth.timestamp, &auth.nonce, &body);
+    if !verify_request_signature(&secret, &to_sign, &auth.signature) {
+        return Err(unauthorized("Invalid request signature"));
+    }
 
-    // Verify Argon2 hash with helper
-    let verified = verify_key(api_key, &key_hash).map_err(|_| {
+    // Record the nonce; a conflict means this exact request was already seen
+    let _ = sqlx::query("DELETE FROM auth_nonces WHERE api_key_id = $1 AND created_at < NOW() - make_interval(secs => $2)")
+        .bind(auth.key_id)
+        .bind(2.0 * max_skew as f64)
+        .execute(pool)
+        .await;
+    let inserted = sqlx::query(
+        "INSERT INTO auth_nonces (api_key_id, nonce) VALUES ($1, $2) ON CONFLICT DO NOTHING",
+    )
+    .bind(auth.key_id)
+    .bind(&auth.nonce)
+    .execute(pool)
+    .await
+    .map_err(|_| {
         (
             StatusCode::INTERNAL_SERVER_ERROR,
-            Json(ErrorResponse::new("crypto_error", "Invalid key hash format")),
+            Json(ErrorResponse::new("database_error", "Failed to record nonce")),
         )
     })?;
+    if inserted.rows_affected() == 0 {
+        return Err(unauthorized("Replayed request nonce"));
+    }
+
+    key_cache::touch(auth.key_id);
+
+    let ctx = AuthContext {
+        api_key_id: auth.key_id,
+        account_id: row.get("account_id"),
+        scopes: None,
+    };
+    Ok((ctx, Request::from_parts(parts, Body::from(body))))
+}
 
-    if !verified {
-        return Err((
-            StatusCode::UNAUTHORIZED,
-            Json(ErrorResponse::new("unauthorized", "Invalid API key")),
-        ));
+/// Seal TS-HMAC signing secrets still stored in plaintext and re-wrap sealed ones under the active key.
+///
+/// Every instance runs this at startup, so each update only applies if the row still holds the values
+/// that were read.
+pub async fn seal_stored_signing_secrets(pool: &PgPool) -> anyhow::Result<()> {
+    let rows: Vec<(uuid::Uuid, Option<String>, Option<String>)> = sqlx::query_as(
+        r#"
+        SELECT id, signing_secret, signing_secret_sealed FROM api_keys
+        WHERE signing_secret IS NOT NULL OR split_part(signing_secret_sealed, '.', 1) <> $1
+        "#,
+    )
+    .bind(secrets::active_key_id())
+    .fetch_all(pool)
+    .await?;
+
+    for (id, plaintext, sealed) in rows {
+        let resealed = match (&plaintext, &sealed) {
+            (Some(plaintext), _) => secrets::seal(plaintext),
+            (None, Some(sealed)) => match secrets::rewrap(sealed)? {
+                Some(rewrapped) => rewrapped,
+                None => continue,
+            },
+            (None, None) => continue,
+        };
+        sqlx::query(
+            r#"
+            UPDATE api_keys SET signing_secret = NULL, signing_secret_sealed = $2
+            WHERE id = $1 AND signing_secret IS NOT DISTINCT FROM $3 AND signing_secret_sealed IS NOT DISTINCT FROM $4
+            "#,
+        )
+        .bind(id)
+        .bind(resealed)
+        .bind(plaintext)
+        .bind(sealed)
+        .execute(pool)
+        .await?;
     }
+    Ok(())
+}
 
-    // Update last_used timestamp (use id from row)
-    let key_id: uuid::Uuid = row.get("id");
-    let _ = sqlx::query(
-        "UPDATE api_keys SET last_used = NOW() WHERE id = $1",
+fn locked_out(retry_after: Duration) -> Response {
+    let mut response = (
+        StatusCode::TOO_MANY_REQUESTS,
+        Json(ErrorResponse::new("locked_out", "Too many attempts from this address; try again later")),
     )
-    .bind(key_id)
-    .execute(&pool)
-    .await;
+        .into_response();
+    // Round up so clients never retry while still locked out
+    let secs = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+    response.headers_mut().insert(RETRY_AFTER, HeaderValue::from(secs));
+    response
+}
 
-    // Rate limiting: enforce per-key limit (fingerprint used as key)
-    let limit_per_min: u64 = env::var("RATE_LIMIT_PER_MIN").ok().and_then(|v| v.parse().ok()).unwrap_or(60);
-    if !rate_limit::allow(&fingerprint, limit_per_min) {
-        return Err((
-            StatusCode::TOO_MANY_REQUESTS,
-            Json(ErrorResponse::new("rate_limited", "Too many requests")),
-        ));
+/// Per-address brute-force guard for authenticated routes: rejects locked-out addresses
+/// and counts every 401 towards a lockout.
+pub async fn failed_auth_guard(State(pool): State<PgPool>, request: Request, next: Next) -> Response {
+    let ip = lockout::client_ip(request.headers(), request.extensions().get::<ConnectInfo<SocketAddr>>());
+    let Some(ip) = ip else {
+        return next.run(request).await;
+    };
+    if let Some(remaining) = lockout::locked_for(ip).await {
+        return locked_out(remaining);
     }
 
-    // Continue to the next handler
-    Ok(next.run(request).await)
+    let response = next.run(request).await;
+    if response.status() == StatusCode::UNAUTHORIZED {
+        lockout::record(&pool, ip, Reason::FailedAuth).await;
+    }
+    response
+}
+
+/// Per-address throttle for the unauthenticated `POST /accounts` and `POST /api-keys` routes.
+pub async fn public_route_guard(State(pool): State<PgPool>, request: Request, next: Next) -> Response {
+    let ip = lockout::client_ip(request.headers(), request.extensions().get::<ConnectInfo<SocketAddr>>());
+    let Some(ip) = ip else {
+        return next.run(request).await;
+    };
+    if let Some(remaining) = lockout::locked_for(ip).await {
+        return locked_out(remaining);
+    }
+    if let Some(duration) = lockout::record(&pool, ip, Reason::PublicRoute).await {
+        return locked_out(duration);
+    }
+    next.run(request).await
 }
@@ -1,12 +1,13 @@
 use serde::{Deserialize, Serialize, Serializer, ser::SerializeStruct};
 use sqlx::FromRow;
+use std::collections::HashMap;
 use uuid::Uuid;
 
 // ============================
 // Account Models
 // ============================
 
-#[derive(Debug, Serialize, FromRow)]
+#[derive(Debug, Clone, Serialize, FromRow)]
 pub struct Account {
     pub id: Uuid,
     pub business_name: String,
@@ -54,6 +55,7 @@ pub struct CreateTransactionRequest {
 // API Key Models
 // ============================
 
+#[allow(dead_code)]
 #[derive(Debug, Serialize, FromRow)]
 pub struct ApiKey {
     pub id: Uuid,
@@ -69,6 +71,89 @@ pub struct CreateApiKeyRequest {
     pub account_id: Uuid,
 }
 
+/// Admin report of the Argon2 parameters used by active API keys.
+#[derive(Debug, Serialize)]
+pub struct HashReport {
+    pub current_params: String,
+    pub total: i64,
+    pub outdated: i64,
+    pub by_params: Vec<HashParamsCount>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct HashParamsCount {
+    pub params: String,
+    pub count: i64,
+    pub current: bool,
+}
+
+/// Admin view of a key's rate-limit bucket.
+#[derive(Debug, Serialize)]
+pub struct RateLimitStatus {
+    pub api_key_id: Uuid,
+    pub capacity: f64,
+    pub refill_per_sec: f64,
+    /// True when the key has a row in `rate_limit_policies`
+    pub policy_override: bool,
+    /// Current fill level; a key without a bucket has a full one
+    pub tokens: f64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SetRateLimitPolicyRequest {
+    pub capacity: i32,
+    pub refill_per_sec: f64,
+}
+
+/// Audit record of a client address locked out by the brute-force guard.
+#[derive(Debug, Serialize, sqlx::FromRow)]
+pub struct AuthLockout {
+    pub id: i64,
+    pub ip_address: String,
+    pub reason: String,
+    pub attempts: i32,
+    pub locked_until: chrono::DateTime<chrono::Utc>,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct LockoutQuery {
+    pub ip: Option<String>,
+    /// Only lockouts still in force
+    #[serde(default)]
+    pub active: bool,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct RouteCost {
+    pub method: String,
+    /// Route pattern as registered, e.g. `/api/transactions` or `/api/accounts/{id}`
+    pub route: String,
+    pub cost: i32,
+}
+
+// ============================
+// OAuth2 Models
+// ============================
+
+/// Form body of `POST /oauth/token` (client_credentials grant).
+/// Client credentials may instead be sent with HTTP Basic authentication.
+#[derive(Debug, Deserialize)]
+pub struct TokenRequest {
+    pub grant_type: String,
+    pub client_id: Option<String>,
+    pub client_secret: Option<String>,
+    pub scope: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TokenResponse {
+    pub access_token: String,
+    pub token_type: String,
+    pub expires_in: i64,
+    pub scope: String,
+}
+
 // ============================
 // Webhook Models
 // ============================
@@ -79,37 +164,341 @@ pub struct Webhook {
     pub account_id: Uuid,
     pub url: String,
     pub secret: String,
+    pub description: Option<String>,
+    /// Subscribed event types; `None` receives every event
+    pub event_types: Option<Vec<String>>,
+    /// Disabled webhooks receive no new events and their pending events are dead-lettered
+    pub enabled: bool,
+    pub disabled_reason: Option<String>,
+    pub disabled_at: Option<chrono::DateTime<chrono::Utc>>,
+    /// Events dead-lettered since the last successful delivery
+    pub consecutive_failures: i32,
+    /// First failed attempt since the last successful delivery
+    pub failing_since: Option<chrono::DateTime<chrono::Utc>>,
+    #[sqlx(flatten)]
+    pub retry_policy: RetryPolicyOverride,
+    /// Deliver strictly by `sequence`, one request at a time
+    pub ordered: bool,
+    /// Events per request in batch mode; `None` sends each event on its own
+    pub batch_size: Option<i32>,
+    /// `legacy`, `cloudevents_structured` or `cloudevents_binary`
+    pub payload_format: String,
+    /// Also sign with the deprecated `X-Signature: sha256=<hex>` header
+    pub legacy_signature: bool,
+    /// Custom header names with masked values
+    pub headers: Option<serde_json::Value>,
+    /// `{"username": ..., "password": "********"}` when basic auth is set
+    pub basic_auth: Option<serde_json::Value>,
+    /// Until when the secret replaced by the last rotation still signs deliveries
+    pub previous_secret_expires_at: Option<chrono::DateTime<chrono::Utc>>,
     pub created_at: chrono::DateTime<chrono::Utc>,
+    pub updated_at: chrono::DateTime<chrono::Utc>,
 }
 
 #[derive(Debug, Deserialize)]
 pub struct CreateWebhookRequest {
     pub account_id: Uuid,
     pub url: String,
+    pub description: Option<String>,
+    /// Event types to subscribe to (see `GET /api/webhooks/event-types`); omit for all events
+    pub event_types: Option<Vec<String>>,
+    pub retry_policy: Option<RetryPolicyOverride>,
+    #[serde(default)]
+    pub ordered: bool,
+    pub batch_size: Option<i32>,
+    /// Defaults to `legacy`
+    pub payload_format: Option<String>,
+    /// Deprecated `X-Signature` header, for receivers not yet verifying `X-Webhook-Signature`
+    #[serde(default)]
+    pub legacy_signature: bool,
+    /// Extra headers sent with every delivery, e.g. a gateway token; stored encrypted
+    pub headers: Option<HashMap<String, String>>,
+    /// Stored encrypted and sent as `Authorization: Basic`
+    pub basic_auth: Option<BasicAuth>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BasicAuth {
+    pub username: String,
+    pub password: String,
+}
+
+/// Per-webhook retry settings. Fields left `null` use the service-wide `WEBHOOK_RETRY_*` defaults.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, FromRow)]
+pub struct RetryPolicyOverride {
+    /// Attempts in total, including the first
+    #[sqlx(rename = "retry_max_attempts")]
+    pub max_attempts: Option<i32>,
+    /// Upper bound of the first retry delay; doubles per attempt
+    #[sqlx(rename = "retry_base_delay_secs")]
+    pub base_delay_secs: Option<i32>,
+    /// Largest delay between two attempts
+    #[sqlx(rename = "retry_max_delay_secs")]
+    pub max_delay_secs: Option<i32>,
+    /// No retry is scheduled later than this after the first attempt
+    #[sqlx(rename = "retry_deadline_secs")]
+    pub deadline_secs: Option<i32>,
+}
+
+/// `PATCH /api/webhooks/{id}`. Absent fields are left unchanged; `null` clears `description`,
+/// resets `event_types` to every event and `retry_policy` to the defaults.
+#[derive(Debug, Deserialize)]
+pub struct UpdateWebhookRequest {
+    pub url: Option<String>,
+    #[serde(default, deserialize_with = "present")]
+    pub description: Option<Option<String>>,
+    #[serde(default, deserialize_with = "present")]
+    pub event_types: Option<Option<Vec<String>>>,
+    /// Replaces the whole override; omitted fields fall back to the defaults
+    #[serde(default, deserialize_with = "present")]
+    pub retry_policy: Option<Option<RetryPolicyOverride>>,
+    pub ordered: Option<bool>,
+    /// `null` turns batch mode off
+    #[serde(default, deserialize_with = "present")]
+    pub batch_size: Option<Option<i32>>,
+    pub payload_format: Option<String>,
+    pub legacy_signature: Option<bool>,
+    /// Replaces every custom header; `null` removes them
+    #[serde(default, deserialize_with = "present")]
+    pub headers: Option<Option<HashMap<String, String>>>,
+    /// `null` removes basic auth
+    #[serde(default, deserialize_with = "present")]
+    pub basic_auth: Option<Option<BasicAuth>>,
+    /// `true` re-enables a disabled webhook and resets its failure counters
+    pub enabled: Option<bool>,
+}
+
+/// Distinguish a field sent as `null` (`Some(None)`) from an absent one (`None`, via `default`).
+fn present<'de, D, T>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    Option::<T>::deserialize(deserializer).map(Some)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RotateWebhookSecretRequest {
+    /// Seconds the old secret keeps signing; defaults to `WEBHOOK_SECRET_OVERLAP_SECS`
+    pub overlap_secs: Option<i64>,
+}
+
+/// Result of `POST /api/webhooks/{id}/test`.
+#[derive(Debug, Serialize)]
+pub struct WebhookTestResult {
+    /// Sent as `X-Webhook-Id`; ping events are not stored
+    pub event_id: Uuid,
+    pub success: bool,
+    pub response_status: Option<u16>,
+    /// First 4 KiB of the response body
+    pub response_body: Option<String>,
+    pub latency_ms: u128,
+    pub error_kind: Option<&'static str>,
+    pub error_message: Option<String>,
 }
 
 #[derive(Debug, Serialize, FromRow)]
 pub struct WebhookEvent {
     pub id: Uuid,
     pub webhook_id: Uuid,
-    pub txn_id: Uuid,
+    pub txn_id: Option<Uuid>,
+    pub event_type: String,
+    /// Position in the webhook's event stream, also sent in the payload
+    pub sequence: Option<i64>,
+    pub payload: Option<serde_json::Value>,
+    /// `pending`, `delivered` or `failed` (dead letter, no further retries)
+    pub status: String,
     pub delivered: bool,
     pub retry_count: i32,
     pub last_attempt: Option<chrono::DateTime<chrono::Utc>>,
+    /// When the dispatcher will next try this event; `None` once delivered or out of retries
+    pub next_attempt_at: Option<chrono::DateTime<chrono::Utc>>,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+}
+
+/// One logged delivery attempt of a webhook event.
+#[derive(Debug, Serialize, FromRow)]
+pub struct WebhookDeliveryAttempt {
+    pub id: Uuid,
+    pub event_id: Uuid,
+    pub attempt: i32,
+    pub request_headers: serde_json::Value,
+    pub response_status: Option<i32>,
+    /// First 4 KiB of the response body
+    pub response_body: Option<String>,
+    pub latency_ms: i32,
+    /// `dns`, `tls`, `timeout`, `connect`, `non_2xx` or `network`; absent on success
+    pub error_kind: Option<String>,
+    pub error_message: Option<String>,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct WebhookEventQuery {
+    pub status: Option<String>,
+    pub limit: Option<i64>,
+}
+
+/// Filter for a batch replay. A single-event replay ignores it.
+#[derive(Debug, Default, Deserialize)]
+pub struct ReplayWebhookEventsRequest {
+    pub webhook_id: Option<Uuid>,
+    /// Defaults to `failed`
+    pub status: Option<String>,
+    pub event_type: Option<String>,
+    pub created_after: Option<chrono::DateTime<chrono::Utc>>,
+    pub created_before: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ReplayWebhookEventsResponse {
+    pub replayed: usize,
+    pub event_ids: Vec<Uuid>,
+}
+
+/// `POST /api/webhooks/{id}/replay`: regenerate the transaction events emitted in `[from, to)`.
+#[derive(Debug, Deserialize)]
+pub struct WebhookReplayRequest {
+    pub from: chrono::DateTime<chrono::Utc>,
+    /// Defaults to now
+    pub to: Option<chrono::DateTime<chrono::Utc>>,
+    /// `transaction.created` and/or `transaction.reversed`; defaults to both, limited to the subscription
+    pub event_types: Option<Vec<String>>,
+}
+
+/// A backfill and its progress.
+#[derive(Debug, Serialize, FromRow)]
+pub struct WebhookReplayJob {
+    pub id: Uuid,
+    pub webhook_id: Uuid,
+    #[sqlx(rename = "range_from")]
+    pub from: chrono::DateTime<chrono::Utc>,
+    #[sqlx(rename = "range_to")]
+    pub to: chrono::DateTime<chrono::Utc>,
+    pub event_types: Vec<String>,
+    /// `running`, `completed` (everything enqueued) or `failed`
+    pub status: String,
+    pub total_events: i64,
+    pub enqueued_events: i64,
+    /// Enqueued events by delivery status
+    pub delivered_events: i64,
+    pub failed_events: i64,
+    pub error: Option<String>,
     pub created_at: chrono::DateTime<chrono::Utc>,
+    pub updated_at: chrono::DateTime<chrono::Utc>,
+    pub completed_at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+// ============================
+// Events
+// ============================
+
+/// An entry of the account's event log (`GET /api/events`).
+#[derive(Debug, Serialize, FromRow)]
+pub struct Event {
+    pub id: Uuid,
+    /// Position in the account's stream; pass it as `starting_after` to read on from here
+    pub sequence: i64,
+    pub event_type: String,
+    /// Same shape as a legacy webhook payload, without the per-webhook `sequence`
+    pub payload: serde_json::Value,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct EventQuery {
+    pub starting_after: Option<i64>,
+    /// Comma-separated event types
+    pub types: Option<String>,
+    pub limit: Option<i64>,
+}
+
+/// Cursor and filter of `GET /api/stream` and `GET /api/stream/ws`.
+#[derive(Debug, Deserialize)]
+pub struct StreamQuery {
+    pub starting_after: Option<i64>,
+    /// Comma-separated event types
+    pub types: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct EventPage {
+    pub events: Vec<Event>,
+    pub has_more: bool,
+    /// `starting_after` for the next request
+    pub next_cursor: i64,
 }
 
 // ============================
 // Webhook Payload
 // ============================
 
+/// Body of a webhook delivery. The affected resource is serialized under its own key, e.g.
+/// `{"event_type": "transaction.created", "transaction": {...}, "timestamp": "..."}`.
 #[derive(Debug, Serialize)]
 pub struct WebhookPayload {
     pub event_type: String,
-    pub transaction: Transaction,
+    #[serde(flatten)]
+    pub data: EventData,
     pub timestamp: chrono::DateTime<chrono::Utc>,
 }
 
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EventData {
+    Account(Account),
+    ApiKey(ApiKeyEventData),
+    Transaction(Transaction),
+    /// Serialized under `transaction` like a stored one, but without an id
+    #[serde(rename = "transaction")]
+    FailedTransaction(FailedTransactionEventData),
+    Webhook(WebhookEventData),
+}
+
+/// Rejected transaction as it appears in `transaction.failed` events. Nothing was stored, so it has no id.
+#[derive(Debug, Clone, Serialize)]
+pub struct FailedTransactionEventData {
+    pub from_account: Option<Uuid>,
+    pub to_account: Option<Uuid>,
+    pub amount: rust_decimal::Decimal,
+    pub txn_type: String,
+    pub status: String,
+    /// Why it was rejected, e.g. `insufficient_funds`
+    pub reason: String,
+}
+
+/// API key as it appears in `api_key.*` events (never includes secrets).
+#[derive(Debug, Clone, Serialize)]
+pub struct ApiKeyEventData {
+    pub id: Uuid,
+    pub account_id: Uuid,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    pub revoked_at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+/// Webhook as it appears in `webhook.*` events (never includes the secret).
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct WebhookEventData {
+    pub id: Uuid,
+    pub account_id: Uuid,
+    pub url: String,
+    pub description: Option<String>,
+    pub event_types: Option<Vec<String>>,
+    pub enabled: bool,
+    pub disabled_reason: Option<String>,
+    pub disabled_at: Option<chrono::DateTime<chrono::Utc>>,
+    pub consecutive_failures: i32,
+    pub failing_since: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+/// Entry of the webhook event catalog.
+#[derive(Debug, Serialize)]
+pub struct EventTypeInfo {
+    #[serde(rename = "type")]
+    pub event_type: &'static str,
+    pub description: &'static str,
+}
+
 // ============================
 // Error Types
 // ============================
@@ -0,0 +1,119 @@
+use dashmap::DashMap;
+use std::time::Instant;
+use uuid::Uuid;
+
+use super::{BoxFuture, Decision, Policy, RateLimitBackend};
+
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    tokens: f64,
+    updated_at: Instant,
+    policy: Policy,
+}
+
+impl Bucket {
+    fn new(policy: Policy, now: Instant) -> Self {
+        Self {
+            tokens: policy.capacity,
+            updated_at: now,
+            policy,
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated_at).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.policy.refill_per_sec).min(self.policy.capacity);
+        self.updated_at = now;
+    }
+
+    fn take(&mut self, cost: f64, now: Instant) -> Decision {
+        self.refill(now);
+        // A request costing more than the whole bucket could never pass otherwise
+        let cost = cost.min(self.policy.capacity);
+        let allowed = self.tokens >= cost;
+        if allowed {
+            self.tokens -= cost;
+        }
+        Decision::new(&self.policy, self.tokens, cost, allowed)
+    }
+}
+
+/// Buckets held in this process only; each replica enforces its own limit.
+#[derive(Default)]
+pub struct MemoryBackend {
+    buckets: DashMap<Uuid, Bucket>,
+}
+
+impl RateLimitBackend for MemoryBackend {
+    fn take(&self, api_key_id: Uuid, policy: Policy, cost: u32) -> BoxFuture<'_, anyhow::Result<Decision>> {
+        let now = Instant::now();
+        let mut bucket = self.buckets.entry(api_key_id).or_insert_with(|| Bucket::new(policy, now));
+        if bucket.policy != policy {
+            // Policy changed: keep the current fill level but respect the new capacity
+            bucket.refill(now);
+            bucket.policy = policy;
+            bucket.tokens = bucket.tokens.min(policy.capacity);
+        }
+        let decision = bucket.take(cost as f64, now);
+        Box::pin(async move { Ok(decision) })
+    }
+
+    fn inspect(&self, api_key_id: Uuid) -> BoxFuture<'_, anyhow::Result<Option<f64>>> {
+        let tokens = self.buckets.get_mut(&api_key_id).map(|mut bucket| {
+            bucket.refill(Instant::now());
+            bucket.tokens
+        });
+        Box::pin(async move { Ok(tokens) })
+    }
+
+    fn reset(&self, api_key_id: Uuid) -> BoxFuture<'_, anyhow::Result<()>> {
+        self.buckets.remove(&api_key_id);
+        Box::pin(async { Ok(()) })
+    }
+
+    fn evict_idle(&self) -> BoxFuture<'_, anyhow::Result<()>> {
+        let now = Instant::now();
+        self.buckets
+            .retain(|_, bucket| now.saturating_duration_since(bucket.updated_at) < bucket.policy.full_refill());
+        Box::pin(async { Ok(()) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn bucket_allows_burst_then_refills() {
+        let policy = Policy { capacity: 3.0, refill_per_sec: 1.0 };
+        let start = Instant::now();
+        let mut bucket = Bucket::new(policy, start);
+
+        for expected_remaining in [2, 1, 0] {
+            let decision = bucket.take(1.0, start);
+            assert!(decision.allowed);
+            assert_eq!(decision.remaining, expected_remaining);
+            assert_eq!(decision.limit, 3);
+        }
+
+        let denied = bucket.take(1.0, start);
+        assert!(!denied.allowed);
+        assert_eq!(denied.retry_after_secs, 1);
+
+        let later = bucket.take(2.0, start + Duration::from_secs(2));
+        assert!(later.allowed);
+        assert_eq!(later.remaining, 0);
+    }
+
+    #[test]
+    fn cost_is_capped_at_capacity() {
+        let policy = Policy { capacity: 2.0, refill_per_sec: 0.5 };
+        let now = Instant::now();
+        let mut bucket = Bucket::new(policy, now);
+        assert!(bucket.take(10.0, now).allowed);
+        let denied = bucket.take(10.0, now);
+        assert!(!denied.allowed);
+        assert_eq!(denied.retry_after_secs, 4);
+    }
+}
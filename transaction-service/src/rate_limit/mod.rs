@@ -0,0 +1,204 @@
+use once_cell::sync::{Lazy, OnceCell};
+use sqlx::{PgPool, Row};
+use std::collections::HashMap;
+use std::env;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::RwLock;
+use std::time::Duration;
+use uuid::Uuid;
+
+mod memory;
+mod postgres;
+
+pub use memory::MemoryBackend;
+pub use postgres::PostgresBackend;
+
+/// Token-bucket parameters: up to `capacity` tokens, refilled continuously at `refill_per_sec`.
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize)]
+pub struct Policy {
+    pub capacity: f64,
+    pub refill_per_sec: f64,
+}
+
+impl Policy {
+    /// Default policy derived from `RATE_LIMIT_PER_MIN` (burst of one minute's worth of requests).
+    pub fn default_from_env() -> Self {
+        let per_min: f64 = env::var("RATE_LIMIT_PER_MIN").ok().and_then(|v| v.parse().ok()).unwrap_or(60.0);
+        Self {
+            capacity: per_min,
+            refill_per_sec: per_min / 60.0,
+        }
+    }
+
+    /// Time for an empty bucket to refill completely.
+    fn full_refill(&self) -> Duration {
+        if self.refill_per_sec <= 0.0 {
+            return Duration::MAX;
+        }
+        Duration::from_secs_f64(self.capacity / self.refill_per_sec)
+    }
+}
+
+/// Outcome of charging a bucket, used for the `X-RateLimit-*` and `Retry-After` headers.
+#[derive(Debug, Clone, Copy)]
+pub struct Decision {
+    pub allowed: bool,
+    pub limit: u64,
+    pub remaining: u64,
+    /// Seconds until the request would be allowed (0 when allowed)
+    pub retry_after_secs: u64,
+}
+
+impl Decision {
+    fn new(policy: &Policy, tokens: f64, cost: f64, allowed: bool) -> Self {
+        let retry_after_secs = if allowed || policy.refill_per_sec <= 0.0 {
+            0
+        } else {
+            ((cost - tokens) / policy.refill_per_sec).ceil() as u64
+        };
+        Self {
+            allowed,
+            limit: policy.capacity as u64,
+            remaining: tokens.max(0.0).floor() as u64,
+            retry_after_secs,
+        }
+    }
+
+    /// Decision used when the backend is unavailable: requests are let through rather than
+    /// failing every authenticated call.
+    fn fail_open(policy: &Policy) -> Self {
+        Self::new(policy, policy.capacity, 0.0, true)
+    }
+}
+
+type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// Storage for token buckets.
+pub trait RateLimitBackend: Send + Sync {
+    /// Refill the key's bucket under `policy` and try to take `cost` tokens from it.
+    fn take(&self, api_key_id: Uuid, policy: Policy, cost: u32) -> BoxFuture<'_, anyhow::Result<Decision>>;
+    /// Current fill level of a key's bucket, if it has one.
+    fn inspect(&self, api_key_id: Uuid) -> BoxFuture<'_, anyhow::Result<Option<f64>>>;
+    /// Forget a key's bucket so it starts full again.
+    fn reset(&self, api_key_id: Uuid) -> BoxFuture<'_, anyhow::Result<()>>;
+    /// Drop buckets that have refilled completely; they are indistinguishable from new ones.
+    fn evict_idle(&self) -> BoxFuture<'_, anyhow::Result<()>>;
+}
+
+static BACKEND: OnceCell<Box<dyn RateLimitBackend>> = OnceCell::new();
+
+fn backend() -> &'static dyn RateLimitBackend {
+    BACKEND.get_or_init(|| Box::new(MemoryBackend::default())).as_ref()
+}
+
+/// Per-key policy overrides and per-route costs, loaded from the database.
+#[derive(Debug, Default)]
+struct LimitConfig {
+    policies: HashMap<Uuid, Policy>,
+    route_costs: HashMap<(String, String), u32>,
+}
+
+static CONFIG: Lazy<RwLock<LimitConfig>> = Lazy::new(|| RwLock::new(LimitConfig::default()));
+
+/// Policy for a key: its `rate_limit_policies` row if any, otherwise the env default.
+pub fn policy_for(api_key_id: Uuid) -> (Policy, bool) {
+    let config = CONFIG.read().expect("rate limit config lock poisoned");
+    match config.policies.get(&api_key_id) {
+        Some(policy) => (*policy, true),
+        None => (Policy::default_from_env(), false),
+    }
+}
+
+/// Tokens charged for `method` on a matched route pattern (e.g. `POST /api/transactions`); defaults to 1.
+pub fn route_cost(method: &str, route: &str) -> u32 {
+    let config = CONFIG.read().expect("rate limit config lock poisoned");
+    config
+        .route_costs
+        .get(&(method.to_string(), route.to_string()))
+        .copied()
+        .unwrap_or(1)
+}
+
+/// Select the backend from `RATE_LIMIT_BACKEND` (`memory`, the default, or `postgres`) and load
+/// the limit configuration.
+pub async fn init(pool: &PgPool) -> anyhow::Result<()> {
+    let backend: Box<dyn RateLimitBackend> = match env::var("RATE_LIMIT_BACKEND").as_deref() {
+        Ok("postgres") => Box::new(PostgresBackend::new(pool.clone())),
+        Ok("memory") | Err(_) => Box::new(MemoryBackend::default()),
+        Ok(other) => anyhow::bail!("unknown RATE_LIMIT_BACKEND '{}'", other),
+    };
+    if BACKEND.set(backend).is_err() {
+        anyhow::bail!("rate limit backend already initialised");
+    }
+    reload_config(pool).await?;
+    Ok(())
+}
+
+/// Charge `cost` tokens to a key's bucket.
+pub async fn take(api_key_id: Uuid, cost: u32) -> Decision {
+    let (policy, _) = policy_for(api_key_id);
+    match backend().take(api_key_id, policy, cost).await {
+        Ok(decision) => decision,
+        Err(e) => {
+            tracing::error!("Rate limit backend unavailable, allowing request: {}", e);
+            Decision::fail_open(&policy)
+        }
+    }
+}
+
+/// Current fill level of a key's bucket, if it has one.
+pub async fn inspect(api_key_id: Uuid) -> anyhow::Result<Option<f64>> {
+    backend().inspect(api_key_id).await
+}
+
+/// Forget a key's bucket so it starts full again.
+pub async fn reset(api_key_id: Uuid) -> anyhow::Result<()> {
+    backend().reset(api_key_id).await
+}
+
+/// Reload policies and route costs from the database.
+pub async fn reload_config(pool: &PgPool) -> Result<(), sqlx::Error> {
+    let policy_rows = sqlx::query("SELECT api_key_id, capacity, refill_per_sec FROM rate_limit_policies")
+        .fetch_all(pool)
+        .await?;
+    let cost_rows = sqlx::query("SELECT method, route, cost FROM rate_limit_route_costs")
+        .fetch_all(pool)
+        .await?;
+
+    let mut config = LimitConfig::default();
+    for row in policy_rows {
+        let capacity: i32 = row.get("capacity");
+        config.policies.insert(
+            row.get("api_key_id"),
+            Policy {
+                capacity: capacity as f64,
+                refill_per_sec: row.get("refill_per_sec"),
+            },
+        );
+    }
+    for row in cost_rows {
+        let method: String = row.get("method");
+        let cost: i32 = row.get("cost");
+        config
+            .route_costs
+            .insert((method.to_uppercase(), row.get("route")), cost.max(0) as u32);
+    }
+    *CONFIG.write().expect("rate limit config lock poisoned") = config;
+    Ok(())
+}
+
+/// Background task: refresh configuration and evict idle buckets.
+pub async fn maintain(pool: PgPool) {
+    let secs = env::var("RATE_LIMIT_CONFIG_REFRESH_SECS").ok().and_then(|v| v.parse().ok()).unwrap_or(30);
+    let mut interval = tokio::time::interval(Duration::from_secs(secs));
+    loop {
+        interval.tick().await;
+        if let Err(e) = reload_config(&pool).await {
+            tracing::error!("Failed to reload rate limit configuration: {}", e);
+        }
+        if let Err(e) = backend().evict_idle().await {
+            tracing::error!("Failed to evict idle rate limit buckets: {}", e);
+        }
+    }
+}
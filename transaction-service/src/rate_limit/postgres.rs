@@ -0,0 +1,89 @@
+use sqlx::{PgPool, Row};
+use uuid::Uuid;
+
+use super::{BoxFuture, Decision, Policy, RateLimitBackend};
+
+/// Buckets stored in `rate_limit_buckets`, shared by every replica.
+///
+/// Each charge is a single upsert, so the row lock serialises concurrent requests for the same key.
+pub struct PostgresBackend {
+    pool: PgPool,
+}
+
+impl PostgresBackend {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+impl RateLimitBackend for PostgresBackend {
+    fn take(&self, api_key_id: Uuid, policy: Policy, cost: u32) -> BoxFuture<'_, anyhow::Result<Decision>> {
+        Box::pin(async move {
+            // A request costing more than the whole bucket could never pass otherwise
+            let cost = (cost as f64).min(policy.capacity);
+            let row = sqlx::query(
+                r#"
+                INSERT INTO rate_limit_buckets AS b (api_key_id, tokens, capacity, refill_per_sec, allowed, updated_at)
+                VALUES ($1, $2 - $4, $2, $3, TRUE, NOW())
+                ON CONFLICT (api_key_id) DO UPDATE SET
+                    tokens = CASE
+                        WHEN LEAST($2, b.tokens + GREATEST(EXTRACT(EPOCH FROM NOW() - b.updated_at)::float8, 0) * $3) >= $4
+                        THEN LEAST($2, b.tokens + GREATEST(EXTRACT(EPOCH FROM NOW() - b.updated_at)::float8, 0) * $3) - $4
+                        ELSE LEAST($2, b.tokens + GREATEST(EXTRACT(EPOCH FROM NOW() - b.updated_at)::float8, 0) * $3)
+                    END,
+                    allowed = LEAST($2, b.tokens + GREATEST(EXTRACT(EPOCH FROM NOW() - b.updated_at)::float8, 0) * $3) >= $4,
+                    capacity = $2,
+                    refill_per_sec = $3,
+                    updated_at = NOW()
+                RETURNING b.tokens, b.allowed
+                "#,
+            )
+            .bind(api_key_id)
+            .bind(policy.capacity)
+            .bind(policy.refill_per_sec)
+            .bind(cost)
+            .fetch_one(&self.pool)
+            .await?;
+            Ok(Decision::new(&policy, row.get("tokens"), cost, row.get("allowed")))
+        })
+    }
+
+    fn inspect(&self, api_key_id: Uuid) -> BoxFuture<'_, anyhow::Result<Option<f64>>> {
+        Box::pin(async move {
+            let tokens = sqlx::query_scalar(
+                r#"
+                SELECT LEAST(capacity, tokens + GREATEST(EXTRACT(EPOCH FROM NOW() - updated_at)::float8, 0) * refill_per_sec)
+                FROM rate_limit_buckets WHERE api_key_id = $1
+                "#,
+            )
+            .bind(api_key_id)
+            .fetch_optional(&self.pool)
+            .await?;
+            Ok(tokens)
+        })
+    }
+
+    fn reset(&self, api_key_id: Uuid) -> BoxFuture<'_, anyhow::Result<()>> {
+        Box::pin(async move {
+            sqlx::query("DELETE FROM rate_limit_buckets WHERE api_key_id = $1")
+                .bind(api_key_id)
+                .execute(&self.pool)
+                .await?;
+            Ok(())
+        })
+    }
+
+    fn evict_idle(&self) -> BoxFuture<'_, anyhow::Result<()>> {
+        Box::pin(async move {
+            sqlx::query(
+                r#"
+                DELETE FROM rate_limit_buckets
+                WHERE tokens + EXTRACT(EPOCH FROM NOW() - updated_at)::float8 * refill_per_sec >= capacity
+                "#,
+            )
+            .execute(&self.pool)
+            .await?;
+            Ok(())
+        })
+    }
+}
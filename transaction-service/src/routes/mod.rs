@@ -1,18 +1,19 @@
 use axum::{
     middleware,
-    routing::{get, post},
+    routing::{delete, get, post, put},
     Router,
 };
 use sqlx::PgPool;
 
 use crate::handlers::*;
-use crate::middleware::auth_middleware;
+use crate::middleware::{admin_middleware, auth_middleware, failed_auth_guard, public_route_guard};
 
 pub fn routes(pool: PgPool) -> Router {
     // Public routes (no authentication required)
     let public_routes = Router::new()
         .route("/accounts", post(create_account))
-        .route("/api-keys", post(create_api_key));
+        .route("/api-keys", post(create_api_key))
+        .route_layer(middleware::from_fn_with_state(pool.clone(), public_route_guard));
 
     // Protected routes (require API key authentication)
     let protected_routes = Router::new()
@@ -21,12 +22,50 @@ pub fn routes(pool: PgPool) -> Router {
         .route("/accounts/{id}/balance", get(get_account_balance))
         .route("/transactions", post(create_transaction).get(list_transactions))
         .route("/transactions/{id}", get(get_transaction))
+        .route("/transactions/{id}/reverse", post(reverse_transaction))
+        .route("/api-keys/{id}", delete(revoke_api_key))
+        .route("/events", get(list_events))
+        .route("/stream", get(stream_events))
+        .route("/stream/ws", get(stream_events_ws))
         .route("/webhooks", post(create_webhook).get(list_webhooks))
-        .route_layer(middleware::from_fn_with_state(pool.clone(), auth_middleware));
+        .route("/webhooks/event-types", get(list_event_types))
+        .route("/webhooks/{id}", get(get_webhook).patch(update_webhook).delete(delete_webhook))
+        .route("/webhooks/{id}/events", get(list_webhook_events))
+        .route("/webhooks/{id}/replay", post(replay_webhook_history))
+        .route("/webhooks/{id}/replays/{job_id}", get(get_webhook_replay))
+        .route("/webhooks/{id}/rotate-secret", post(rotate_webhook_secret))
+        .route("/webhooks/{id}/test", post(test_webhook))
+        .route("/webhook-events/replay", post(replay_webhook_events))
+        .route("/webhook-events/{id}/replay", post(replay_webhook_event))
+        .route("/webhook-events/{id}/attempts", get(list_webhook_event_attempts))
+        .route_layer(middleware::from_fn_with_state(pool.clone(), auth_middleware))
+        .route_layer(middleware::from_fn_with_state(pool.clone(), failed_auth_guard));
+
+    // Operator routes (require x-admin-token)
+    let admin_routes = Router::new()
+        .route("/admin/jwt-keys/rotate", post(rotate_jwt_keys))
+        .route("/admin/api-keys/hash-report", get(api_key_hash_report))
+        .route("/admin/rate-limits/routes", put(set_route_cost))
+        .route("/admin/rate-limits/{key_id}", get(get_rate_limit).delete(reset_rate_limit))
+        .route("/admin/rate-limits/{key_id}/policy", put(set_rate_limit_policy))
+        .route("/admin/lockouts", get(list_lockouts))
+        .route("/admin/lockouts/{ip}", delete(clear_lockout))
+        .route("/admin/transactions/{id}/reverse", post(admin_reverse_transaction))
+        .route_layer(middleware::from_fn(admin_middleware));
 
     // Combine routes
     Router::new()
         .merge(public_routes)
         .merge(protected_routes)
+        .merge(admin_routes)
+        .with_state(pool)
+}
+
+/// OAuth2 token and key discovery endpoints, served outside `/api`.
+pub fn oauth_routes(pool: PgPool) -> Router {
+    Router::new()
+        .route("/oauth/token", post(issue_token))
+        .route_layer(middleware::from_fn_with_state(pool.clone(), failed_auth_guard))
+        .route("/.well-known/jwks.json", get(jwks))
         .with_state(pool)
 }
@@ -0,0 +1,180 @@
+//! Envelope encryption for secrets stored in the database (webhook signing secrets, JWT signing keys).
+//!
+//! Each value is encrypted with its own random data key (AES-256-GCM), and the data key is wrapped
+//! with an application key from `SECRET_ENCRYPTION_KEYS`. The sealed form is
+//! `<key id>.<nonce + wrapped data key>.<nonce + ciphertext>` (URL-safe base64), so old values stay
+//! readable after the active key changes and can be re-wrapped without touching the ciphertext.
+
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use anyhow::{anyhow, bail, Context};
+use base64::{engine::general_purpose::STANDARD, engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use once_cell::sync::OnceCell;
+use std::collections::HashMap;
+use std::env;
+
+const NONCE_LEN: usize = 12;
+
+static KEYRING: OnceCell<KeyRing> = OnceCell::new();
+
+/// Application keys by id, plus the one new values are sealed with.
+pub struct KeyRing {
+    active: String,
+    keys: HashMap<String, Aes256Gcm>,
+}
+
+impl KeyRing {
+    /// Parse `<id>:<base64 32-byte key>` entries separated by commas.
+    pub fn parse(spec: &str, active: Option<&str>) -> anyhow::Result<Self> {
+        let mut keys = HashMap::new();
+        let mut first = None;
+        for entry in spec.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            let (id, key) = entry.split_once(':').ok_or_else(|| anyhow!("expected <id>:<base64 key>, got {:?}", entry))?;
+            if id.is_empty() || id.contains('.') {
+                bail!("invalid key id {:?}", id);
+            }
+            let key = STANDARD.decode(key).with_context(|| format!("key {} is not valid base64", id))?;
+            if key.len() != 32 {
+                bail!("key {} must be 32 bytes, got {}", id, key.len());
+            }
+            keys.insert(id.to_string(), Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key)));
+            first.get_or_insert(id.to_string());
+        }
+        let active = match active {
+            Some(id) => id.to_string(),
+            None => first.ok_or_else(|| anyhow!("no keys configured"))?,
+        };
+        if !keys.contains_key(&active) {
+            bail!("active key {} is not configured", active);
+        }
+        Ok(Self { active, keys })
+    }
+
+    pub fn seal(&self, plaintext: &str) -> String {
+        let data_key = Aes256Gcm::generate_key(OsRng);
+        let nonce = Aes256Gcm::generate_nonce(OsRng);
+        let mut ciphertext = nonce.to_vec();
+        ciphertext.extend(
+            Aes256Gcm::new(&data_key)
+                .encrypt(&nonce, plaintext.as_bytes())
+                .expect("AES-GCM encryption cannot fail for in-memory buffers"),
+        );
+        format!("{}.{}.{}", self.active, self.wrap(&self.active, &data_key), URL_SAFE_NO_PAD.encode(ciphertext))
+    }
+
+    pub fn open(&self, sealed: &str) -> anyhow::Result<String> {
+        let (key_id, wrapped, ciphertext) = split(sealed)?;
+        let data_key = self.unwrap(key_id, wrapped)?;
+        let plaintext = decrypt(&Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&data_key)), ciphertext)?;
+        String::from_utf8(plaintext).context("sealed value is not UTF-8")
+    }
+
+    /// Re-wrap the data key of a value sealed under another key with the active key.
+    /// Returns `None` if it already uses the active key.
+    pub fn rewrap(&self, sealed: &str) -> anyhow::Result<Option<String>> {
+        let (key_id, wrapped, ciphertext) = split(sealed)?;
+        if key_id == self.active {
+            return Ok(None);
+        }
+        let data_key = self.unwrap(key_id, wrapped)?;
+        Ok(Some(format!(
+            "{}.{}.{}",
+            self.active,
+            self.wrap(&self.active, Key::<Aes256Gcm>::from_slice(&data_key)),
+            ciphertext
+        )))
+    }
+
+    fn wrap(&self, key_id: &str, data_key: &Key<Aes256Gcm>) -> String {
+        let nonce = Aes256Gcm::generate_nonce(OsRng);
+        let mut wrapped = nonce.to_vec();
+        wrapped.extend(
+            self.keys[key_id]
+                .encrypt(&nonce, data_key.as_slice())
+                .expect("AES-GCM encryption cannot fail for in-memory buffers"),
+        );
+        URL_SAFE_NO_PAD.encode(wrapped)
+    }
+
+    fn unwrap(&self, key_id: &str, wrapped: &str) -> anyhow::Result<Vec<u8>> {
+        let key = self.keys.get(key_id).ok_or_else(|| anyhow!("key {} is not configured", key_id))?;
+        decrypt(key, wrapped)
+    }
+}
+
+fn split(sealed: &str) -> anyhow::Result<(&str, &str, &str)> {
+    let mut parts = sealed.splitn(3, '.');
+    match (parts.next(), parts.next(), parts.next()) {
+        (Some(key_id), Some(wrapped), Some(ciphertext)) => Ok((key_id, wrapped, ciphertext)),
+        _ => bail!("malformed sealed value"),
+    }
+}
+
+fn decrypt(cipher: &Aes256Gcm, encoded: &str) -> anyhow::Result<Vec<u8>> {
+    let bytes = URL_SAFE_NO_PAD.decode(encoded).context("sealed value is not valid base64")?;
+    if bytes.len() < NONCE_LEN {
+        bail!("sealed value is too short");
+    }
+    let (nonce, ciphertext) = bytes.split_at(NONCE_LEN);
+    cipher
+        .decrypt(Nonce::from_slice(nonce), ciphertext)
+        .map_err(|_| anyhow!("sealed value failed authentication"))
+}
+
+/// Load the key ring from `SECRET_ENCRYPTION_KEYS` and `SECRET_ENCRYPTION_KEY_ID` (defaults to the first key).
+pub fn init() -> anyhow::Result<()> {
+    let spec = env::var("SECRET_ENCRYPTION_KEYS").context("SECRET_ENCRYPTION_KEYS must be set")?;
+    let active = env::var("SECRET_ENCRYPTION_KEY_ID").ok().filter(|id| !id.is_empty());
+    let ring = KeyRing::parse(&spec, active.as_deref()).context("invalid SECRET_ENCRYPTION_KEYS")?;
+    KEYRING.set(ring).map_err(|_| anyhow!("secret key ring already initialised"))
+}
+
+fn keyring() -> &'static KeyRing {
+    KEYRING.get().expect("secrets::init must run at startup")
+}
+
+/// Id of the key new values are sealed with.
+pub fn active_key_id() -> &'static str {
+    &keyring().active
+}
+
+pub fn seal(plaintext: &str) -> String {
+    keyring().seal(plaintext)
+}
+
+pub fn open(sealed: &str) -> anyhow::Result<String> {
+    keyring().open(sealed)
+}
+
+pub fn rewrap(sealed: &str) -> anyhow::Result<Option<String>> {
+    keyring().rewrap(sealed)
+}
+
+/// Install a fixed key ring for tests that go through the functions above; later calls keep it.
+#[cfg(test)]
+pub fn init_for_tests() {
+    let _ = KEYRING.set(KeyRing::parse("test:AgICAgICAgICAgICAgICAgICAgICAgICAgICAgICAgI=", None).expect("valid test key"));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OLD: &str = "old:AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=";
+    const NEW: &str = "new:AQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQE=";
+
+    #[test]
+    fn seals_and_rewraps_under_a_new_key() {
+        let old = KeyRing::parse(OLD, None).unwrap();
+        let sealed = old.seal("whsec");
+        assert!(sealed.starts_with("old."));
+        assert_eq!(old.open(&sealed).unwrap(), "whsec");
+        assert!(old.rewrap(&sealed).unwrap().is_none());
+
+        let rotated = KeyRing::parse(&format!("{},{}", NEW, OLD), None).unwrap();
+        let rewrapped = rotated.rewrap(&sealed).unwrap().unwrap();
+        assert!(rewrapped.starts_with("new."));
+        assert_eq!(rotated.open(&rewrapped).unwrap(), "whsec");
+        assert!(old.open(&rewrapped).is_err());
+    }
+}
@@ -0,0 +1,227 @@
+//! Live event streams over Server-Sent Events (`GET /api/stream`) and WebSocket (`GET /api/stream/ws`).
+//!
+//! A stream is a cursor over the account's event log (`events`). It reads everything after the
+//! cursor, then waits for `events::listen` to announce a commit for the account and reads again.
+//! Events are always read from the table, never from the notification, so a missed or coalesced
+//! notification only delays an event, and a reconnecting client resumes from the last `sequence` it
+//! saw without gaps or duplicates.
+
+use axum::extract::ws::{CloseFrame, Message, WebSocket};
+use axum::response::sse;
+use futures_util::stream::{self, Stream};
+use sqlx::PgPool;
+use std::collections::VecDeque;
+use std::convert::Infallible;
+use std::time::Duration;
+use tokio::sync::broadcast::{self, error::RecvError};
+use uuid::Uuid;
+
+use crate::events::{self, ListError};
+use crate::models::Event;
+
+/// Events read per query.
+const PAGE_SIZE: i64 = 100;
+
+/// Re-read the log this often even without a notification.
+const RECHECK: Duration = Duration::from_secs(30);
+
+/// Interval between WebSocket pings.
+const PING_INTERVAL: Duration = Duration::from_secs(15);
+
+/// One client's position in an account's event log.
+pub struct Feed {
+    pool: PgPool,
+    account_id: Uuid,
+    types: Option<Vec<String>>,
+    cursor: i64,
+    buffered: VecDeque<Event>,
+    /// Read again before waiting for a notification
+    stale: bool,
+    notifications: broadcast::Receiver<Uuid>,
+}
+
+impl Feed {
+    /// Start after sequence `starting_after`, or at the newest event if `None`. Fails up front if the
+    /// cursor is expired or ahead of the log, so the error can still be an HTTP status.
+    pub async fn open(
+        pool: PgPool,
+        account_id: Uuid,
+        starting_after: Option<i64>,
+        types: Option<Vec<String>>,
+    ) -> Result<Self, ListError> {
+        // Subscribe before the first read so nothing committed in between goes unannounced
+        let notifications = events::subscribe();
+        let mut feed = Feed {
+            cursor: 0,
+            buffered: VecDeque::new(),
+            stale: false,
+            notifications,
+            pool,
+            account_id,
+            types,
+        };
+        match starting_after {
+            Some(cursor) => {
+                feed.cursor = cursor;
+                feed.fill().await?;
+            }
+            None => feed.cursor = events::head(&feed.pool, account_id).await?,
+        }
+        Ok(feed)
+    }
+
+    async fn fill(&mut self) -> Result<(), ListError> {
+        let page = events::list(&self.pool, self.account_id, Some(self.cursor), self.types.as_deref(), PAGE_SIZE).await?;
+        self.buffered.extend(page.events);
+        self.cursor = page.next_cursor;
+        self.stale = page.has_more;
+        Ok(())
+    }
+
+    /// The next event, waiting as long as it takes. Cancel-safe: state only changes after each await.
+    pub async fn next(&mut self) -> Result<Event, ListError> {
+        loop {
+            if let Some(event) = self.buffered.pop_front() {
+                return Ok(event);
+            }
+            if self.stale {
+                self.fill().await?;
+                continue;
+            }
+            match tokio::time::timeout(RECHECK, self.notifications.recv()).await {
+                Ok(Ok(account_id)) if account_id != self.account_id && !account_id.is_nil() => {}
+                // Our account, a possible miss (nil, lagged) or the recheck timeout
+                Ok(Ok(_)) | Ok(Err(RecvError::Lagged(_))) | Err(_) => self.stale = true,
+                // The sender lives in a static and is never dropped
+                Ok(Err(RecvError::Closed)) => unreachable!("event notifications closed"),
+            }
+        }
+    }
+}
+
+/// SSE events named after the event type, with the sequence as the SSE id so a reconnecting
+/// `EventSource` resumes via `Last-Event-ID`. A failure ends the stream with an `error` event.
+pub fn into_sse(feed: Feed) -> impl Stream<Item = Result<sse::Event, Infallible>> {
+    stream::unfold(Some(feed), |feed| async move {
+        let mut feed = feed?;
+        let (event, feed) = match feed.next().await {
+            Ok(event) => (
+                sse::Event::default().id(event.sequence.to_string()).event(event.event_type.as_str()).json_data(&event),
+                Some(feed),
+            ),
+            Err(e) => (sse::Event::default().event("error").json_data(e.into_error().1), None),
+        };
+        // Serializing plain data cannot fail
+        Some((Ok(event.unwrap_or_default()), feed))
+    })
+}
+
+/// Send events as JSON text messages until the client goes away. Incoming messages other than
+/// close are ignored. A failure is sent as an error message, followed by a close frame.
+pub async fn serve_socket(mut socket: WebSocket, mut feed: Feed) {
+    let mut ping = tokio::time::interval(PING_INTERVAL);
+    ping.tick().await;
+    loop {
+        tokio::select! {
+            event = feed.next() => {
+                let message = match event {
+                    Ok(event) => serde_json::to_string(&event),
+                    Err(e) => {
+                        if let Ok(error) = serde_json::to_string(&e.into_error().1) {
+                            let _ = socket.send(Message::Text(error.into())).await;
+                        }
+                        let close = CloseFrame { code: 1011, reason: "stream ended".into() };
+                        let _ = socket.send(Message::Close(Some(close))).await;
+                        return;
+                    }
+                };
+                let Ok(message) = message else { continue };
+                if socket.send(Message::Text(message.into())).await.is_err() {
+                    return;
+                }
+            }
+            incoming = socket.recv() => match incoming {
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => return,
+                Some(Ok(_)) => {}
+            },
+            _ = ping.tick() => {
+                if socket.send(Message::Ping(Default::default())).await.is_err() {
+                    return;
+                }
+            }
+        }
+    }
+}
+
+/// Tests against Postgres; see `webhooks::tests` for how to run them.
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{Account, EventData, WebhookPayload};
+    use crate::webhooks::tests::account;
+
+    /// Longest a test waits for an event that should arrive.
+    const ARRIVAL: Duration = Duration::from_secs(5);
+
+    async fn record(pool: &PgPool, account: &Account, event_type: &str) {
+        let payload = WebhookPayload {
+            event_type: event_type.to_string(),
+            data: EventData::Account(account.clone()),
+            timestamp: chrono::Utc::now(),
+        };
+        let mut tx = pool.begin().await.unwrap();
+        events::record(&mut tx, &[account.id], event_type, &payload).await.unwrap();
+        tx.commit().await.unwrap();
+    }
+
+    async fn next_sequence(feed: &mut Feed) -> i64 {
+        tokio::time::timeout(ARRIVAL, feed.next()).await.expect("event arrives").unwrap().sequence
+    }
+
+    #[sqlx::test]
+    #[ignore]
+    async fn resumes_after_the_cursor_then_follows_new_commits(pool: PgPool) {
+        tokio::spawn(events::listen(pool.clone()));
+        let owner = account(&pool).await;
+        for _ in 0..3 {
+            record(&pool, &owner, "account.updated").await;
+        }
+
+        let mut feed = Feed::open(pool.clone(), owner.id, Some(1), None).await.unwrap();
+        assert_eq!(next_sequence(&mut feed).await, 2);
+        assert_eq!(next_sequence(&mut feed).await, 3);
+        assert!(tokio::time::timeout(Duration::from_millis(200), feed.next()).await.is_err());
+
+        // Another account's commit does not show up; ours does
+        let other = account(&pool).await;
+        record(&pool, &other, "account.updated").await;
+        record(&pool, &owner, "account.updated").await;
+        assert_eq!(next_sequence(&mut feed).await, 4);
+    }
+
+    #[sqlx::test]
+    #[ignore]
+    async fn starts_at_the_head_and_skips_filtered_types(pool: PgPool) {
+        tokio::spawn(events::listen(pool.clone()));
+        let owner = account(&pool).await;
+        record(&pool, &owner, "api_key.created").await;
+
+        let mut feed = Feed::open(pool.clone(), owner.id, None, Some(vec!["api_key.created".to_string()])).await.unwrap();
+        record(&pool, &owner, "account.updated").await;
+        record(&pool, &owner, "api_key.created").await;
+        assert_eq!(next_sequence(&mut feed).await, 3);
+    }
+
+    #[sqlx::test]
+    #[ignore]
+    async fn refuses_a_cursor_behind_retention_up_front(pool: PgPool) {
+        let owner = account(&pool).await;
+        for _ in 0..3 {
+            record(&pool, &owner, "account.updated").await;
+        }
+        sqlx::query("DELETE FROM events WHERE sequence = 1").execute(&pool).await.unwrap();
+
+        assert!(matches!(Feed::open(pool.clone(), owner.id, Some(0), None).await, Err(ListError::Expired)));
+        assert!(matches!(Feed::open(pool.clone(), owner.id, Some(9), None).await, Err(ListError::Ahead)));
+    }
+}
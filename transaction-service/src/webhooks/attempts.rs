@@ -0,0 +1,197 @@
+//! Per-attempt delivery log (`webhook_delivery_attempts`), kept so customers can debug their endpoints.
+
+use reqwest::header::{HeaderMap, RETRY_AFTER};
+use sqlx::types::Json;
+use sqlx::PgConnection;
+use std::collections::BTreeMap;
+use std::error::Error as _;
+use std::time::Duration;
+use uuid::Uuid;
+
+use super::ssrf::Blocked;
+
+/// Bytes of the response body kept per attempt.
+pub const MAX_RESPONSE_BODY_BYTES: usize = 4096;
+
+/// What happened on one delivery attempt.
+#[derive(Debug, Default)]
+pub struct Outcome {
+    pub response_status: Option<u16>,
+    pub response_body: Option<String>,
+    /// `blocked`, `dns`, `tls`, `timeout`, `connect`, `non_2xx` or `network`; `None` on success
+    pub error_kind: Option<&'static str>,
+    pub error_message: Option<String>,
+    /// `Retry-After` of a 429 or 503 response, in seconds from now
+    pub retry_after_secs: Option<f64>,
+}
+
+impl Outcome {
+    pub fn succeeded(&self) -> bool {
+        self.error_kind.is_none()
+    }
+
+    /// Build the outcome of a request that got a response, reading at most `MAX_RESPONSE_BODY_BYTES` of the body.
+    pub async fn from_response(mut response: reqwest::Response) -> Self {
+        let status = response.status();
+        let retry_after_secs = match status.as_u16() {
+            429 | 503 => response.headers().get(RETRY_AFTER).and_then(|v| v.to_str().ok()).and_then(parse_retry_after),
+            _ => None,
+        };
+        let mut body = Vec::new();
+        while body.len() < MAX_RESPONSE_BODY_BYTES {
+            match response.chunk().await {
+                Ok(Some(chunk)) => body.extend_from_slice(&chunk),
+                Ok(None) | Err(_) => break,
+            }
+        }
+        body.truncate(MAX_RESPONSE_BODY_BYTES);
+
+        let success = status.is_success();
+        Self {
+            response_status: Some(status.as_u16()),
+            response_body: Some(String::from_utf8_lossy(&body).into_owned()),
+            error_kind: (!success).then_some("non_2xx"),
+            error_message: (!success).then(|| format!("endpoint responded with {}", status)),
+            retry_after_secs,
+        }
+    }
+
+    /// The target was refused by the SSRF checks before any request was sent.
+    pub fn blocked(blocked: &Blocked) -> Self {
+        Self {
+            error_kind: Some("blocked"),
+            error_message: Some(blocked.to_string()),
+            ..Self::default()
+        }
+    }
+
+    pub fn from_error(error: &reqwest::Error) -> Self {
+        Self {
+            error_kind: Some(classify(error)),
+            error_message: Some(error_chain(error)),
+            ..Self::default()
+        }
+    }
+}
+
+/// `Retry-After` is either delay-seconds or an HTTP date.
+fn parse_retry_after(value: &str) -> Option<f64> {
+    let value = value.trim();
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(secs as f64);
+    }
+    let at = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+    Some((at.timestamp_millis() - chrono::Utc::now().timestamp_millis()).max(0) as f64 / 1000.0)
+}
+
+/// Full error message including its sources, e.g. "error sending request: dns error: ...".
+fn error_chain(error: &reqwest::Error) -> String {
+    let mut message = error.to_string();
+    let mut source = error.source();
+    while let Some(cause) = source {
+        message.push_str(": ");
+        message.push_str(&cause.to_string());
+        source = cause.source();
+    }
+    message
+}
+
+/// Map a transport error to a coarse kind. reqwest does not expose DNS or TLS failures as types,
+/// so those are recognised from the error chain.
+fn classify(error: &reqwest::Error) -> &'static str {
+    if error.is_timeout() {
+        return "timeout";
+    }
+    let mut source = error.source();
+    while let Some(cause) = source {
+        if cause.is::<Blocked>() {
+            return "blocked";
+        }
+        source = cause.source();
+    }
+    let chain = error_chain(error).to_lowercase();
+    if chain.contains("dns error") || chain.contains("failed to lookup address") {
+        "dns"
+    } else if ["tls", "ssl", "certificate", "handshake"].iter().any(|needle| chain.contains(needle)) {
+        "tls"
+    } else if error.is_connect() {
+        "connect"
+    } else {
+        "network"
+    }
+}
+
+/// Append an attempt to the log.
+pub async fn record(
+    conn: &mut PgConnection,
+    event_id: Uuid,
+    attempt: i32,
+    request_headers: &HeaderMap,
+    latency: Duration,
+    outcome: &Outcome,
+) -> Result<(), sqlx::Error> {
+    let headers: BTreeMap<&str, &str> = request_headers
+        .iter()
+        .filter_map(|(name, value)| Some((name.as_str(), value.to_str().ok()?)))
+        .collect();
+    sqlx::query(
+        r#"
+        INSERT INTO webhook_delivery_attempts
+            (event_id, attempt, request_headers, response_status, response_body, latency_ms, error_kind, error_message)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+        "#,
+    )
+    .bind(event_id)
+    .bind(attempt)
+    .bind(Json(headers))
+    .bind(outcome.response_status.map(i32::from))
+    .bind(outcome.response_body.as_deref())
+    .bind(latency.as_millis().min(i32::MAX as u128) as i32)
+    .bind(outcome.error_kind)
+    .bind(outcome.error_message.as_deref())
+    .execute(conn)
+    .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn response(status: u16, retry_after: Option<&str>, body: String) -> reqwest::Response {
+        let mut builder = axum::http::Response::builder().status(status);
+        if let Some(retry_after) = retry_after {
+            builder = builder.header(RETRY_AFTER, retry_after);
+        }
+        reqwest::Response::from(builder.body(body).unwrap())
+    }
+
+    #[tokio::test]
+    async fn keeps_status_truncated_body_and_retry_after() {
+        let outcome = Outcome::from_response(response(503, Some("7"), "x".repeat(MAX_RESPONSE_BODY_BYTES + 100))).await;
+        assert_eq!(outcome.response_status, Some(503));
+        assert_eq!(outcome.response_body.as_ref().map(String::len), Some(MAX_RESPONSE_BODY_BYTES));
+        assert_eq!(outcome.error_kind, Some("non_2xx"));
+        assert_eq!(outcome.retry_after_secs, Some(7.0));
+        assert!(!outcome.succeeded());
+
+        // Retry-After only counts on 429 and 503
+        let outcome = Outcome::from_response(response(500, Some("7"), String::new())).await;
+        assert_eq!(outcome.retry_after_secs, None);
+
+        let outcome = Outcome::from_response(response(204, None, "ok".into())).await;
+        assert!(outcome.succeeded());
+        assert_eq!(outcome.response_body.as_deref(), Some("ok"));
+        assert_eq!(outcome.error_message, None);
+    }
+
+    #[test]
+    fn parses_retry_after_seconds_and_dates() {
+        assert_eq!(parse_retry_after(" 120 "), Some(120.0));
+        let in_a_minute = (chrono::Utc::now() + chrono::Duration::seconds(60)).to_rfc2822();
+        let secs = parse_retry_after(&in_a_minute).unwrap();
+        assert!((58.0..=60.0).contains(&secs), "{}", secs);
+        assert_eq!(parse_retry_after("Thu, 01 Jan 1970 00:00:00 +0000"), Some(0.0));
+        assert_eq!(parse_retry_after("soon"), None);
+    }
+}
@@ -0,0 +1,376 @@
+//! Backfills: regenerate transaction events for a past time range and queue them for one webhook
+//! (`POST /api/webhooks/{id}/replay`).
+//!
+//! A backfill is a `webhook_replay_jobs` row. `run` works off running jobs a chunk at a time: each
+//! chunk takes the next events after the job's cursor, builds their payloads as `emit` built them at
+//! the time, marks them with `"replay": true` and inserts them as ordinary pending events. Signing, retries,
+//! ordering and batching are then the dispatcher's. The chunk and the cursor commit together, so a
+//! crash never queues an event twice, and several instances can share the work.
+//!
+//! Chunks are paced to `WEBHOOK_REPLAY_EVENTS_PER_SEC` (default 10) per job, and a job waits while
+//! its webhook has `WEBHOOK_REPLAY_MAX_PENDING` (default 100) or more pending events, so a backfill
+//! never floods the endpoint or pushes live events far back.
+
+use sqlx::{FromRow, PgConnection, PgPool};
+use std::env;
+use std::time::Duration;
+use uuid::Uuid;
+
+use super::{TRANSACTION_CREATED, TRANSACTION_REVERSED, WAKE_CHANNEL};
+use crate::models::{EventData, Transaction, WebhookPayload, WebhookReplayJob};
+
+/// Event types a backfill can regenerate. Balances and other events are not reconstructed.
+pub const REPLAYABLE_EVENT_TYPES: &[&str] = &[TRANSACTION_CREATED, TRANSACTION_REVERSED];
+
+/// Events a job can produce: `transaction.created` for every stored transaction, at its creation
+/// time, and `transaction.reversed` for reversed ones, at the creation time of the reversal (`reversed_by`).
+/// `event_at` is the time the live event was emitted. A reversal emits `transaction.created` for the
+/// compensating transaction before `transaction.reversed` at the same time, so `rank` orders ties. Binds: account id, from, to, event types.
+const CANDIDATES: &str = r#"
+    FROM transactions t
+    LEFT JOIN transactions r ON r.id = t.reversed_by
+    CROSS JOIN LATERAL (
+        VALUES ('transaction.created', 0, t.created_at), ('transaction.reversed', 1, r.created_at)
+    ) AS k(event_type, rank, event_at)
+    WHERE (t.from_account = $1 OR t.to_account = $1)
+      -- Implied by the range on event_at, since a reversal comes after its transaction; lets the index bound the scan
+      AND t.created_at < $3
+      AND k.event_at >= $2 AND k.event_at < $3
+      AND k.event_type = ANY($4)
+      AND t.status IN ('completed', 'reversed')
+      AND (k.rank = 0 OR t.status = 'reversed')
+"#;
+
+fn env_i64(var: &str, default: i64) -> i64 {
+    env::var(var).ok().and_then(|v| v.parse().ok()).unwrap_or(default)
+}
+
+/// Create a running job for the webhook's account. Fails with a unique violation if the webhook
+/// already has one running, and with `RowNotFound` if the webhook is deleted meanwhile.
+pub async fn start(
+    pool: &PgPool,
+    webhook_id: Uuid,
+    account_id: Uuid,
+    from: chrono::DateTime<chrono::Utc>,
+    to: chrono::DateTime<chrono::Utc>,
+    event_types: &[String],
+) -> Result<WebhookReplayJob, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    let total: i64 = sqlx::query_scalar(&format!("SELECT COUNT(*) {CANDIDATES}"))
+        .bind(account_id)
+        .bind(from)
+        .bind(to)
+        .bind(event_types)
+        .fetch_one(&mut *tx)
+        .await?;
+    let job_id: Uuid = sqlx::query_scalar(
+        r#"
+        INSERT INTO webhook_replay_jobs (webhook_id, range_from, range_to, event_types, total_events)
+        VALUES ($1, $2, $3, $4, $5)
+        RETURNING id
+        "#,
+    )
+    .bind(webhook_id)
+    .bind(from)
+    .bind(to)
+    .bind(event_types)
+    .bind(total)
+    .fetch_one(&mut *tx)
+    .await?;
+    let job = progress(&mut tx, webhook_id, job_id).await?.ok_or(sqlx::Error::RowNotFound)?;
+    tx.commit().await?;
+    Ok(job)
+}
+
+/// A job of the webhook with delivery counts of the events it queued so far.
+pub async fn progress(conn: &mut PgConnection, webhook_id: Uuid, job_id: Uuid) -> Result<Option<WebhookReplayJob>, sqlx::Error> {
+    sqlx::query_as::<_, WebhookReplayJob>(
+        r#"
+        SELECT j.id, j.webhook_id, j.range_from, j.range_to, j.event_types, j.status, j.total_events, j.enqueued_events,
+               COUNT(e.id) FILTER (WHERE e.status = 'delivered') AS delivered_events,
+               COUNT(e.id) FILTER (WHERE e.status = 'failed') AS failed_events,
+               j.error, j.created_at, j.updated_at, j.completed_at
+        FROM webhook_replay_jobs j
+        LEFT JOIN webhook_events e ON e.replay_job_id = j.id
+        WHERE j.id = $1 AND j.webhook_id = $2
+        GROUP BY j.id
+        "#,
+    )
+    .bind(job_id)
+    .bind(webhook_id)
+    .fetch_optional(conn)
+    .await
+}
+
+/// Background task: advance every running job by one chunk per second.
+pub async fn run(pool: PgPool) {
+    let mut interval = tokio::time::interval(Duration::from_secs(1));
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    loop {
+        interval.tick().await;
+        let jobs: Vec<Uuid> = match sqlx::query_scalar(
+            "SELECT id FROM webhook_replay_jobs WHERE status = 'running' ORDER BY created_at LIMIT 100",
+        )
+        .fetch_all(&pool)
+        .await
+        {
+            Ok(jobs) => jobs,
+            Err(e) => {
+                tracing::error!("Failed to list webhook replay jobs: {}", e);
+                continue;
+            }
+        };
+        for job_id in jobs {
+            if let Err(e) = advance(&pool, job_id).await {
+                tracing::error!("Failed to advance webhook replay job {}: {}", job_id, e);
+            }
+        }
+    }
+}
+
+#[derive(FromRow)]
+struct Job {
+    webhook_id: Uuid,
+    account_id: Uuid,
+    enabled: bool,
+    range_from: chrono::DateTime<chrono::Utc>,
+    range_to: chrono::DateTime<chrono::Utc>,
+    event_types: Vec<String>,
+    cursor_created_at: Option<chrono::DateTime<chrono::Utc>>,
+    cursor_txn_id: Option<Uuid>,
+    cursor_rank: Option<i32>,
+}
+
+#[derive(FromRow)]
+struct Candidate {
+    #[sqlx(flatten)]
+    transaction: Transaction,
+    event_type: String,
+    event_at: chrono::DateTime<chrono::Utc>,
+    rank: i32,
+}
+
+/// Queue the job's next chunk, or finish it. Skips a job another instance is working on.
+async fn advance(pool: &PgPool, job_id: Uuid) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    let job = sqlx::query_as::<_, Job>(
+        r#"
+        SELECT j.webhook_id, w.account_id, w.enabled, j.range_from, j.range_to, j.event_types,
+               j.cursor_created_at, j.cursor_txn_id, j.cursor_rank
+        FROM webhook_replay_jobs j JOIN webhooks w ON w.id = j.webhook_id
+        WHERE j.id = $1 AND j.status = 'running'
+        FOR UPDATE OF j SKIP LOCKED
+        "#,
+    )
+    .bind(job_id)
+    .fetch_optional(&mut *tx)
+    .await?;
+    let Some(job) = job else {
+        return Ok(());
+    };
+
+    if !job.enabled {
+        sqlx::query(
+            r#"
+            UPDATE webhook_replay_jobs SET status = 'failed', error = 'Webhook was disabled', updated_at = NOW(), completed_at = NOW()
+            WHERE id = $1
+            "#,
+        )
+        .bind(job_id)
+        .execute(&mut *tx)
+        .await?;
+        return tx.commit().await;
+    }
+
+    let pending: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM webhook_events WHERE webhook_id = $1 AND status = 'pending'")
+        .bind(job.webhook_id)
+        .fetch_one(&mut *tx)
+        .await?;
+    let budget = env_i64("WEBHOOK_REPLAY_EVENTS_PER_SEC", 10).min(env_i64("WEBHOOK_REPLAY_MAX_PENDING", 100) - pending);
+    if budget <= 0 {
+        return Ok(());
+    }
+
+    let chunk = sqlx::query_as::<_, Candidate>(&format!(
+        r#"
+        SELECT t.id, t.from_account, t.to_account, t.amount, t.txn_type, t.status, t.created_at,
+               k.event_type, k.event_at, k.rank
+        {CANDIDATES}
+          AND (k.event_at, k.rank, t.id) > (
+              COALESCE($5::timestamptz, '-infinity'), COALESCE($7::int, -1), COALESCE($6::uuid, '00000000-0000-0000-0000-000000000000')
+          )
+        ORDER BY k.event_at, k.rank, t.id
+        LIMIT $8
+        "#
+    ))
+    .bind(job.account_id)
+    .bind(job.range_from)
+    .bind(job.range_to)
+    .bind(&job.event_types)
+    .bind(job.cursor_created_at)
+    .bind(job.cursor_txn_id)
+    .bind(job.cursor_rank)
+    .bind(budget)
+    .fetch_all(&mut *tx)
+    .await?;
+
+    let Some(last) = chunk.last() else {
+        sqlx::query(
+            "UPDATE webhook_replay_jobs SET status = 'completed', updated_at = NOW(), completed_at = NOW() WHERE id = $1",
+        )
+        .bind(job_id)
+        .execute(&mut *tx)
+        .await?;
+        return tx.commit().await;
+    };
+    let cursor = (last.event_at, last.transaction.id, last.rank);
+
+    // Take a block of the webhook's sequence, as emit does per event
+    let last_sequence: i64 = sqlx::query_scalar(
+        r#"
+        INSERT INTO webhook_sequences (webhook_id, last_sequence) VALUES ($1, $2)
+        ON CONFLICT (webhook_id) DO UPDATE SET last_sequence = webhook_sequences.last_sequence + $2
+        RETURNING last_sequence
+        "#,
+    )
+    .bind(job.webhook_id)
+    .bind(chunk.len() as i64)
+    .fetch_one(&mut *tx)
+    .await?;
+    let first_sequence = last_sequence - chunk.len() as i64 + 1;
+
+    let mut txn_ids = Vec::with_capacity(chunk.len());
+    let mut event_types = Vec::with_capacity(chunk.len());
+    let mut sequences = Vec::with_capacity(chunk.len());
+    let mut payloads = Vec::with_capacity(chunk.len());
+    for (sequence, mut candidate) in (first_sequence..).zip(chunk) {
+        txn_ids.push(candidate.transaction.id);
+        sequences.push(sequence);
+        // transaction.created went out when the transaction completed, before any reversal
+        if candidate.event_type == TRANSACTION_CREATED {
+            candidate.transaction.status = "completed".to_string();
+        }
+        let payload = WebhookPayload {
+            event_type: candidate.event_type.clone(),
+            data: EventData::Transaction(candidate.transaction),
+            timestamp: candidate.event_at,
+        };
+        let mut payload = serde_json::to_value(&payload).unwrap_or_default();
+        if let Some(object) = payload.as_object_mut() {
+            object.insert("sequence".to_string(), sequence.into());
+            object.insert("replay".to_string(), true.into());
+        }
+        payloads.push(payload);
+        event_types.push(candidate.event_type);
+    }
+
+    sqlx::query(
+        r#"
+        INSERT INTO webhook_events (webhook_id, txn_id, event_type, sequence, payload, next_attempt_at, replay_job_id)
+        SELECT $1, e.txn_id, e.event_type, e.sequence, e.payload, NOW(), $2
+        FROM UNNEST($3::uuid[], $4::text[], $5::bigint[], $6::jsonb[]) AS e(txn_id, event_type, sequence, payload)
+        "#,
+    )
+    .bind(job.webhook_id)
+    .bind(job_id)
+    .bind(&txn_ids)
+    .bind(&event_types)
+    .bind(&sequences)
+    .bind(&payloads)
+    .execute(&mut *tx)
+    .await?;
+
+    sqlx::query(
+        r#"
+        UPDATE webhook_replay_jobs
+        SET enqueued_events = enqueued_events + $2, cursor_created_at = $3, cursor_txn_id = $4, cursor_rank = $5,
+            updated_at = NOW()
+        WHERE id = $1
+        "#,
+    )
+    .bind(job_id)
+    .bind(txn_ids.len() as i64)
+    .bind(cursor.0)
+    .bind(cursor.1)
+    .bind(cursor.2)
+    .execute(&mut *tx)
+    .await?;
+
+    sqlx::query("SELECT pg_notify($1, '')").bind(WAKE_CHANNEL).execute(&mut *tx).await?;
+    tx.commit().await
+}
+
+/// Tests against Postgres; see `webhooks::tests` for how to run them.
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handlers::{admin_reverse_transaction, create_transaction};
+    use crate::models::CreateTransactionRequest;
+    use crate::webhooks::tests::{account, webhook};
+    use axum::extract::{Json, Path, State};
+    use rust_decimal::Decimal;
+
+    /// Payloads queued for the webhook in sequence order, without the fields that differ between a
+    /// live event and its replay, and the timestamp apart.
+    async fn payloads(pool: &PgPool, webhook_id: Uuid) -> Vec<(serde_json::Value, chrono::DateTime<chrono::Utc>)> {
+        let rows: Vec<serde_json::Value> =
+            sqlx::query_scalar("SELECT payload FROM webhook_events WHERE webhook_id = $1 ORDER BY sequence")
+                .bind(webhook_id)
+                .fetch_all(pool)
+                .await
+                .unwrap();
+        rows.into_iter()
+            .map(|mut payload| {
+                let object = payload.as_object_mut().unwrap();
+                object.remove("sequence");
+                object.remove("replay");
+                let timestamp = object.remove("timestamp").unwrap();
+                (payload, serde_json::from_value(timestamp).unwrap())
+            })
+            .collect()
+    }
+
+    #[sqlx::test]
+    #[ignore]
+    async fn replayed_events_match_the_live_ones(pool: PgPool) {
+        let sender = account(&pool).await;
+        let recipient = account(&pool).await;
+        let live = webhook(&pool, sender.id, Some(REPLAYABLE_EVENT_TYPES)).await;
+
+        let transfer = |amount: i64| {
+            Json(CreateTransactionRequest {
+                from_account_id: Some(sender.id),
+                to_account_id: Some(recipient.id),
+                amount: Decimal::from(amount),
+                txn_type: "transfer".to_string(),
+            })
+        };
+        let Json(first) = create_transaction(State(pool.clone()), transfer(10)).await.unwrap();
+        let _ = create_transaction(State(pool.clone()), transfer(20)).await.unwrap();
+        let _ = admin_reverse_transaction(State(pool.clone()), Path(first.id)).await.unwrap();
+
+        let replayed = webhook(&pool, sender.id, None).await;
+        let now = chrono::Utc::now();
+        let types: Vec<String> = REPLAYABLE_EVENT_TYPES.iter().map(|t| t.to_string()).collect();
+        let job = start(&pool, replayed, sender.id, now - chrono::Duration::hours(1), now, &types).await.unwrap();
+        assert_eq!(job.total_events, 4);
+        advance(&pool, job.id).await.unwrap();
+        advance(&pool, job.id).await.unwrap();
+        let status: String = sqlx::query_scalar("SELECT status FROM webhook_replay_jobs WHERE id = $1")
+            .bind(job.id)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(status, "completed");
+
+        let (live, replayed) = (payloads(&pool, live).await, payloads(&pool, replayed).await);
+        let types: Vec<&str> = live.iter().map(|(payload, _)| payload["event_type"].as_str().unwrap()).collect();
+        assert_eq!(types, [TRANSACTION_CREATED, TRANSACTION_CREATED, TRANSACTION_CREATED, TRANSACTION_REVERSED]);
+        assert_eq!(live.len(), replayed.len());
+        for ((live, emitted_at), (replayed, replayed_at)) in live.iter().zip(&replayed) {
+            assert_eq!(live, replayed);
+            // Live events are stamped when emitted, replays with the transaction's creation time
+            assert!((*emitted_at - *replayed_at).num_milliseconds().abs() < 1000);
+        }
+    }
+}
@@ -0,0 +1,125 @@
+//! Per-host circuit breaker for webhook delivery.
+//!
+//! After `WEBHOOK_CIRCUIT_FAILURE_THRESHOLD` (default 5) consecutive failed attempts against a host,
+//! its circuit opens and deliveries to it are paused for `WEBHOOK_CIRCUIT_OPEN_SECS` (default 30).
+//! Then a single probe is let through: success closes the circuit, failure opens it again.
+//! Only transport errors, timeouts, 5xx and 429 count as failures, since any other answer means the
+//! host is up. State is kept per dispatcher instance.
+
+use dashmap::DashMap;
+use once_cell::sync::Lazy;
+use std::env;
+use std::time::{Duration, Instant};
+
+use super::attempts::Outcome;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum State {
+    Closed,
+    Open { until: Instant },
+    /// A probe is in flight; everything else waits for its result. If no result arrives within the
+    /// open period, another probe is let through.
+    HalfOpen { probe_started: Instant },
+}
+
+/// A host without an entry has a closed circuit.
+#[derive(Debug)]
+struct Circuit {
+    state: State,
+    failures: u32,
+}
+
+impl Default for Circuit {
+    fn default() -> Self {
+        Self { state: State::Closed, failures: 0 }
+    }
+}
+
+impl Circuit {
+    fn admit(&mut self, now: Instant, open_for: Duration) -> Result<(), Duration> {
+        let wait_until = match self.state {
+            State::Closed => return Ok(()),
+            State::Open { until } => until,
+            State::HalfOpen { probe_started } => probe_started + open_for,
+        };
+        if now < wait_until {
+            return Err(wait_until - now);
+        }
+        self.state = State::HalfOpen { probe_started: now };
+        Ok(())
+    }
+
+    /// Count a failed attempt. Returns true if it opened the circuit.
+    fn fail(&mut self, now: Instant, threshold: u32, open_for: Duration) -> bool {
+        self.failures += 1;
+        let trips = match self.state {
+            State::Closed => self.failures >= threshold,
+            State::HalfOpen { .. } => true,
+            State::Open { .. } => false,
+        };
+        if trips {
+            self.state = State::Open { until: now + open_for };
+        }
+        trips
+    }
+}
+
+static CIRCUITS: Lazy<DashMap<String, Circuit>> = Lazy::new(DashMap::new);
+
+fn threshold() -> u32 {
+    env::var("WEBHOOK_CIRCUIT_FAILURE_THRESHOLD").ok().and_then(|v| v.parse().ok()).unwrap_or(5)
+}
+
+fn open_for() -> Duration {
+    Duration::from_secs(env::var("WEBHOOK_CIRCUIT_OPEN_SECS").ok().and_then(|v| v.parse().ok()).unwrap_or(30))
+}
+
+/// Whether a delivery to `host` may go out now; otherwise how long to hold it back.
+pub fn admit(host: &str) -> Result<(), Duration> {
+    match CIRCUITS.get_mut(host) {
+        Some(mut circuit) => circuit.admit(Instant::now(), open_for()),
+        None => Ok(()),
+    }
+}
+
+/// Feed the result of an admitted delivery back into the host's circuit.
+pub fn record(host: &str, outcome: &Outcome) {
+    let failed = match outcome.response_status {
+        Some(status) => status >= 500 || status == 429,
+        // Refused by the SSRF checks: nothing was sent, so it says nothing about the host
+        None if outcome.error_kind == Some("blocked") => return,
+        None => !outcome.succeeded(),
+    };
+    if !failed {
+        CIRCUITS.remove(host);
+        return;
+    }
+    let open_for = open_for();
+    let mut circuit = CIRCUITS.entry(host.to_string()).or_default();
+    if circuit.fail(Instant::now(), threshold(), open_for) {
+        tracing::warn!("Circuit for webhook host {} opened after {} failures; pausing for {:?}", host, circuit.failures, open_for);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn opens_after_threshold_and_probes_once() {
+        let open_for = Duration::from_secs(30);
+        let now = Instant::now();
+        let mut circuit = Circuit::default();
+        assert!(!circuit.fail(now, 2, open_for));
+        assert!(circuit.fail(now, 2, open_for));
+        assert_eq!(circuit.admit(now + Duration::from_secs(10), open_for), Err(Duration::from_secs(20)));
+
+        // One probe after the pause; a failed probe reopens the circuit
+        let later = now + open_for;
+        assert_eq!(circuit.admit(later, open_for), Ok(()));
+        assert!(circuit.admit(later, open_for).is_err());
+        assert!(circuit.fail(later, 2, open_for));
+        assert!(circuit.admit(later, open_for).is_err());
+        assert!(circuit.admit(later + open_for, open_for).is_ok());
+    }
+}
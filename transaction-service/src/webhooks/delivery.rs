@@ -0,0 +1,191 @@
+//! HTTP transport shared by the dispatcher and test pings: one client, signing, target headers and SSRF checks.
+
+use anyhow::Context;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use once_cell::sync::OnceCell;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, AUTHORIZATION};
+use reqwest::redirect::Policy;
+use std::collections::HashMap;
+use std::env;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+use super::attempts::Outcome;
+use super::format::Encoded;
+use super::{signature, ssrf};
+use crate::models::BasicAuth;
+use crate::secrets;
+
+/// Shown in the attempt log instead of custom header values.
+const MASK: &str = "********";
+
+static CLIENT: OnceCell<Client> = OnceCell::new();
+
+struct Client {
+    http: reqwest::Client,
+    /// Requests go through `WEBHOOK_PROXY_URL`
+    proxied: bool,
+}
+
+fn env_secs(var: &str, default: u64) -> Duration {
+    Duration::from_secs(env::var(var).ok().and_then(|v| v.parse().ok()).unwrap_or(default))
+}
+
+/// Build the client shared by every delivery, so connections to an endpoint are pooled and reused.
+/// `WEBHOOK_TIMEOUT_SECS` (default 10) bounds the whole request, `WEBHOOK_CONNECT_TIMEOUT_SECS` (5)
+/// connection setup and `WEBHOOK_READ_TIMEOUT_SECS` (10) each read of the response.
+///
+/// Proxies from the standard environment variables are ignored. `WEBHOOK_PROXY_URL` sends every delivery
+/// through the given proxy instead. The proxy resolves target hosts itself, so the resolver cannot pin
+/// checked addresses and our own up-front check can be rebound before the proxy connects. The proxy is
+/// therefore only accepted when `WEBHOOK_PROXY_ENFORCES_ADDRESS_RULES=true` states that it refuses
+/// non-public addresses itself. Redirects are not followed through it.
+pub fn init() -> anyhow::Result<()> {
+    let builder = reqwest::Client::builder()
+        .timeout(env_secs("WEBHOOK_TIMEOUT_SECS", 10))
+        .connect_timeout(env_secs("WEBHOOK_CONNECT_TIMEOUT_SECS", 5))
+        .read_timeout(env_secs("WEBHOOK_READ_TIMEOUT_SECS", 10))
+        .pool_idle_timeout(Duration::from_secs(90))
+        .tcp_keepalive(Duration::from_secs(60))
+        .user_agent(concat!("transaction-service-webhooks/", env!("CARGO_PKG_VERSION")));
+    let proxy = env::var("WEBHOOK_PROXY_URL").ok().filter(|url| !url.is_empty());
+    if proxy.is_some() && !env::var("WEBHOOK_PROXY_ENFORCES_ADDRESS_RULES").is_ok_and(|v| v == "true") {
+        anyhow::bail!(
+            "WEBHOOK_PROXY_URL bypasses the pinned address checks; configure the proxy to refuse non-public \
+             addresses and set WEBHOOK_PROXY_ENFORCES_ADDRESS_RULES=true"
+        );
+    }
+    let builder = match &proxy {
+        Some(url) => builder
+            .proxy(reqwest::Proxy::all(url).context("invalid WEBHOOK_PROXY_URL")?)
+            .redirect(Policy::none()),
+        None => builder
+            .no_proxy()
+            .dns_resolver(Arc::new(ssrf::GuardedResolver))
+            .redirect(ssrf::redirect_policy()),
+    };
+    let http = builder.build().context("failed to build webhook HTTP client")?;
+    CLIENT
+        .set(Client { http, proxied: proxy.is_some() })
+        .map_err(|_| anyhow::anyhow!("webhook HTTP client already initialised"))
+}
+
+fn client() -> &'static Client {
+    CLIENT.get().expect("delivery::init must run at startup")
+}
+
+/// One signed POST to a webhook endpoint.
+pub struct Delivery {
+    pub request_headers: HeaderMap,
+    pub latency: Duration,
+    pub outcome: Outcome,
+}
+
+/// Decrypt the custom headers and basic-auth credentials configured on a webhook.
+pub fn unseal_target_headers(custom_headers_sealed: Option<&str>, basic_auth_sealed: Option<&str>) -> anyhow::Result<HeaderMap> {
+    let mut headers = HeaderMap::new();
+    if let Some(sealed) = custom_headers_sealed {
+        let custom: HashMap<String, String> = serde_json::from_str(&secrets::open(sealed)?).context("malformed custom headers")?;
+        for (name, value) in custom {
+            let mut value = HeaderValue::from_str(&value).context("invalid custom header value")?;
+            value.set_sensitive(true);
+            headers.insert(HeaderName::try_from(name).context("invalid custom header name")?, value);
+        }
+    }
+    if let Some(sealed) = basic_auth_sealed {
+        let auth: BasicAuth = serde_json::from_str(&secrets::open(sealed)?).context("malformed basic auth")?;
+        let credentials = STANDARD.encode(format!("{}:{}", auth.username, auth.password));
+        let mut value = HeaderValue::from_str(&format!("Basic {}", credentials)).context("invalid basic auth")?;
+        value.set_sensitive(true);
+        headers.insert(AUTHORIZATION, value);
+    }
+    Ok(headers)
+}
+
+/// Decrypt the current secret and, during a rotation overlap, the previous one.
+pub fn unseal_secrets(secret_sealed: &str, previous_secret_sealed: Option<&str>) -> anyhow::Result<Vec<String>> {
+    std::iter::once(secret_sealed).chain(previous_secret_sealed).map(secrets::open).collect()
+}
+
+/// Sign the encoded request with every secret and POST it to `url` together with the webhook's own headers.
+/// `request_id` is sent as `X-Webhook-Id`. The returned request headers show target header values masked.
+pub async fn send(
+    url: &str,
+    request_id: Uuid,
+    request: Encoded,
+    target_headers: &HeaderMap,
+    secrets: &[String],
+    legacy_signature: bool,
+) -> Delivery {
+    let secrets: Vec<&str> = secrets.iter().map(String::as_str).collect();
+    let Encoded { headers: mut logged, body } = request;
+    signature::sign(&mut logged, request_id, &body, &secrets, legacy_signature);
+    // Our own headers win; names that would clash are refused when the webhook is saved
+    let mut headers = target_headers.clone();
+    headers.extend(logged.clone());
+    for name in target_headers.keys() {
+        if !logged.contains_key(name) {
+            logged.insert(name.clone(), HeaderValue::from_static(MASK));
+        }
+    }
+
+    let started = Instant::now();
+    let client = client();
+    // Rechecked on every attempt, since rules may have tightened since the webhook was registered
+    let checked = match ssrf::validate(url) {
+        // Refuses obvious cases early; the proxy's own rules are what hold against rebinding
+        Ok(url) if client.proxied => ssrf::check_resolved(&url).await.map(|()| url),
+        checked => checked,
+    };
+    let outcome = match checked {
+        Err(blocked) => {
+            tracing::warn!("Webhook delivery {} refused: {}", request_id, blocked);
+            Outcome::blocked(&blocked)
+        }
+        Ok(url) => match client.http.post(url).headers(headers).body(body).send().await {
+            Ok(response) => Outcome::from_response(response).await,
+            Err(e) => {
+                tracing::error!("Webhook delivery failed: {}", e);
+                Outcome::from_error(&e)
+            }
+        },
+    };
+    Delivery { request_headers: logged, latency: started.elapsed(), outcome }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::webhooks::format;
+
+    fn target_headers() -> HeaderMap {
+        secrets::init_for_tests();
+        let custom = secrets::seal(r#"{"X-Api-Token":"gateway-token"}"#);
+        let basic = secrets::seal(r#"{"username":"hooks","password":"s3cret"}"#);
+        unseal_target_headers(Some(&custom), Some(&basic)).unwrap()
+    }
+
+    #[test]
+    fn unseals_custom_headers_and_basic_auth() {
+        let headers = target_headers();
+        assert_eq!(headers["x-api-token"], "gateway-token");
+        assert_eq!(headers[AUTHORIZATION], format!("Basic {}", STANDARD.encode("hooks:s3cret")));
+        assert!(headers.values().all(HeaderValue::is_sensitive));
+        assert!(unseal_target_headers(Some("not sealed"), None).is_err());
+    }
+
+    #[tokio::test]
+    async fn the_attempt_log_masks_target_header_values() {
+        let _ = init();
+        let payload = serde_json::json!({ "event_type": "webhook.ping" });
+        let request = format::encode(format::LEGACY, &[format::Event { id: Uuid::new_v4(), payload: &payload }], false).unwrap();
+
+        // Refused before sending, but the headers of the attempt are logged all the same
+        let delivery = send("https://127.0.0.1:9/hook", Uuid::new_v4(), request, &target_headers(), &["whsec".into()], false).await;
+        assert!(!delivery.outcome.succeeded());
+        assert_eq!(delivery.request_headers["x-api-token"], MASK);
+        assert_eq!(delivery.request_headers[AUTHORIZATION], MASK);
+        assert!(delivery.request_headers.contains_key("x-webhook-signature"));
+    }
+}
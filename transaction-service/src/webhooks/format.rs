@@ -0,0 +1,171 @@
+//! Wire formats of webhook requests.
+//!
+//! Payloads are stored in the legacy shape (`{"event_type", "sequence", "<resource>": {...}, "timestamp"}`)
+//! and encoded in the webhook's `payload_format` when sent:
+//! - `legacy`: the stored payload as is; batches are `{"events": [...]}` with each event's `id` added.
+//! - `cloudevents_structured`: a CloudEvents 1.0 JSON envelope (`application/cloudevents+json`);
+//!   batches use the JSON batch format (`application/cloudevents-batch+json`).
+//! - `cloudevents_binary`: the resource as the body, with the attributes in `ce-*` headers.
+//!   Carries one event per request, so it cannot be combined with batch mode.
+//!
+//! Events regenerated by a history replay (`backfill`) carry `"replay": true`, which becomes the
+//! `replay` extension attribute in CloudEvents.
+//!
+//! The CloudEvents `id` is the `webhook_events` id, so it is stable across retries and replays, and
+//! `dataschema` names the event type and `SCHEMA_VERSION`.
+
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, CONTENT_TYPE};
+use serde_json::{json, Map, Value};
+use std::env;
+use uuid::Uuid;
+
+pub const LEGACY: &str = "legacy";
+pub const CLOUDEVENTS_STRUCTURED: &str = "cloudevents_structured";
+pub const CLOUDEVENTS_BINARY: &str = "cloudevents_binary";
+
+pub const PAYLOAD_FORMATS: &[&str] = &[LEGACY, CLOUDEVENTS_STRUCTURED, CLOUDEVENTS_BINARY];
+
+/// Version of the payload schemas; bumped on breaking changes to an event's data.
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// An event to send: its id and stored payload.
+pub struct Event<'a> {
+    pub id: Uuid,
+    pub payload: &'a Value,
+}
+
+/// Request body plus the headers that describe it.
+pub struct Encoded {
+    pub headers: HeaderMap,
+    pub body: Vec<u8>,
+}
+
+/// Encode one event, or several as a batch, in `format`. Unknown formats fall back to legacy.
+pub fn encode(format: &str, events: &[Event], batched: bool) -> serde_json::Result<Encoded> {
+    let mut headers = HeaderMap::new();
+    let (content_type, body) = match (format, batched) {
+        (CLOUDEVENTS_STRUCTURED, false) => ("application/cloudevents+json", serde_json::to_vec(&cloud_event(&events[0]))?),
+        (CLOUDEVENTS_STRUCTURED, true) => (
+            "application/cloudevents-batch+json",
+            serde_json::to_vec(&events.iter().map(cloud_event).collect::<Vec<_>>())?,
+        ),
+        (CLOUDEVENTS_BINARY, _) => {
+            let Value::Object(mut event) = cloud_event(&events[0]) else {
+                unreachable!("cloud_event returns an object")
+            };
+            let data = event.remove("data").unwrap_or(Value::Null);
+            event.remove("datacontenttype");
+            for (attribute, value) in event {
+                let value = match value {
+                    Value::String(s) => s,
+                    other => other.to_string(),
+                };
+                if let (Ok(name), Ok(value)) =
+                    (HeaderName::try_from(format!("ce-{}", attribute)), HeaderValue::from_str(&value))
+                {
+                    headers.insert(name, value);
+                }
+            }
+            ("application/json", serde_json::to_vec(&data)?)
+        }
+        (_, false) => ("application/json", serde_json::to_vec(events[0].payload)?),
+        (_, true) => {
+            let events: Vec<Value> = events
+                .iter()
+                .map(|event| {
+                    let mut payload = event.payload.clone();
+                    if let Some(object) = payload.as_object_mut() {
+                        object.insert("id".to_string(), event.id.to_string().into());
+                    }
+                    payload
+                })
+                .collect();
+            ("application/json", serde_json::to_vec(&json!({ "events": events }))?)
+        }
+    };
+    headers.insert(CONTENT_TYPE, HeaderValue::from_static(content_type));
+    Ok(Encoded { headers, body })
+}
+
+/// `source` attribute of every event; `CLOUDEVENTS_SOURCE` (default `/transaction-service`).
+fn source() -> String {
+    env::var("CLOUDEVENTS_SOURCE").unwrap_or_else(|_| "/transaction-service".to_string())
+}
+
+/// Structured-mode CloudEvent for a stored payload. `data` is the resource object and `subject` its id.
+fn cloud_event(event: &Event) -> Value {
+    let mut fields: Map<String, Value> = event.payload.as_object().cloned().unwrap_or_default();
+    let event_type = fields.remove("event_type").and_then(|v| v.as_str().map(str::to_string)).unwrap_or_default();
+    let time = fields.remove("timestamp");
+    let sequence = fields.remove("sequence");
+    let replay = fields.remove("replay");
+    let data = match fields.len() {
+        1 => fields.into_iter().next().map(|(_, resource)| resource).unwrap_or(Value::Null),
+        _ => Value::Object(fields),
+    };
+
+    let mut attributes = Map::new();
+    attributes.insert("specversion".into(), "1.0".into());
+    attributes.insert("id".into(), event.id.to_string().into());
+    attributes.insert("source".into(), source().into());
+    attributes.insert("type".into(), event_type.clone().into());
+    if let Some(time) = time {
+        attributes.insert("time".into(), time);
+    }
+    attributes.insert("datacontenttype".into(), "application/json".into());
+    attributes.insert(
+        "dataschema".into(),
+        format!("urn:transaction-service:webhooks:{}:v{}", event_type, SCHEMA_VERSION).into(),
+    );
+    if let Some(subject) = data.get("id").and_then(Value::as_str) {
+        attributes.insert("subject".into(), subject.into());
+    }
+    // Sequence extension: the value is a string
+    if let Some(sequence) = sequence.filter(|s| !s.is_null()) {
+        attributes.insert("sequence".into(), sequence.to_string().into());
+    }
+    // Replay extension: set on events regenerated by a history replay
+    if let Some(replay) = replay.filter(Value::is_boolean) {
+        attributes.insert("replay".into(), replay);
+    }
+    attributes.insert("data".into(), data);
+    Value::Object(attributes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn maps_payload_to_cloudevent_attributes() {
+        let id = Uuid::new_v4();
+        let payload = json!({
+            "event_type": "transaction.created",
+            "sequence": 7,
+            "transaction": { "id": "t-1", "amount": "5" },
+            "timestamp": "2025-10-01T00:00:00Z"
+        });
+        let event = Event { id, payload: &payload };
+
+        let structured: Value = serde_json::from_slice(&encode(CLOUDEVENTS_STRUCTURED, &[event], false).unwrap().body).unwrap();
+        assert_eq!(structured["id"], id.to_string());
+        assert_eq!(structured["type"], "transaction.created");
+        assert_eq!(structured["subject"], "t-1");
+        assert_eq!(structured["sequence"], "7");
+        assert_eq!(structured["dataschema"], "urn:transaction-service:webhooks:transaction.created:v1");
+        assert_eq!(structured["data"]["amount"], "5");
+
+        let binary = encode(CLOUDEVENTS_BINARY, &[Event { id, payload: &payload }], false).unwrap();
+        assert_eq!(binary.headers["ce-id"], id.to_string().as_str());
+        assert_eq!(binary.headers["ce-time"], "2025-10-01T00:00:00Z");
+        assert_eq!(binary.headers[CONTENT_TYPE], "application/json");
+        assert_eq!(serde_json::from_slice::<Value>(&binary.body).unwrap(), json!({ "id": "t-1", "amount": "5" }));
+
+        // A replayed event keeps its resource as data and gets the replay extension
+        let mut replayed = payload.clone();
+        replayed["replay"] = json!(true);
+        let binary = encode(CLOUDEVENTS_BINARY, &[Event { id, payload: &replayed }], false).unwrap();
+        assert_eq!(binary.headers["ce-replay"], "true");
+        assert_eq!(serde_json::from_slice::<Value>(&binary.body).unwrap(), json!({ "id": "t-1", "amount": "5" }));
+    }
+}
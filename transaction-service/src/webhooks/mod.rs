@@ -0,0 +1,378 @@
+//! Webhook outbox: events are written to `webhook_events` in the same database transaction as the
+//! change they describe, and delivered by the dispatcher in `worker`.
+
+use sqlx::types::Json;
+use sqlx::{PgConnection, PgPool};
+use uuid::Uuid;
+
+use crate::secrets;
+use crate::models::{EventData, EventTypeInfo, ReplayWebhookEventsRequest, WebhookEventData, WebhookPayload};
+
+pub mod attempts;
+pub mod backfill;
+pub mod circuit;
+pub mod delivery;
+pub mod format;
+pub mod retry;
+pub mod signature;
+pub mod ssrf;
+pub mod worker;
+
+/// Postgres channel notified whenever events become due, to wake the dispatcher.
+pub const WAKE_CHANNEL: &str = "webhook_events";
+
+/// Delivery states of a `webhook_events` row; `failed` is the dead letter state.
+pub const EVENT_STATUSES: &[&str] = &["pending", "delivered", "failed"];
+
+/// Most events a batch-mode webhook receives in one request.
+pub const MAX_BATCH_SIZE: i32 = 100;
+
+/// Most events a single batch replay will requeue.
+pub const MAX_REPLAY_BATCH: i64 = 1000;
+
+pub const ACCOUNT_CREATED: &str = "account.created";
+pub const ACCOUNT_UPDATED: &str = "account.updated";
+pub const API_KEY_CREATED: &str = "api_key.created";
+pub const API_KEY_REVOKED: &str = "api_key.revoked";
+pub const TRANSACTION_CREATED: &str = "transaction.created";
+pub const TRANSACTION_FAILED: &str = "transaction.failed";
+pub const TRANSACTION_REVERSED: &str = "transaction.reversed";
+pub const WEBHOOK_DISABLED: &str = "webhook.disabled";
+
+/// Sent only by `POST /api/webhooks/{id}/test`, regardless of the subscription; not in the catalog.
+pub const WEBHOOK_PING: &str = "webhook.ping";
+
+/// Every event type a webhook can subscribe to.
+pub const EVENT_CATALOG: &[EventTypeInfo] = &[
+    EventTypeInfo { event_type: ACCOUNT_CREATED, description: "An account was opened" },
+    EventTypeInfo { event_type: ACCOUNT_UPDATED, description: "An account changed, including its balance" },
+    EventTypeInfo { event_type: API_KEY_CREATED, description: "An API key was issued for the account" },
+    EventTypeInfo { event_type: API_KEY_REVOKED, description: "An API key of the account was revoked" },
+    EventTypeInfo { event_type: TRANSACTION_CREATED, description: "A transaction completed" },
+    EventTypeInfo { event_type: TRANSACTION_FAILED, description: "A transaction of the account was rejected, e.g. for insufficient funds" },
+    EventTypeInfo { event_type: TRANSACTION_REVERSED, description: "A completed transaction of the account was reversed" },
+    EventTypeInfo { event_type: WEBHOOK_DISABLED, description: "A webhook was disabled, manually or after repeated delivery failures" },
+];
+
+pub fn is_known_event_type(event_type: &str) -> bool {
+    EVENT_CATALOG.iter().any(|info| info.event_type == event_type)
+}
+
+/// Queue an event for every webhook of `account_ids` subscribed to `event_type`, and record it in
+/// the accounts' event logs (`events::record`).
+///
+/// Must be called on the connection of the transaction that makes the change, so the events
+/// commit (or roll back) together with it. Each event takes the next number of its webhook's
+/// sequence, which is added to the payload. The `webhook_sequences` rows stay locked until commit,
+/// so sequence order is commit order and ordered delivery never has to wait for a gap to fill. The
+/// `webhooks` rows themselves are not locked, since the dispatcher updates them while it may be
+/// emitting `webhook.disabled`.
+pub async fn emit(
+    conn: &mut PgConnection,
+    account_ids: &[Uuid],
+    event_type: &str,
+    data: EventData,
+) -> Result<(), sqlx::Error> {
+    // Transaction events keep a link to the ledger row, if it was persisted
+    let txn_id = match &data {
+        EventData::Transaction(transaction) => Some(transaction.id),
+        _ => None,
+    };
+    let payload = WebhookPayload {
+        event_type: event_type.to_string(),
+        data,
+        timestamp: chrono::Utc::now(),
+    };
+
+    let queued = sqlx::query(
+        r#"
+        WITH targets AS (
+            INSERT INTO webhook_sequences (webhook_id, last_sequence)
+            SELECT w.id, 1 FROM webhooks w
+            WHERE w.account_id = ANY($1) AND w.enabled AND (w.event_types IS NULL OR $3 = ANY(w.event_types))
+            ORDER BY w.id
+            ON CONFLICT (webhook_id) DO UPDATE SET last_sequence = webhook_sequences.last_sequence + 1
+            RETURNING webhook_id AS id, last_sequence
+        )
+        INSERT INTO webhook_events (webhook_id, txn_id, event_type, sequence, payload, next_attempt_at)
+        SELECT t.id, (SELECT tx.id FROM transactions tx WHERE tx.id = $2), $3, t.last_sequence,
+               $4::jsonb || jsonb_build_object('sequence', t.last_sequence), NOW()
+        FROM targets t
+        "#,
+    )
+    .bind(account_ids)
+    .bind(txn_id)
+    .bind(event_type)
+    .bind(Json(&payload))
+    .execute(&mut *conn)
+    .await?;
+
+    crate::events::record(&mut *conn, account_ids, event_type, &payload).await?;
+
+    if queued.rows_affected() > 0 {
+        // Delivered to listeners only when the surrounding transaction commits
+        sqlx::query("SELECT pg_notify($1, '')").bind(WAKE_CHANNEL).execute(&mut *conn).await?;
+    }
+    Ok(())
+}
+
+/// Disable a webhook, dead-letter its pending events and tell the account through `webhook.disabled`.
+///
+/// Returns `false` if the webhook was already disabled. The event goes to the account's other
+/// enabled webhooks, since this one no longer receives any.
+pub async fn disable(conn: &mut PgConnection, webhook_id: Uuid, reason: &str) -> Result<bool, sqlx::Error> {
+    let webhook = sqlx::query_as::<_, WebhookEventData>(
+        r#"
+        UPDATE webhooks SET enabled = false, disabled_reason = $2, disabled_at = NOW(), updated_at = NOW()
+        WHERE id = $1 AND enabled
+        RETURNING id, account_id, url, description, event_types, enabled, disabled_reason, disabled_at,
+                  consecutive_failures, failing_since
+        "#,
+    )
+    .bind(webhook_id)
+    .bind(reason)
+    .fetch_optional(&mut *conn)
+    .await?;
+    let Some(webhook) = webhook else {
+        return Ok(false);
+    };
+
+    sqlx::query(
+        "UPDATE webhook_events SET status = 'failed', next_attempt_at = NULL WHERE webhook_id = $1 AND status = 'pending'",
+    )
+    .bind(webhook_id)
+    .execute(&mut *conn)
+    .await?;
+
+    emit(conn, &[webhook.account_id], WEBHOOK_DISABLED, EventData::Webhook(webhook)).await?;
+    Ok(true)
+}
+
+/// Requeue delivered or dead-lettered events for immediate delivery with a fresh retry budget.
+///
+/// Only events of webhooks owned by `account_id` are touched. Pass `event_id` to replay one event,
+/// otherwise the filter selects the batch. Returns the ids of the requeued events.
+pub async fn replay(
+    pool: &PgPool,
+    account_id: Uuid,
+    event_id: Option<Uuid>,
+    filter: &ReplayWebhookEventsRequest,
+) -> Result<Vec<Uuid>, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    let ids: Vec<Uuid> = sqlx::query_scalar(
+        r#"
+        UPDATE webhook_events SET status = 'pending', delivered = false, retry_count = 0, next_attempt_at = NOW()
+        WHERE id IN (
+            SELECT e.id FROM webhook_events e JOIN webhooks w ON w.id = e.webhook_id
+            WHERE w.account_id = $1
+              AND e.status <> 'pending'
+              AND e.payload IS NOT NULL
+              AND ($2::uuid IS NULL OR e.id = $2)
+              AND ($3::uuid IS NULL OR e.webhook_id = $3)
+              AND ($4::text IS NULL OR e.status = $4)
+              AND ($5::text IS NULL OR e.event_type = $5)
+              AND ($6::timestamptz IS NULL OR e.created_at >= $6)
+              AND ($7::timestamptz IS NULL OR e.created_at < $7)
+            ORDER BY e.created_at
+            LIMIT $8
+            FOR UPDATE OF e SKIP LOCKED
+        )
+        RETURNING id
+        "#,
+    )
+    .bind(account_id)
+    .bind(event_id)
+    .bind(filter.webhook_id)
+    .bind(filter.status.as_deref())
+    .bind(filter.event_type.as_deref())
+    .bind(filter.created_after)
+    .bind(filter.created_before)
+    .bind(MAX_REPLAY_BATCH)
+    .fetch_all(&mut *tx)
+    .await?;
+
+    if !ids.is_empty() {
+        sqlx::query("SELECT pg_notify($1, '')").bind(WAKE_CHANNEL).execute(&mut *tx).await?;
+    }
+    tx.commit().await?;
+    Ok(ids)
+}
+
+/// Seal webhook secrets still stored in plaintext and re-wrap sealed ones (including custom headers
+/// and basic auth) under the active key.
+/// Runs at startup, before the dispatcher needs to read them.
+///
+/// A row is only written back if none of its secrets changed since it was read, so a rotation,
+/// update or another instance's reseal made in between is not overwritten.
+pub async fn seal_stored_secrets(pool: &PgPool) -> anyhow::Result<()> {
+    #[derive(sqlx::FromRow)]
+    struct StoredSecrets {
+        id: Uuid,
+        secret: Option<String>,
+        secret_sealed: Option<String>,
+        previous_secret: Option<String>,
+        previous_secret_sealed: Option<String>,
+        custom_headers_sealed: Option<String>,
+        basic_auth_sealed: Option<String>,
+    }
+
+    let rows: Vec<StoredSecrets> = sqlx::query_as(
+        r#"
+        SELECT id, secret, secret_sealed, previous_secret, previous_secret_sealed, custom_headers_sealed, basic_auth_sealed
+        FROM webhooks
+        WHERE secret IS NOT NULL OR previous_secret IS NOT NULL
+           OR split_part(secret_sealed, '.', 1) <> $1 OR split_part(previous_secret_sealed, '.', 1) <> $1
+           OR split_part(custom_headers_sealed, '.', 1) <> $1 OR split_part(basic_auth_sealed, '.', 1) <> $1
+        "#,
+    )
+    .bind(secrets::active_key_id())
+    .fetch_all(pool)
+    .await?;
+
+    let reseal = |plaintext: Option<String>, sealed: Option<String>| -> anyhow::Result<Option<String>> {
+        Ok(match (plaintext, sealed) {
+            (Some(plaintext), _) => Some(secrets::seal(&plaintext)),
+            (None, Some(sealed)) => Some(secrets::rewrap(&sealed)?.unwrap_or(sealed)),
+            (None, None) => None,
+        })
+    };
+    for row in rows {
+        let secret = reseal(row.secret.clone(), row.secret_sealed.clone())?;
+        let previous_secret = reseal(row.previous_secret.clone(), row.previous_secret_sealed.clone())?;
+        let custom_headers = reseal(None, row.custom_headers_sealed.clone())?;
+        let basic_auth = reseal(None, row.basic_auth_sealed.clone())?;
+        sqlx::query(
+            r#"
+            UPDATE webhooks
+            SET secret = NULL, secret_sealed = $2, previous_secret = NULL, previous_secret_sealed = $3,
+                custom_headers_sealed = $4, basic_auth_sealed = $5
+            WHERE id = $1
+              AND secret IS NOT DISTINCT FROM $6 AND secret_sealed IS NOT DISTINCT FROM $7
+              AND previous_secret IS NOT DISTINCT FROM $8 AND previous_secret_sealed IS NOT DISTINCT FROM $9
+              AND custom_headers_sealed IS NOT DISTINCT FROM $10 AND basic_auth_sealed IS NOT DISTINCT FROM $11
+            "#,
+        )
+        .bind(row.id)
+        .bind(secret)
+        .bind(previous_secret)
+        .bind(custom_headers)
+        .bind(basic_auth)
+        .bind(row.secret)
+        .bind(row.secret_sealed)
+        .bind(row.previous_secret)
+        .bind(row.previous_secret_sealed)
+        .bind(row.custom_headers_sealed)
+        .bind(row.basic_auth_sealed)
+        .execute(pool)
+        .await?;
+    }
+    Ok(())
+}
+
+/// Fixtures for the outbox tests in this module and its submodules.
+///
+/// The tests need Postgres: `sqlx::test` creates a fresh database per test from `DATABASE_URL` and
+/// applies the migrations. They are ignored by default; run them with `cargo test -- --ignored`.
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::models::Account;
+
+    pub(crate) async fn account(pool: &PgPool) -> Account {
+        sqlx::query_as(
+            r#"
+            INSERT INTO accounts (business_name, balance) VALUES ('Test', 100)
+            RETURNING id, business_name, balance, created_at, updated_at
+            "#,
+        )
+        .fetch_one(pool)
+        .await
+        .unwrap()
+    }
+
+    /// Register an enabled webhook, subscribed to `event_types` or to everything for `None`.
+    pub(crate) async fn webhook(pool: &PgPool, account_id: Uuid, event_types: Option<&[&str]>) -> Uuid {
+        sqlx::query_scalar(
+            r#"
+            INSERT INTO webhooks (account_id, url, secret_sealed, event_types)
+            VALUES ($1, 'https://example.com/hook', 'unused', $2)
+            RETURNING id
+            "#,
+        )
+        .bind(account_id)
+        .bind(event_types)
+        .fetch_one(pool)
+        .await
+        .unwrap()
+    }
+
+    /// Emit `account.updated` for the account in a transaction of its own.
+    pub(crate) async fn emit_account_updated(pool: &PgPool, account: &Account) {
+        let mut tx = pool.begin().await.unwrap();
+        emit(&mut tx, &[account.id], ACCOUNT_UPDATED, EventData::Account(account.clone())).await.unwrap();
+        tx.commit().await.unwrap();
+    }
+
+    /// Event types queued for the webhook, in sequence order.
+    pub(crate) async fn queued(pool: &PgPool, webhook_id: Uuid) -> Vec<String> {
+        sqlx::query_scalar("SELECT event_type FROM webhook_events WHERE webhook_id = $1 ORDER BY sequence")
+            .bind(webhook_id)
+            .fetch_all(pool)
+            .await
+            .unwrap()
+    }
+
+    #[sqlx::test]
+    #[ignore]
+    async fn emit_reaches_only_enabled_subscribed_webhooks_of_the_accounts(pool: PgPool) {
+        let owner = account(&pool).await;
+        let other = account(&pool).await;
+        let everything = webhook(&pool, owner.id, None).await;
+        let subscribed = webhook(&pool, owner.id, Some(&[ACCOUNT_CREATED, ACCOUNT_UPDATED])).await;
+        let unsubscribed = webhook(&pool, owner.id, Some(&[TRANSACTION_CREATED])).await;
+        let disabled = webhook(&pool, owner.id, None).await;
+        sqlx::query("UPDATE webhooks SET enabled = false WHERE id = $1").bind(disabled).execute(&pool).await.unwrap();
+        let others = webhook(&pool, other.id, None).await;
+
+        emit_account_updated(&pool, &owner).await;
+        emit_account_updated(&pool, &owner).await;
+
+        assert_eq!(queued(&pool, everything).await, [ACCOUNT_UPDATED, ACCOUNT_UPDATED]);
+        assert_eq!(queued(&pool, subscribed).await, [ACCOUNT_UPDATED, ACCOUNT_UPDATED]);
+        assert!(queued(&pool, unsubscribed).await.is_empty());
+        assert!(queued(&pool, disabled).await.is_empty());
+        assert!(queued(&pool, others).await.is_empty());
+
+        // Each webhook numbers its own events, and the payload carries the number
+        let sequences: Vec<(i64, i64)> = sqlx::query_as(
+            "SELECT sequence, (payload->>'sequence')::bigint FROM webhook_events WHERE webhook_id = $1 ORDER BY sequence",
+        )
+        .bind(subscribed)
+        .fetch_all(&pool)
+        .await
+        .unwrap();
+        assert_eq!(sequences, [(1, 1), (2, 2)]);
+
+        // The event log records it once per account, whatever the subscriptions
+        let logged: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM events WHERE account_id = $1 AND event_type = $2")
+            .bind(owner.id)
+            .bind(ACCOUNT_UPDATED)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(logged, 2);
+    }
+
+    #[sqlx::test]
+    #[ignore]
+    async fn an_event_rolled_back_with_its_change_is_not_queued(pool: PgPool) {
+        let owner = account(&pool).await;
+        let webhook_id = webhook(&pool, owner.id, None).await;
+
+        let mut tx = pool.begin().await.unwrap();
+        emit(&mut tx, &[owner.id], ACCOUNT_UPDATED, EventData::Account(owner.clone())).await.unwrap();
+        tx.rollback().await.unwrap();
+
+        assert!(queued(&pool, webhook_id).await.is_empty());
+    }
+}
@@ -0,0 +1,111 @@
+//! When to retry a failed delivery.
+//!
+//! Delays grow exponentially from the base delay up to the cap, with full jitter (a uniform random
+//! delay between zero and that bound) so endpoints coming back from an outage are not hit by every
+//! event at once. A `Retry-After` on 429 or 503 replaces the computed delay. Other 4xx responses and
+//! blocked targets are not retried: the request will not succeed unchanged.
+
+use rand::Rng;
+use std::env;
+
+use super::attempts::Outcome;
+use crate::models::RetryPolicyOverride;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RetryPolicy {
+    /// Attempts in total, including the first
+    pub max_attempts: i32,
+    pub base_delay_secs: f64,
+    pub max_delay_secs: f64,
+    /// No attempt is scheduled later than this after the first one
+    pub deadline_secs: f64,
+}
+
+impl RetryPolicy {
+    /// `WEBHOOK_RETRY_MAX_ATTEMPTS` (15), `WEBHOOK_RETRY_BASE_DELAY_SECS` (5), `WEBHOOK_RETRY_MAX_DELAY_SECS`
+    /// (3600) and `WEBHOOK_RETRY_DEADLINE_SECS` (259200, i.e. 72 hours).
+    pub fn global() -> Self {
+        fn var<T: std::str::FromStr>(name: &str, default: T) -> T {
+            env::var(name).ok().and_then(|v| v.parse().ok()).unwrap_or(default)
+        }
+        Self {
+            max_attempts: var("WEBHOOK_RETRY_MAX_ATTEMPTS", 15),
+            base_delay_secs: var("WEBHOOK_RETRY_BASE_DELAY_SECS", 5.0),
+            max_delay_secs: var("WEBHOOK_RETRY_MAX_DELAY_SECS", 3600.0),
+            deadline_secs: var("WEBHOOK_RETRY_DEADLINE_SECS", 259200.0),
+        }
+    }
+
+    /// The global policy with a webhook's overrides applied.
+    pub fn for_webhook(overrides: &RetryPolicyOverride) -> Self {
+        let global = Self::global();
+        Self {
+            max_attempts: overrides.max_attempts.unwrap_or(global.max_attempts),
+            base_delay_secs: overrides.base_delay_secs.map_or(global.base_delay_secs, f64::from),
+            max_delay_secs: overrides.max_delay_secs.map_or(global.max_delay_secs, f64::from),
+            deadline_secs: overrides.deadline_secs.map_or(global.deadline_secs, f64::from),
+        }
+    }
+
+    /// Seconds until the next attempt after `attempts` failed ones, or `None` to give up.
+    /// `elapsed_secs` is the time since the first attempt of this retry cycle.
+    pub fn next_delay(&self, attempts: i32, elapsed_secs: f64, outcome: &Outcome) -> Option<f64> {
+        if !is_retryable(outcome) || attempts >= self.max_attempts {
+            return None;
+        }
+        let delay = match outcome.retry_after_secs {
+            Some(retry_after) => retry_after,
+            None => {
+                let bound = (self.base_delay_secs * 2_f64.powi(attempts - 1)).min(self.max_delay_secs);
+                rand::thread_rng().gen_range(0.0..=bound)
+            }
+        };
+        (elapsed_secs + delay <= self.deadline_secs).then_some(delay)
+    }
+}
+
+fn is_retryable(outcome: &Outcome) -> bool {
+    match outcome.response_status {
+        // Request Timeout and Too Many Requests are transient; other client errors are not
+        Some(status @ 400..=499) => status == 408 || status == 429,
+        _ => outcome.error_kind != Some("blocked"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const POLICY: RetryPolicy = RetryPolicy {
+        max_attempts: 5,
+        base_delay_secs: 10.0,
+        max_delay_secs: 60.0,
+        deadline_secs: 3600.0,
+    };
+
+    fn status(code: u16) -> Outcome {
+        Outcome {
+            response_status: Some(code),
+            error_kind: Some("non_2xx"),
+            ..Outcome::default()
+        }
+    }
+
+    #[test]
+    fn jittered_delay_stays_under_the_capped_bound() {
+        for _ in 0..100 {
+            assert!(POLICY.next_delay(1, 0.0, &status(500)).unwrap() <= 10.0);
+            assert!(POLICY.next_delay(4, 0.0, &status(502)).unwrap() <= 60.0);
+        }
+        assert_eq!(POLICY.next_delay(5, 0.0, &status(500)), None);
+        assert_eq!(POLICY.next_delay(1, 3599.99, &Outcome { retry_after_secs: Some(1.0), ..status(503) }), None);
+    }
+
+    #[test]
+    fn client_errors_are_final_except_timeouts_and_throttling() {
+        assert_eq!(POLICY.next_delay(1, 0.0, &status(400)), None);
+        assert_eq!(POLICY.next_delay(1, 0.0, &status(410)), None);
+        assert!(POLICY.next_delay(1, 0.0, &status(408)).is_some());
+        assert_eq!(POLICY.next_delay(1, 0.0, &Outcome { retry_after_secs: Some(120.0), ..status(429) }), Some(120.0));
+    }
+}
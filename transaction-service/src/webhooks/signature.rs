@@ -0,0 +1,84 @@
+//! Outgoing webhook signatures.
+//!
+//! `X-Webhook-Signature: t=<unix seconds>,v1=<hex>` where `<hex>` is the HMAC-SHA256 of
+//! `"<t>.<raw body>"`. Binding the timestamp lets receivers reject old requests. While a rotated
+//! secret is still in its overlap window a second `v1` is appended, signed with the old secret.
+//!
+//! Webhooks with `legacy_signature` set also get the deprecated `X-Signature: sha256=<hex>`, the
+//! HMAC-SHA256 of the raw body with the current secret, until their receivers have moved over.
+
+use hmac::{Hmac, Mac};
+use reqwest::header::{HeaderMap, HeaderValue};
+use uuid::Uuid;
+
+pub const SIGNATURE_HEADER: &str = "x-webhook-signature";
+
+/// Deprecated; only sent to webhooks with `legacy_signature` set.
+pub const LEGACY_SIGNATURE_HEADER: &str = "x-signature";
+
+/// Carries the `webhook_events` id, which stays the same across retries and replays.
+pub const EVENT_ID_HEADER: &str = "x-webhook-id";
+
+/// Value of the signature header, with one `v1` per secret.
+pub fn header_value(timestamp: i64, body: &[u8], secrets: &[&str]) -> String {
+    let mut value = format!("t={}", timestamp);
+    for secret in secrets {
+        let mut mac = Hmac::<sha2::Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC can take key of any size");
+        mac.update(timestamp.to_string().as_bytes());
+        mac.update(b".");
+        mac.update(body);
+        value.push_str(",v1=");
+        value.push_str(&hex::encode(mac.finalize().into_bytes()));
+    }
+    value
+}
+
+/// Value of the deprecated `X-Signature` header: the body alone, signed with the current secret.
+pub fn legacy_header_value(body: &[u8], secret: &str) -> String {
+    let mut mac = Hmac::<sha2::Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC can take key of any size");
+    mac.update(body);
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+/// Add the event id and signature headers for a delivery made now. `secrets` starts with the current secret.
+pub fn sign(headers: &mut HeaderMap, event_id: Uuid, body: &[u8], secrets: &[&str], legacy: bool) {
+    let value = header_value(chrono::Utc::now().timestamp(), body, secrets);
+    headers.insert(EVENT_ID_HEADER, HeaderValue::from_str(&event_id.to_string()).expect("uuid is a valid header value"));
+    headers.insert(SIGNATURE_HEADER, HeaderValue::from_str(&value).expect("hex is a valid header value"));
+    if let (true, Some(secret)) = (legacy, secrets.first()) {
+        let value = legacy_header_value(body, secret);
+        headers.insert(LEGACY_SIGNATURE_HEADER, HeaderValue::from_str(&value).expect("hex is a valid header value"));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn signs_timestamp_and_body_with_every_secret() {
+        let value = header_value(1700000000, b"{}", &["new", "old"]);
+        let parts: Vec<&str> = value.split(',').collect();
+        assert_eq!(parts[0], "t=1700000000");
+        assert_eq!(parts.len(), 3);
+
+        let mut mac = Hmac::<sha2::Sha256>::new_from_slice(b"old").unwrap();
+        mac.update(b"1700000000.{}");
+        assert_eq!(parts[2], format!("v1={}", hex::encode(mac.finalize().into_bytes())));
+        assert_ne!(parts[1], parts[2]);
+    }
+
+    #[test]
+    fn legacy_header_only_when_asked_for() {
+        let mut headers = HeaderMap::new();
+        sign(&mut headers, Uuid::nil(), b"{}", &["new", "old"], false);
+        assert!(!headers.contains_key(LEGACY_SIGNATURE_HEADER));
+
+        sign(&mut headers, Uuid::nil(), b"{}", &["new", "old"], true);
+        let mut mac = Hmac::<sha2::Sha256>::new_from_slice(b"new").unwrap();
+        mac.update(b"{}");
+        let expected = format!("sha256={}", hex::encode(mac.finalize().into_bytes()));
+        assert_eq!(headers[LEGACY_SIGNATURE_HEADER], expected.as_str());
+        assert!(headers.contains_key(SIGNATURE_HEADER));
+    }
+}
@@ -0,0 +1,667 @@
+use reqwest::Url;
+use sqlx::postgres::PgListener;
+use sqlx::{FromRow, PgConnection, PgPool};
+use std::collections::HashMap;
+use std::env;
+use std::time::Duration;
+use tokio::task::JoinSet;
+use uuid::Uuid;
+
+use super::attempts;
+use super::circuit;
+use super::delivery::{self, Delivery};
+use super::format;
+use super::retry::RetryPolicy;
+use super::WAKE_CHANNEL;
+use crate::models::RetryPolicyOverride;
+
+/// An event claimed for delivery, together with its endpoint.
+#[derive(Debug, FromRow)]
+struct ClaimedEvent {
+    id: Uuid,
+    webhook_id: Uuid,
+    sequence: Option<i64>,
+    retry_count: i32,
+    payload: serde_json::Value,
+    url: String,
+    secret_sealed: String,
+    /// Set while a rotated-out secret is still inside its overlap window
+    previous_secret_sealed: Option<String>,
+    custom_headers_sealed: Option<String>,
+    basic_auth_sealed: Option<String>,
+    /// Seconds since the first attempt of the current retry cycle; a replay resets `retry_count` and starts a new one
+    retrying_for_secs: f64,
+    #[sqlx(flatten)]
+    retry_policy: RetryPolicyOverride,
+    /// Set for webhooks in batch mode
+    batch_size: Option<i32>,
+    payload_format: String,
+    legacy_signature: bool,
+}
+
+fn env_secs(var: &str, default: u64) -> Duration {
+    Duration::from_secs(env::var(var).ok().and_then(|v| v.parse().ok()).unwrap_or(default))
+}
+
+/// Background task: deliver due webhook events.
+///
+/// Several instances can run this loop against the same database. Each claims events with
+/// `FOR UPDATE SKIP LOCKED` and leases them by pushing `next_attempt_at` forward, so an event whose
+/// worker dies mid-delivery is picked up again once the lease expires.
+///
+/// Up to `WEBHOOK_MAX_IN_FLIGHT` (default 50) deliveries run at once per instance, and at most
+/// `WEBHOOK_MAX_IN_FLIGHT_PER_ENDPOINT` (default 5) per webhook across all instances. New events are
+/// claimed as soon as a slot frees up, so a slow endpoint does not hold up the others.
+pub async fn run(pool: PgPool) {
+    let poll = env_secs("WEBHOOK_POLL_SECS", 5);
+    let max_in_flight: usize = env::var("WEBHOOK_MAX_IN_FLIGHT").ok().and_then(|v| v.parse().ok()).unwrap_or(50);
+    let mut listener = connect_listener(&pool).await;
+    let mut deliveries = JoinSet::new();
+
+    loop {
+        let free = max_in_flight.saturating_sub(deliveries.len());
+        let claimed = match claim_due(&pool, free).await {
+            Ok(claimed) => claimed,
+            Err(e) => {
+                tracing::error!("Failed to claim webhook events: {}", e);
+                Vec::new()
+            }
+        };
+        let filled = free > 0 && claimed.len() == free;
+        for events in into_requests(claimed) {
+            deliveries.spawn(deliver(pool.clone(), events));
+        }
+        if filled {
+            // There may be more due; claim again once a slot frees up
+            deliveries.join_next().await;
+            continue;
+        }
+
+        // Nothing claimable: sleep until a delivery finishes (freeing an endpoint slot), a notification
+        // of new events arrives, the next scheduled retry comes due or the next poll
+        let wait = match next_due_in(&pool).await {
+            Ok(Some(due_in)) => due_in.min(poll),
+            Ok(None) => poll,
+            Err(e) => {
+                tracing::error!("Failed to look up next webhook retry: {}", e);
+                poll
+            }
+        };
+        let woken = async {
+            match listener.as_mut() {
+                Some(l) => match tokio::time::timeout(wait, l.recv()).await {
+                    Ok(Ok(_)) | Err(_) => true,
+                    Ok(Err(e)) => {
+                        tracing::warn!("Webhook listener disconnected: {}", e);
+                        false
+                    }
+                },
+                None => {
+                    tokio::time::sleep(wait).await;
+                    false
+                }
+            }
+        };
+        tokio::select! {
+            Some(_) = deliveries.join_next(), if !deliveries.is_empty() => {}
+            listening = woken => {
+                if !listening {
+                    listener = connect_listener(&pool).await;
+                }
+            }
+        }
+    }
+}
+
+async fn connect_listener(pool: &PgPool) -> Option<PgListener> {
+    let mut listener = match PgListener::connect_with(pool).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            tracing::error!("Failed to connect webhook listener: {}", e);
+            return None;
+        }
+    };
+    if let Err(e) = listener.listen(WAKE_CHANNEL).await {
+        tracing::error!("Failed to LISTEN on {}: {}", WAKE_CHANNEL, e);
+        return None;
+    }
+    Some(listener)
+}
+
+async fn claim_due(pool: &PgPool, limit: usize) -> Result<Vec<ClaimedEvent>, sqlx::Error> {
+    if limit == 0 {
+        return Ok(Vec::new());
+    }
+    let per_endpoint: i64 =
+        env::var("WEBHOOK_MAX_IN_FLIGHT_PER_ENDPOINT").ok().and_then(|v| v.parse().ok()).unwrap_or(5);
+    let lease = env_secs("WEBHOOK_LEASE_SECS", 60);
+
+    let mut tx = pool.begin().await?;
+    // Claims are serialised so two instances cannot both see a free slot on the same endpoint
+    sqlx::query("SELECT pg_advisory_xact_lock(hashtext('webhook_claim'))")
+        .execute(&mut *tx)
+        .await?;
+    let claimed = sqlx::query_as::<_, ClaimedEvent>(
+        r#"
+        WITH in_flight AS (
+            SELECT webhook_id, COUNT(*) AS n FROM webhook_events
+            WHERE leased_until > NOW()
+            GROUP BY webhook_id
+        ),
+        ranked AS (
+            SELECT pe.id, pw.ordered, pw.batch_size, f.n AS in_flight,
+                   ROW_NUMBER() OVER w + COALESCE(f.n, 0) AS slot,
+                   -- For ordered webhooks: this event and every earlier one are due
+                   bool_and(pe.next_attempt_at <= NOW()) OVER w AS due_in_order,
+                   pe.next_attempt_at <= NOW() AS due
+            FROM webhook_events pe
+            JOIN webhooks pw ON pw.id = pe.webhook_id
+            LEFT JOIN in_flight f ON f.webhook_id = pe.webhook_id
+            WHERE pe.status = 'pending' AND pe.payload IS NOT NULL AND pw.enabled
+              AND (pw.ordered OR pe.next_attempt_at <= NOW())
+            WINDOW w AS (
+                PARTITION BY pe.webhook_id
+                ORDER BY CASE WHEN pw.ordered THEN pe.sequence END NULLS FIRST, pe.next_attempt_at, pe.id
+            )
+        ),
+        claimable AS (
+            SELECT id, slot FROM ranked
+            WHERE due
+              AND (NOT ordered OR due_in_order)
+              AND CASE
+                      -- One batch at a time, of up to batch_size events
+                      WHEN batch_size IS NOT NULL THEN in_flight IS NULL AND slot <= batch_size
+                      WHEN ordered THEN slot = 1
+                      ELSE slot <= $3
+                  END
+        )
+        UPDATE webhook_events e
+        SET next_attempt_at = NOW() + make_interval(secs => $2), leased_until = NOW() + make_interval(secs => $2)
+        FROM webhooks w
+        WHERE w.id = e.webhook_id
+          AND e.id IN (
+              -- Lowest slots first: endpoints take turns, and ordered events are claimed from the head
+              SELECT pe.id FROM webhook_events pe JOIN claimable c ON c.id = pe.id
+              ORDER BY c.slot, pe.next_attempt_at
+              LIMIT $1
+              FOR UPDATE OF pe SKIP LOCKED
+          )
+        RETURNING e.id, e.webhook_id, e.sequence, e.retry_count, e.payload, w.url, w.secret_sealed,
+                  CASE WHEN w.previous_secret_expires_at > NOW() THEN w.previous_secret_sealed END AS previous_secret_sealed,
+                  w.custom_headers_sealed, w.basic_auth_sealed,
+                  CASE WHEN e.retry_count = 0 THEN 0
+                       ELSE COALESCE(EXTRACT(EPOCH FROM NOW() - e.first_attempt_at), 0) END::float8 AS retrying_for_secs,
+                  w.retry_max_attempts, w.retry_base_delay_secs, w.retry_max_delay_secs, w.retry_deadline_secs,
+                  w.batch_size, w.payload_format, w.legacy_signature
+        "#,
+    )
+    .bind(limit as i64)
+    .bind(lease.as_secs_f64())
+    .bind(per_endpoint)
+    .fetch_all(&mut *tx)
+    .await?;
+    tx.commit().await?;
+    Ok(claimed)
+}
+
+/// Time until the earliest scheduled attempt, if any event is waiting. Events already due are
+/// skipped: they were not claimable, so a finished delivery or a poll has to make room first.
+async fn next_due_in(pool: &PgPool) -> Result<Option<Duration>, sqlx::Error> {
+    let secs: Option<f64> = sqlx::query_scalar(
+        r#"
+        SELECT EXTRACT(EPOCH FROM MIN(e.next_attempt_at) - NOW())::float8
+        FROM webhook_events e JOIN webhooks w ON w.id = e.webhook_id
+        WHERE e.status = 'pending' AND e.next_attempt_at > NOW() AND e.payload IS NOT NULL AND w.enabled
+        "#,
+    )
+    .fetch_one(pool)
+    .await?;
+    Ok(secs.map(|secs| Duration::from_secs_f64(secs.max(0.0))))
+}
+
+/// Group claimed events into requests: one per event, or one per batch-mode webhook in sequence order.
+fn into_requests(claimed: Vec<ClaimedEvent>) -> Vec<Vec<ClaimedEvent>> {
+    let mut requests = Vec::new();
+    let mut batches: HashMap<Uuid, Vec<ClaimedEvent>> = HashMap::new();
+    for event in claimed {
+        // Binary CloudEvents carry one event per request; the API refuses to combine them with batching
+        if event.batch_size.is_some() && event.payload_format != format::CLOUDEVENTS_BINARY {
+            batches.entry(event.webhook_id).or_default().push(event);
+        } else {
+            requests.push(vec![event]);
+        }
+    }
+    for (_, mut batch) in batches {
+        batch.sort_by_key(|event| (event.sequence, event.id));
+        requests.push(batch);
+    }
+    requests
+}
+
+/// Deliver one request's worth of events, all for the same webhook.
+async fn deliver(pool: PgPool, events: Vec<ClaimedEvent>) {
+    let Some(first) = events.first() else {
+        return;
+    };
+    let batched = first.batch_size.is_some() && first.payload_format != format::CLOUDEVENTS_BINARY;
+    let ids: Vec<Uuid> = events.iter().map(|event| event.id).collect();
+    tracing::info!("Attempting webhook delivery {} for events {:?}", first.retry_count + 1, ids);
+
+    // A single event is sent under its own id; a batch gets an id of its own
+    let request_id = if batched { Uuid::new_v4() } else { first.id };
+    let encoded: Vec<format::Event> =
+        events.iter().map(|event| format::Event { id: event.id, payload: &event.payload }).collect();
+    let request = match format::encode(&first.payload_format, &encoded, batched) {
+        Ok(request) => request,
+        Err(e) => {
+            tracing::error!("Failed to serialize webhook payload for events {:?}: {}", ids, e);
+            return;
+        }
+    };
+
+    // An unreadable secret is a key configuration problem: leave the events for a later claim rather than
+    // spending their retries
+    let unsealed = delivery::unseal_secrets(&first.secret_sealed, first.previous_secret_sealed.as_deref()).and_then(
+        |secrets| {
+            let headers = delivery::unseal_target_headers(
+                first.custom_headers_sealed.as_deref(),
+                first.basic_auth_sealed.as_deref(),
+            )?;
+            Ok((secrets, headers))
+        },
+    );
+    let (secrets, target_headers) = match unsealed {
+        Ok(unsealed) => unsealed,
+        Err(e) => {
+            tracing::error!("Failed to decrypt webhook secrets for events {:?}: {:#}", ids, e);
+            return;
+        }
+    };
+
+    let host = Url::parse(&first.url).ok().and_then(|url| url.host_str().map(str::to_lowercase)).unwrap_or_default();
+    if let Err(wait) = circuit::admit(&host) {
+        tracing::info!("Circuit for webhook host {} is open; holding events {:?} for {:?}", host, ids, wait);
+        if let Err(e) = postpone(&pool, &ids, wait).await {
+            tracing::error!("Failed to postpone webhook events {:?}: {}", ids, e);
+        }
+        return;
+    }
+
+    let result = delivery::send(&first.url, request_id, request, &target_headers, &secrets, first.legacy_signature).await;
+    circuit::record(&host, &result.outcome);
+    for event in &events {
+        if let Err(e) = record_attempt(&pool, event, &result).await {
+            tracing::error!("Failed to record webhook attempt for event {}: {}", event.id, e);
+        }
+    }
+}
+
+/// Release events without attempting them; their retry budget is untouched.
+async fn postpone(pool: &PgPool, event_ids: &[Uuid], wait: Duration) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"
+        UPDATE webhook_events SET next_attempt_at = NOW() + make_interval(secs => $2), leased_until = NULL
+        WHERE id = ANY($1) AND status = 'pending'
+        "#,
+    )
+    .bind(event_ids)
+    .bind(wait.as_secs_f64())
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+async fn record_attempt(pool: &PgPool, event: &ClaimedEvent, result: &Delivery) -> Result<(), sqlx::Error> {
+    let outcome = &result.outcome;
+    let mut tx = pool.begin().await?;
+    attempts::record(&mut tx, event.id, event.retry_count + 1, &result.request_headers, result.latency, outcome).await?;
+
+    if outcome.succeeded() {
+        tracing::info!("Webhook delivered successfully for event {}", event.id);
+        sqlx::query(
+            r#"
+            UPDATE webhook_events
+            SET status = 'delivered', delivered = true, next_attempt_at = NULL, last_attempt = NOW(), leased_until = NULL
+            WHERE id = $1
+            "#,
+        )
+        .bind(event.id)
+        .execute(&mut *tx)
+        .await?;
+        sqlx::query(
+            r#"
+            UPDATE webhooks SET consecutive_failures = 0, failing_since = NULL
+            WHERE id = $1 AND (consecutive_failures <> 0 OR failing_since IS NOT NULL)
+            "#,
+        )
+        .bind(event.webhook_id)
+        .execute(&mut *tx)
+        .await?;
+    } else {
+        let failures = event.retry_count + 1;
+        let policy = RetryPolicy::for_webhook(&event.retry_policy);
+        let next_attempt_in = policy.next_delay(failures, event.retrying_for_secs, outcome);
+        if next_attempt_in.is_none() {
+            tracing::error!("Failed to deliver webhook after {} attempts for event {}; moved to dead letter", failures, event.id);
+        }
+        sqlx::query(
+            r#"
+            UPDATE webhook_events
+            SET retry_count = $2, last_attempt = NOW(), next_attempt_at = NOW() + make_interval(secs => $3), leased_until = NULL,
+                status = CASE WHEN $3 IS NULL THEN 'failed' ELSE 'pending' END,
+                first_attempt_at = CASE WHEN $2 = 1 THEN NOW() ELSE COALESCE(first_attempt_at, NOW()) END
+            WHERE id = $1
+            "#,
+        )
+        .bind(event.id)
+        .bind(failures)
+        .bind(next_attempt_in)
+        .execute(&mut *tx)
+        .await?;
+        track_failure(&mut tx, event.webhook_id, next_attempt_in.is_none()).await?;
+    }
+    tx.commit().await
+}
+
+/// Count a failed attempt against the webhook and disable it once it has failed `WEBHOOK_DISABLE_AFTER_FAILURES`
+/// events in a row (default 20) or has been failing for `WEBHOOK_DISABLE_AFTER_DAYS` (default 5). 0 turns a rule off.
+async fn track_failure(conn: &mut PgConnection, webhook_id: Uuid, dead_lettered: bool) -> Result<(), sqlx::Error> {
+    let max_failures: i32 = env::var("WEBHOOK_DISABLE_AFTER_FAILURES").ok().and_then(|v| v.parse().ok()).unwrap_or(20);
+    let max_days: f64 = env::var("WEBHOOK_DISABLE_AFTER_DAYS").ok().and_then(|v| v.parse().ok()).unwrap_or(5.0);
+
+    let (failures, failing_days): (i32, f64) = sqlx::query_as(
+        r#"
+        UPDATE webhooks
+        SET failing_since = COALESCE(failing_since, NOW()),
+            consecutive_failures = consecutive_failures + CASE WHEN $2 THEN 1 ELSE 0 END
+        WHERE id = $1
+        RETURNING consecutive_failures, (EXTRACT(EPOCH FROM NOW() - failing_since) / 86400)::float8
+        "#,
+    )
+    .bind(webhook_id)
+    .bind(dead_lettered)
+    .fetch_one(&mut *conn)
+    .await?;
+
+    let reason = if max_failures > 0 && failures >= max_failures {
+        format!("{} consecutive events failed", failures)
+    } else if max_days > 0.0 && failing_days >= max_days {
+        format!("deliveries failing for {} days", max_days)
+    } else {
+        return Ok(());
+    };
+    if super::disable(conn, webhook_id, &reason).await? {
+        tracing::warn!("Disabled webhook {}: {}", webhook_id, reason);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{ReplayWebhookEventsRequest, WebhookDeliveryAttempt};
+    use crate::webhooks::attempts::Outcome;
+    use crate::webhooks::replay;
+    use crate::webhooks::tests::{account, emit_account_updated, webhook};
+    use reqwest::header::HeaderMap;
+
+    fn ids(claimed: &[ClaimedEvent]) -> Vec<Uuid> {
+        let mut ids: Vec<Uuid> = claimed.iter().map(|event| event.id).collect();
+        ids.sort();
+        ids
+    }
+
+    /// A delivery that got `status` back from the endpoint.
+    fn responded(status: u16) -> Delivery {
+        let success = (200..300).contains(&status);
+        Delivery {
+            request_headers: HeaderMap::new(),
+            latency: Duration::from_millis(5),
+            outcome: Outcome {
+                response_status: Some(status),
+                error_kind: (!success).then_some("non_2xx"),
+                ..Outcome::default()
+            },
+        }
+    }
+
+    /// `(status, retry_count)` of an event.
+    async fn state(pool: &PgPool, event_id: Uuid) -> (String, i32) {
+        sqlx::query_as("SELECT status, retry_count FROM webhook_events WHERE id = $1")
+            .bind(event_id)
+            .fetch_one(pool)
+            .await
+            .unwrap()
+    }
+
+    #[sqlx::test]
+    #[ignore]
+    async fn concurrent_claims_never_share_an_event(pool: PgPool) {
+        let account = account(&pool).await;
+        webhook(&pool, account.id, None).await;
+        for _ in 0..4 {
+            emit_account_updated(&pool, &account).await;
+        }
+
+        let (first, second) = tokio::join!(claim_due(&pool, 10), claim_due(&pool, 10));
+        let (first, second) = (first.unwrap(), second.unwrap());
+        let mut all = [ids(&first), ids(&second)].concat();
+        all.sort();
+        all.dedup();
+        assert_eq!(all.len(), first.len() + second.len());
+        assert_eq!(all.len(), 4);
+
+        // Leased events are not claimed again
+        assert!(claim_due(&pool, 10).await.unwrap().is_empty());
+    }
+
+    #[sqlx::test]
+    #[ignore]
+    async fn an_expired_lease_is_claimed_again(pool: PgPool) {
+        let account = account(&pool).await;
+        webhook(&pool, account.id, None).await;
+        emit_account_updated(&pool, &account).await;
+
+        let claimed = claim_due(&pool, 10).await.unwrap();
+        assert_eq!(claimed.len(), 1);
+        // The worker died mid-delivery and its lease ran out
+        sqlx::query("UPDATE webhook_events SET next_attempt_at = NOW() - INTERVAL '1 second', leased_until = NOW() - INTERVAL '1 second'")
+            .execute(&pool)
+            .await
+            .unwrap();
+        assert_eq!(ids(&claim_due(&pool, 10).await.unwrap()), ids(&claimed));
+    }
+
+    #[sqlx::test]
+    #[ignore]
+    async fn claims_respect_the_per_endpoint_limit(pool: PgPool) {
+        let account = account(&pool).await;
+        webhook(&pool, account.id, None).await;
+        for _ in 0..7 {
+            emit_account_updated(&pool, &account).await;
+        }
+
+        // WEBHOOK_MAX_IN_FLIGHT_PER_ENDPOINT defaults to 5
+        assert_eq!(claim_due(&pool, 10).await.unwrap().len(), 5);
+        assert!(claim_due(&pool, 10).await.unwrap().is_empty());
+    }
+
+    #[sqlx::test]
+    #[ignore]
+    async fn a_dead_lettered_event_is_replayed_and_delivered(pool: PgPool) {
+        let owner = account(&pool).await;
+        let stranger = account(&pool).await;
+        webhook(&pool, owner.id, None).await;
+        emit_account_updated(&pool, &owner).await;
+        let all = ReplayWebhookEventsRequest::default();
+
+        // A 400 is final, so the first failure dead-letters the event
+        let claimed = claim_due(&pool, 10).await.unwrap();
+        let event_id = claimed[0].id;
+        record_attempt(&pool, &claimed[0], &responded(400)).await.unwrap();
+        assert_eq!(state(&pool, event_id).await, ("failed".to_string(), 1));
+        assert!(claim_due(&pool, 10).await.unwrap().is_empty());
+
+        // Only the owner can replay it, and it comes back with a fresh retry budget
+        assert!(replay(&pool, stranger.id, Some(event_id), &all).await.unwrap().is_empty());
+        assert_eq!(replay(&pool, owner.id, Some(event_id), &all).await.unwrap(), vec![event_id]);
+        assert_eq!(state(&pool, event_id).await, ("pending".to_string(), 0));
+        assert!(replay(&pool, owner.id, Some(event_id), &all).await.unwrap().is_empty());
+
+        let claimed = claim_due(&pool, 10).await.unwrap();
+        assert_eq!(ids(&claimed), vec![event_id]);
+        assert_eq!(claimed[0].retry_count, 0);
+        record_attempt(&pool, &claimed[0], &responded(200)).await.unwrap();
+        assert_eq!(state(&pool, event_id).await, ("delivered".to_string(), 0));
+    }
+
+    #[sqlx::test]
+    #[ignore]
+    async fn every_attempt_is_logged(pool: PgPool) {
+        let account = account(&pool).await;
+        webhook(&pool, account.id, None).await;
+        emit_account_updated(&pool, &account).await;
+
+        let claimed = claim_due(&pool, 10).await.unwrap();
+        let mut failed = responded(503);
+        failed.request_headers.insert("x-webhook-id", claimed[0].id.to_string().parse().unwrap());
+        failed.outcome.response_body = Some("try later".into());
+        record_attempt(&pool, &claimed[0], &failed).await.unwrap();
+        sqlx::query("UPDATE webhook_events SET next_attempt_at = NOW()").execute(&pool).await.unwrap();
+        let claimed = claim_due(&pool, 10).await.unwrap();
+        record_attempt(&pool, &claimed[0], &responded(200)).await.unwrap();
+
+        let logged: Vec<WebhookDeliveryAttempt> = sqlx::query_as("SELECT * FROM webhook_delivery_attempts WHERE event_id = $1 ORDER BY attempt")
+            .bind(claimed[0].id)
+            .fetch_all(&pool)
+            .await
+            .unwrap();
+        assert_eq!(logged.len(), 2);
+        assert_eq!((logged[0].attempt, logged[0].response_status), (1, Some(503)));
+        assert_eq!(logged[0].response_body.as_deref(), Some("try later"));
+        assert_eq!(logged[0].error_kind.as_deref(), Some("non_2xx"));
+        assert_eq!(logged[0].request_headers["x-webhook-id"], claimed[0].id.to_string());
+        assert_eq!((logged[1].attempt, logged[1].response_status), (2, Some(200)));
+        assert_eq!(logged[1].error_kind, None);
+    }
+
+    fn sequences(claimed: &[ClaimedEvent]) -> Vec<i64> {
+        claimed.iter().filter_map(|event| event.sequence).collect()
+    }
+
+    #[sqlx::test]
+    #[ignore]
+    async fn ordered_delivery_holds_back_later_sequences(pool: PgPool) {
+        let account = account(&pool).await;
+        let webhook_id = webhook(&pool, account.id, None).await;
+        sqlx::query("UPDATE webhooks SET ordered = true WHERE id = $1").bind(webhook_id).execute(&pool).await.unwrap();
+        for _ in 0..3 {
+            emit_account_updated(&pool, &account).await;
+        }
+
+        let head = claim_due(&pool, 10).await.unwrap();
+        assert_eq!(sequences(&head), [1]);
+        assert!(claim_due(&pool, 10).await.unwrap().is_empty());
+
+        // While the head waits for its retry, the due events behind it wait too
+        let mut retry_later = responded(503);
+        retry_later.outcome.retry_after_secs = Some(60.0);
+        record_attempt(&pool, &head[0], &retry_later).await.unwrap();
+        assert!(claim_due(&pool, 10).await.unwrap().is_empty());
+
+        sqlx::query("UPDATE webhook_events SET next_attempt_at = NOW() WHERE id = $1").bind(head[0].id).execute(&pool).await.unwrap();
+        let head = claim_due(&pool, 10).await.unwrap();
+        assert_eq!(sequences(&head), [1]);
+        record_attempt(&pool, &head[0], &responded(200)).await.unwrap();
+
+        // A dead-lettered event no longer blocks the ones after it
+        let head = claim_due(&pool, 10).await.unwrap();
+        assert_eq!(sequences(&head), [2]);
+        record_attempt(&pool, &head[0], &responded(400)).await.unwrap();
+        assert_eq!(sequences(&claim_due(&pool, 10).await.unwrap()), [3]);
+    }
+
+    #[sqlx::test]
+    #[ignore]
+    async fn batches_are_claimed_one_at_a_time_in_sequence_order(pool: PgPool) {
+        let account = account(&pool).await;
+        let webhook_id = webhook(&pool, account.id, None).await;
+        sqlx::query("UPDATE webhooks SET ordered = true, batch_size = 2 WHERE id = $1").bind(webhook_id).execute(&pool).await.unwrap();
+        for _ in 0..3 {
+            emit_account_updated(&pool, &account).await;
+        }
+
+        let requests = into_requests(claim_due(&pool, 10).await.unwrap());
+        assert_eq!(requests.len(), 1);
+        assert_eq!(sequences(&requests[0]), [1, 2]);
+        assert!(claim_due(&pool, 10).await.unwrap().is_empty());
+
+        for event in &requests[0] {
+            record_attempt(&pool, event, &responded(200)).await.unwrap();
+        }
+        assert_eq!(sequences(&claim_due(&pool, 10).await.unwrap()), [3]);
+    }
+
+    #[sqlx::test]
+    #[ignore]
+    async fn repeated_dead_letters_disable_the_webhook(pool: PgPool) {
+        let account = account(&pool).await;
+        let failing = webhook(&pool, account.id, None).await;
+        let healthy = webhook(&pool, account.id, None).await;
+        // One dead letter short of WEBHOOK_DISABLE_AFTER_FAILURES (default 20)
+        sqlx::query("UPDATE webhooks SET consecutive_failures = 19 WHERE id = $1").bind(failing).execute(&pool).await.unwrap();
+        emit_account_updated(&pool, &account).await;
+        emit_account_updated(&pool, &account).await;
+
+        let claimed = claim_due(&pool, 10).await.unwrap();
+        let head = claimed.iter().find(|event| event.webhook_id == failing && event.sequence == Some(1)).unwrap();
+        record_attempt(&pool, head, &responded(400)).await.unwrap();
+
+        let (enabled, reason, failures): (bool, Option<String>, i32) =
+            sqlx::query_as("SELECT enabled, disabled_reason, consecutive_failures FROM webhooks WHERE id = $1")
+                .bind(failing)
+                .fetch_one(&pool)
+                .await
+                .unwrap();
+        assert!(!enabled);
+        assert_eq!(reason.as_deref(), Some("20 consecutive events failed"));
+        assert_eq!(failures, 20);
+
+        // Its other events are dead-lettered, and the account hears about it on the webhook still working
+        let pending: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM webhook_events WHERE webhook_id = $1 AND status = 'pending'")
+            .bind(failing)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(pending, 0);
+        let told: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM webhook_events WHERE webhook_id = $1 AND event_type = 'webhook.disabled'")
+            .bind(healthy)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(told, 1);
+    }
+
+    #[sqlx::test]
+    #[ignore]
+    async fn a_delivery_resets_the_failure_count(pool: PgPool) {
+        let account = account(&pool).await;
+        let webhook_id = webhook(&pool, account.id, None).await;
+        sqlx::query("UPDATE webhooks SET consecutive_failures = 5, failing_since = NOW() WHERE id = $1")
+            .bind(webhook_id)
+            .execute(&pool)
+            .await
+            .unwrap();
+        emit_account_updated(&pool, &account).await;
+
+        let claimed = claim_due(&pool, 10).await.unwrap();
+        record_attempt(&pool, &claimed[0], &responded(200)).await.unwrap();
+        let (failures, failing_since): (i32, Option<chrono::DateTime<chrono::Utc>>) =
+            sqlx::query_as("SELECT consecutive_failures, failing_since FROM webhooks WHERE id = $1")
+                .bind(webhook_id)
+                .fetch_one(&pool)
+                .await
+                .unwrap();
+        assert_eq!((failures, failing_since), (0, None));
+    }
+}